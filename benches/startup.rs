@@ -0,0 +1,63 @@
+//! Benchmarks for the non-network startup path: running `gptsh --help`, building the system
+//! message, setting up the client and classifying responses. Run with `cargo bench`.
+//!
+//! This is a plain timing loop rather than a criterion benchmark, so that it builds without
+//! downloading more dependencies.
+
+use gptsh::{classify, system_message, Endpoint, Gptsh, PromptRules};
+use std::{
+    hint::black_box,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+/// Run `f` repeatedly for about a second and print the average time per iteration.
+fn bench(name: &str, mut f: impl FnMut()) {
+    // Warm up
+    for _ in 0..100 {
+        f();
+    }
+
+    let start = Instant::now();
+    let mut iterations = 0u32;
+    while start.elapsed() < Duration::from_secs(1) {
+        f();
+        iterations += 1;
+    }
+    println!("{name:<24} {:?}/iter", start.elapsed() / iterations);
+}
+
+fn main() {
+    // The whole process, as the shell starts it
+    bench("gptsh --help", || {
+        let status = Command::new(env!("CARGO_BIN_EXE_gptsh"))
+            .arg("--help")
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    });
+    bench("system_message", || {
+        black_box(system_message("bash", black_box(PromptRules::default())));
+    });
+    bench("Gptsh::new", || {
+        let endpoint = Endpoint::openai("sk-benchmark");
+        black_box(Gptsh::new(&endpoint, "gpt-3.5-turbo", PromptRules::default()).unwrap());
+    });
+    // What Gptsh::new saves by leaving the client until the first request
+    bench("reqwest::Client::new", || {
+        black_box(reqwest::Client::new());
+    });
+    bench("classify (command)", || {
+        black_box(classify(
+            black_box("[shell] ```bash\nls -la\n```"),
+            PromptRules::default(),
+        ));
+    });
+    bench("classify (answer)", || {
+        black_box(classify(
+            black_box("The capital of France is Paris."),
+            PromptRules::default(),
+        ));
+    });
+}
@@ -0,0 +1,153 @@
+//! An optional allowlist of the programs that generated commands may run, for locked-down
+//! environments such as shared machines.
+//!
+//! When `allowlist` is set in the config file, a command is only run if every command in it (each
+//! part of a pipeline or a chain like `a && b; c`) starts with one of the listed programs, after
+//! any leading `VAR=value` assignments. Command substitutions are refused outright, since the
+//! commands inside them can't be checked reliably.
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::fmt;
+
+/// The programs that commands may run.
+#[derive(Debug, Clone, Default)]
+pub struct Allowlist {
+    pub programs: Vec<String>,
+}
+
+/// Why a command isn't allowed to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Refusal {
+    /// The command runs a program that isn't on the allowlist.
+    NotAllowed(String),
+    /// The command contains a command substitution, like `$(...)` or `` `...` ``.
+    Substitution,
+    /// The command's quoting couldn't be parsed.
+    Unparseable,
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotAllowed(program) => write!(f, "`{program}` is not on the allowlist"),
+            Self::Substitution => f.write_str("command substitutions are not allowed"),
+            Self::Unparseable => f.write_str("the command couldn't be parsed"),
+        }
+    }
+}
+
+impl Allowlist {
+    /// Parse the `allowlist` array in the config file.
+    pub fn from_json(json: &Value) -> Result<Self> {
+        let Some(programs) = json.as_array() else {
+            bail!("`allowlist` must be an array");
+        };
+        let programs = programs
+            .iter()
+            .map(|program| program.as_str().map(str::to_owned))
+            .collect::<Option<_>>()
+            .context("`allowlist` must only contain strings")?;
+        Ok(Self { programs })
+    }
+
+    /// Check whether a command only runs allowed programs.
+    pub fn check(&self, command: &str) -> Result<(), Refusal> {
+        for program in programs(command)? {
+            if !self.programs.contains(&program) {
+                return Err(Refusal::NotAllowed(program));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Get the programs a command runs, in order: the first word of each command in it, after any
+/// `VAR=value` assignments.
+pub fn programs(command: &str) -> Result<Vec<String>, Refusal> {
+    let mut programs = Vec::new();
+    for segment in segments(command)? {
+        let words = shell_words::split(segment).map_err(|_| Refusal::Unparseable)?;
+        if let Some(program) = words.into_iter().find(|word| !is_assignment(word)) {
+            programs.push(program);
+        }
+    }
+    Ok(programs)
+}
+
+/// Split a command into the simple commands in it, at unquoted `;`, `&`, `|`, newlines, and
+/// parentheses.
+fn segments(command: &str) -> Result<Vec<&str>, Refusal> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    let mut chars = command.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        let prev = command[..i].chars().next_back();
+        let next = chars.peek().map(|&(_, c)| c);
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => {
+                chars.next();
+            }
+            (_, '`') => return Err(Refusal::Substitution),
+            (_, '$') if next == Some('(') => return Err(Refusal::Substitution),
+            (Some('"'), '"') => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '<' | '>') if next == Some('(') => return Err(Refusal::Substitution),
+            // Redirections like `2>&1` and `&>` aren't separators
+            (None, '&') if matches!(prev, Some('<' | '>')) || next == Some('>') => {}
+            (None, ';' | '&' | '|' | '\n' | '(' | ')') => {
+                segments.push(&command[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    if quote.is_some() {
+        return Err(Refusal::Unparseable);
+    }
+    segments.push(&command[start..]);
+
+    Ok(segments
+        .into_iter()
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
+        .collect())
+}
+
+/// Check whether a command redirects output to a file, with an unquoted `>` that isn't duplicating
+/// a file descriptor like `2>&1`.
+pub fn writes_files(command: &str) -> bool {
+    let mut quote = None;
+    let mut chars = command.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, '\\') => {
+                chars.next();
+            }
+            (Some('"'), '"') => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '>') if chars.peek() != Some(&'&') => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Check whether a word is a variable assignment like `VAR=value`.
+fn is_assignment(word: &str) -> bool {
+    match word.split_once('=') {
+        Some((name, _)) => {
+            name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,149 @@
+//! Attaching files to prompts by mentioning them as `@path`, e.g. "summarize @~/notes.txt". The
+//! contents of each file are added to the end of the prompt, and the mention is left as it is so
+//! the model knows which file is meant.
+//!
+//! Only words after the `@` that look like paths (containing `/`, `\`, `.`, `~`, or `$`) are
+//! attached, so that mentions like `@someone` are left alone. A leading `~` or environment
+//! variable is expanded, as in the shell.
+
+use crate::config::expand_path;
+use anyhow::{bail, Context, Result};
+use std::{fs, path::PathBuf};
+
+/// The largest file that can be attached, since the whole file is sent with the prompt.
+pub const MAX_ATTACHMENT_SIZE: u64 = 256 * 1024;
+
+/// A file mentioned in a prompt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment<'a> {
+    /// The path as it was written, without the `@`.
+    pub mention: &'a str,
+    /// The path after expanding `~` and environment variables.
+    pub path: PathBuf,
+}
+
+/// Find the files mentioned in a prompt, in order.
+pub fn find(prompt: &str) -> Vec<Attachment<'_>> {
+    prompt
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        // Punctuation after the mention isn't part of the path, as in "look at @notes.txt."
+        .map(|mention| mention.trim_end_matches([',', ';', ':', '!', '?', ')', '"', '\'', '.']))
+        .filter(|mention| mention.contains(['/', '\\', '.', '~', '$']))
+        .map(|mention| Attachment {
+            mention,
+            path: expand_path(mention),
+        })
+        .collect()
+}
+
+/// Add the contents of the files mentioned in a prompt to the end of it. Errors if a file doesn't
+/// exist or can't be read as text.
+pub fn attach(prompt: &str) -> Result<String> {
+    let mut attached = prompt.to_owned();
+    for attachment in find(prompt) {
+        let path = &attachment.path;
+        let Ok(metadata) = fs::metadata(path) else {
+            bail!(
+                "the attached file @{} doesn't exist ({})",
+                attachment.mention,
+                path.display()
+            );
+        };
+        if metadata.len() > MAX_ATTACHMENT_SIZE {
+            bail!(
+                "the attached file @{} is too large ({} KiB, at most {} KiB)",
+                attachment.mention,
+                metadata.len() / 1024,
+                MAX_ATTACHMENT_SIZE / 1024
+            );
+        }
+        let contents = fs::read_to_string(path).with_context(|| {
+            format!(
+                "failed to read the attached file @{} as text",
+                attachment.mention
+            )
+        })?;
+        attached.push_str(&format!(
+            "\n\nContents of {}:\n```\n{}\n```",
+            attachment.mention,
+            contents.trim_end()
+        ));
+    }
+    Ok(attached)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn mentions(prompt: &str) -> Vec<&str> {
+        find(prompt).into_iter().map(|a| a.mention).collect()
+    }
+
+    #[test]
+    fn expands_home_and_variables() {
+        let Some(home) = std::env::var_os("HOME") else {
+            return;
+        };
+        let notes = Path::new(&home).join("notes.txt");
+        for prompt in [
+            "summarize @~/notes.txt",
+            "summarize @$HOME/notes.txt",
+            "summarize @${HOME}/notes.txt",
+        ] {
+            assert_eq!(find(prompt)[0].path, notes, "{prompt}");
+        }
+    }
+
+    #[test]
+    fn leaves_out_trailing_punctuation() {
+        assert_eq!(
+            mentions("compare @a.txt, @b.txt; and @./c.rs."),
+            ["a.txt", "b.txt", "./c.rs"]
+        );
+        assert_eq!(mentions("(see @src/main.rs)"), ["src/main.rs"]);
+    }
+
+    #[test]
+    fn ignores_mentions_that_arent_paths() {
+        assert!(find("ask @someone about it").is_empty());
+        assert!(find("email me@example.com").is_empty());
+        assert!(find("just @ it").is_empty());
+    }
+
+    #[test]
+    fn attaches_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.txt");
+        fs::write(&path, "hello\n").unwrap();
+        let prompt = format!("summarize @{}", path.display());
+        assert_eq!(
+            attach(&prompt).unwrap(),
+            format!(
+                "{prompt}\n\nContents of {}:\n```\nhello\n```",
+                path.display()
+            )
+        );
+
+        let missing = format!("summarize @{}", dir.path().join("missing.txt").display());
+        assert!(attach(&missing)
+            .unwrap_err()
+            .to_string()
+            .contains("doesn't exist"));
+    }
+
+    #[test]
+    fn limits_the_size_of_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large.txt");
+        fs::write(&path, "a".repeat(MAX_ATTACHMENT_SIZE as usize)).unwrap();
+        let prompt = format!("summarize @{}", path.display());
+        assert!(attach(&prompt).is_ok());
+
+        fs::write(&path, "a".repeat(MAX_ATTACHMENT_SIZE as usize + 1)).unwrap();
+        let err = attach(&prompt).unwrap_err().to_string();
+        assert!(err.contains("too large"), "{err}");
+    }
+}
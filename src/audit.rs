@@ -0,0 +1,139 @@
+//! A log of every command that was actually run, as opposed to only generated, for finding out
+//! later what was run where. It's separate from the conversation history and only ever appended to.
+//!
+//! Each line of the log is a JSON object with the time the command finished (in seconds since the
+//! Unix epoch), the working directory, the exit code (null if the command was killed), and the
+//! command. Logging can be turned off by setting `executed_log` to false in the config.
+
+use crate::{config::data_dir, exec::Outcome};
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A command in the log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub time: SystemTime,
+    pub cwd: PathBuf,
+    /// `None` if the command was killed by a signal or for timing out.
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub command: String,
+}
+
+impl Entry {
+    fn to_json(&self) -> Value {
+        let secs = self
+            .time
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+        json!({
+            "time": secs,
+            "cwd": self.cwd.to_string_lossy(),
+            "exit_code": self.exit_code,
+            "timed_out": self.timed_out,
+            "command": self.command,
+        })
+    }
+
+    /// Parse a line of the log, returning `None` if it's malformed.
+    fn from_json(json: &Value) -> Option<Self> {
+        Some(Self {
+            time: UNIX_EPOCH + Duration::from_secs(json.get("time")?.as_u64()?),
+            cwd: PathBuf::from(json.get("cwd")?.as_str()?),
+            exit_code: json
+                .get("exit_code")
+                .and_then(Value::as_i64)
+                .and_then(|code| i32::try_from(code).ok()),
+            timed_out: json
+                .get("timed_out")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            command: json.get("command")?.as_str()?.to_owned(),
+        })
+    }
+}
+
+/// Get the path of the log.
+pub fn log_path() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("executed.log"))
+}
+
+/// Add a command that was just run to the log.
+pub fn append(command: &str, outcome: Outcome) -> Result<()> {
+    let path = log_path().context("couldn't find a data directory for the log")?;
+    let entry = Entry {
+        time: SystemTime::now(),
+        cwd: std::env::current_dir().unwrap_or_default(),
+        exit_code: match outcome {
+            Outcome::Exited(status) => status.code(),
+            Outcome::TimedOut => None,
+        },
+        timed_out: matches!(outcome, Outcome::TimedOut),
+        command: command.to_owned(),
+    };
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(file, "{}", entry.to_json())?;
+    Ok(())
+}
+
+/// Get the last `count` commands in the log, oldest first. Malformed lines are skipped.
+pub fn recent(count: usize) -> Result<Vec<Entry>> {
+    let Some(path) = log_path() else {
+        return Ok(Vec::new());
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read {}", path.display()));
+        }
+    };
+
+    let entries: Vec<Entry> = text
+        .lines()
+        .filter_map(|line| Entry::from_json(&serde_json::from_str(line).ok()?))
+        .collect();
+    let start = entries.len().saturating_sub(count);
+    Ok(entries[start..].to_vec())
+}
+
+/// Format a time in UTC as `YYYY-MM-DD HH:MM:SS`.
+pub fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // Convert days since the epoch to a date in the proleptic Gregorian calendar
+    let days = days as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
@@ -0,0 +1,37 @@
+//! Generating responses for many prompts at once, with several requests in flight.
+
+use crate::{Gptsh, Response};
+use anyhow::Result;
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+/// Ask each prompt on its own, sending up to `concurrency` requests at a time. The responses are
+/// returned in the same order as the prompts.
+pub fn ask_all(gptsh: &Gptsh, prompts: &[String], concurrency: usize) -> Vec<Result<Response>> {
+    let results: Vec<Mutex<Option<Result<Response>>>> =
+        prompts.iter().map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
+
+    // Each worker takes the next unanswered prompt until there are none left
+    thread::scope(|scope| {
+        for _ in 0..concurrency.clamp(1, prompts.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(prompt) = prompts.get(i) else {
+                    break;
+                };
+                *results[i].lock().unwrap() = Some(gptsh.ask(prompt));
+            });
+        }
+    });
+
+    results
+        .into_iter()
+        .map(|result| result.into_inner().unwrap().unwrap())
+        .collect()
+}
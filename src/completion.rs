@@ -0,0 +1,110 @@
+//! The model's reply to a request, along with the metadata the API returns about it.
+
+use crate::{classify, tools::ToolCall, ApiError, ChatMessage, PromptRules, Response};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// Token counts for a request.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+}
+
+impl Usage {
+    fn from_json(json: &Value) -> Option<Self> {
+        let count = |key| json.get(key).and_then(Value::as_u64).unwrap_or(0);
+        json.is_object().then(|| Self {
+            prompt_tokens: count("prompt_tokens"),
+            completion_tokens: count("completion_tokens"),
+            total_tokens: count("total_tokens"),
+        })
+    }
+
+    /// Add the counts of another request.
+    pub fn add(&mut self, other: Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+
+    pub fn to_json(self) -> Value {
+        json!({
+            "prompt_tokens": self.prompt_tokens,
+            "completion_tokens": self.completion_tokens,
+            "total_tokens": self.total_tokens,
+        })
+    }
+}
+
+/// A reply from the model.
+#[derive(Debug, Clone)]
+pub struct Completion {
+    /// The raw content of the reply, which is empty if the model only called tools.
+    pub content: String,
+    /// The tools the model called, when tool calling is enabled.
+    pub tool_calls: Vec<ToolCall>,
+    /// The tool calls as the API returned them, to be sent back as part of the conversation.
+    tool_calls_json: Option<Value>,
+    /// Why the model stopped generating, e.g. `stop` or `length`.
+    pub finish_reason: Option<String>,
+    /// Token counts, if the API reported them.
+    pub usage: Option<Usage>,
+    /// How long the request took.
+    pub latency: Duration,
+}
+
+impl Completion {
+    /// Parse the API's response JSON, returning it as an [`ApiError`] if it doesn't contain a reply.
+    pub(crate) fn from_json(json: Value, latency: Duration) -> Result<Self, ApiError> {
+        let choice = json.get("choices").and_then(|v| v.get(0));
+        let message = choice.and_then(|v| v.get("message"));
+        let content = message
+            .and_then(|v| v.get("content"))
+            .and_then(Value::as_str);
+        let tool_calls_json = message
+            .and_then(|v| v.get("tool_calls"))
+            .filter(|v| !v.is_null())
+            .cloned();
+        if content.is_none() && tool_calls_json.is_none() {
+            // Return response JSON if the server returns an error
+            return Err(ApiError(json));
+        }
+
+        Ok(Self {
+            content: content.unwrap_or_default().to_owned(),
+            tool_calls: tool_calls_json
+                .as_ref()
+                .map(ToolCall::parse_all)
+                .unwrap_or_default(),
+            tool_calls_json,
+            finish_reason: choice
+                .and_then(|v| v.get("finish_reason"))
+                .and_then(Value::as_str)
+                .map(str::to_owned),
+            usage: json.get("usage").and_then(Usage::from_json),
+            latency,
+        })
+    }
+
+    /// The reply as a message to add to the conversation.
+    pub fn message(&self) -> ChatMessage {
+        ChatMessage {
+            content: (!self.content.is_empty() || self.tool_calls_json.is_none())
+                .then(|| self.content.clone()),
+            tool_calls: self.tool_calls_json.clone(),
+            ..ChatMessage::assistant("")
+        }
+    }
+
+    /// Whether the model was cut off by the token limit, leaving the reply incomplete.
+    pub fn truncated(&self) -> bool {
+        self.finish_reason.as_deref() == Some("length")
+    }
+
+    /// Classify the reply as an answer or a command, for a request made with `rules`.
+    pub fn response(&self, rules: PromptRules) -> Response {
+        classify(&self.content, rules)
+    }
+}
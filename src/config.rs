@@ -0,0 +1,323 @@
+//! Loading of the optional JSON config file.
+
+use crate::{
+    allowlist::Allowlist, confirm::Confirm, danger::DangerPatterns, history::HistoryFormat,
+    hooks::Hooks, preset::Preset, temperature::TemperaturePresets, template::Template,
+};
+use anyhow::{bail, Context, Result};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// User configuration, read from `config.json` in the gptsh config directory.
+#[derive(Default)]
+pub struct Config {
+    /// Additional (or overridden) provider presets, keyed by name.
+    pub presets: HashMap<String, Preset>,
+    /// Where to save the REPL conversation history, if it should be saved.
+    pub history: Option<HistoryConfig>,
+    /// Scripts to run around generated commands.
+    pub hooks: Hooks,
+    /// How generated commands are confirmed.
+    pub confirm: Confirm,
+    /// The only programs generated commands may run, if set.
+    pub allowlist: Option<Allowlist>,
+    /// Commands that always have to be confirmed by typing a word.
+    pub danger_patterns: DangerPatterns,
+    /// Prompt templates, keyed by name.
+    pub templates: HashMap<String, Template>,
+    /// Fields to add to every request body, overriding gptsh's own.
+    pub extra_body: Map<String, Value>,
+    /// Named temperatures for `--preset-temp` and `/temp`.
+    pub temperature_presets: TemperaturePresets,
+    /// Whether to log executed commands, which is done unless this is set to false.
+    pub executed_log: Option<bool>,
+    /// Default request parameters for particular models, keyed by model name.
+    pub models: HashMap<String, ModelDefaults>,
+    /// How many turns a REPL conversation can have before gptsh warns that it's getting long, or 0
+    /// to never warn.
+    pub max_turns: Option<u64>,
+}
+
+/// Request parameters to use by default with a model, from the `models` object of the config:
+///
+/// ```json
+/// "models": {
+///     "gpt-4o-mini": {"temperature": 0},
+///     "gpt-4o": {"temperature": 0.7, "max_tokens": 1000}
+/// }
+/// ```
+///
+/// They apply when the model's name matches exactly, and are overridden by a template's
+/// temperature, `--preset-temp`, and `--max-tokens`. Without them, the API's defaults are used.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ModelDefaults {
+    pub temperature: Option<f64>,
+    pub max_tokens: Option<u64>,
+}
+
+impl ModelDefaults {
+    fn from_json(json: &Value) -> Result<Self> {
+        if !json.is_object() {
+            bail!("model defaults must be an object");
+        }
+        Ok(Self {
+            temperature: match json.get("temperature") {
+                None | Some(Value::Null) => None,
+                Some(temperature) => Some(
+                    temperature
+                        .as_f64()
+                        .context("`temperature` must be a number")?,
+                ),
+            },
+            max_tokens: match json.get("max_tokens") {
+                None | Some(Value::Null) => None,
+                Some(max_tokens) => Some(
+                    max_tokens
+                        .as_u64()
+                        .filter(|&max_tokens| max_tokens > 0)
+                        .context("`max_tokens` must be a positive integer")?,
+                ),
+            },
+        })
+    }
+}
+
+/// Settings for saving the REPL conversation history.
+pub struct HistoryConfig {
+    pub path: PathBuf,
+    /// Detected from the file if not set.
+    pub format: Option<HistoryFormat>,
+}
+
+impl Config {
+    /// Load the config file, returning the default config if it doesn't exist. If `path` is given,
+    /// or `$GPTSH_CONFIG` is set, exactly that file is loaded instead, and it must exist.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        if let Some(path) = path.map(Path::to_owned).or_else(env_config_path) {
+            return Self::load_from(&path);
+        }
+        match config_path() {
+            Some(path) if path.exists() => Self::load_from(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    fn load_from(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        let json: Value = serde_json::from_str(&text)
+            .with_context(|| format!("failed to parse config file {}", path.display()))?;
+        Self::from_json(&json).with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    fn from_json(json: &Value) -> Result<Self> {
+        let mut config = Self::default();
+
+        if let Some(presets) = json.get("presets") {
+            let Some(presets) = presets.as_object() else {
+                bail!("`presets` must be an object");
+            };
+            for (name, value) in presets {
+                let preset =
+                    Preset::from_json(value).with_context(|| format!("invalid preset `{name}`"))?;
+                config.presets.insert(name.clone(), preset);
+            }
+        }
+
+        if let Some(templates) = json.get("templates") {
+            let Some(templates) = templates.as_object() else {
+                bail!("`templates` must be an object");
+            };
+            for (name, value) in templates {
+                let template = Template::from_json(value)
+                    .with_context(|| format!("invalid template `{name}`"))?;
+                config.templates.insert(name.clone(), template);
+            }
+        }
+
+        if let Some(models) = json.get("models") {
+            let Some(models) = models.as_object() else {
+                bail!("`models` must be an object");
+            };
+            for (name, value) in models {
+                let defaults = ModelDefaults::from_json(value)
+                    .with_context(|| format!("invalid defaults for model `{name}`"))?;
+                config.models.insert(name.clone(), defaults);
+            }
+        }
+
+        if let Some(allowlist) = json.get("allowlist") {
+            config.allowlist = Some(Allowlist::from_json(allowlist)?);
+        }
+
+        if let Some(safety) = json.get("safety") {
+            config.danger_patterns = DangerPatterns::from_json(safety)?;
+        }
+
+        if let Some(extra_body) = json.get("extra_body") {
+            let Some(extra_body) = extra_body.as_object() else {
+                bail!("`extra_body` must be an object");
+            };
+            config.extra_body = extra_body.clone();
+        }
+
+        if let Some(presets) = json.get("temperature_presets") {
+            config.temperature_presets = TemperaturePresets::from_json(presets)?;
+        }
+
+        config.executed_log = match json.get("executed_log") {
+            None | Some(Value::Null) => None,
+            Some(Value::Bool(log)) => Some(*log),
+            Some(_) => bail!("`executed_log` must be a boolean"),
+        };
+
+        config.max_turns = match json.get("max_turns") {
+            None | Some(Value::Null) => None,
+            Some(max_turns) => Some(
+                max_turns
+                    .as_u64()
+                    .context("`max_turns` must be a non-negative integer")?,
+            ),
+        };
+
+        if let Some(hooks) = json.get("hooks") {
+            config.hooks = Hooks::from_json(hooks)?;
+        }
+
+        if let Some(confirm) = json.get("confirm") {
+            config.confirm = Confirm::from_json(confirm)?;
+        }
+
+        config.history = match json.get("history") {
+            None | Some(Value::Null | Value::Bool(false)) => None,
+            Some(Value::Bool(true)) => Some(HistoryConfig {
+                path: default_history_path()?,
+                format: None,
+            }),
+            Some(history @ Value::Object(_)) => Some(HistoryConfig {
+                path: match get_str(history, "path")? {
+                    Some(path) => expand_tilde(&path),
+                    None => default_history_path()?,
+                },
+                format: get_str(history, "format")?
+                    .map(|s| HistoryFormat::parse(&s))
+                    .transpose()?,
+            }),
+            Some(_) => bail!("`history` must be a boolean or an object"),
+        };
+
+        Ok(config)
+    }
+}
+
+/// Get where the history is saved when the config doesn't give a path.
+pub fn default_history_path() -> Result<PathBuf> {
+    Ok(data_dir()
+        .context("couldn't find a data directory for the history file")?
+        .join("history.jsonl"))
+}
+
+/// Get the directory that gptsh's config files live in.
+pub fn config_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        std::env::var_os("APPDATA").map(|dir| PathBuf::from(dir).join("gptsh"))
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .map(|dir| dir.join("gptsh"))
+    }
+}
+
+/// Get the directory that gptsh stores its data (such as history) in.
+pub fn data_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(|dir| PathBuf::from(dir).join("gptsh"))
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })
+            .map(|dir| dir.join("gptsh"))
+    }
+}
+
+/// Get the directory that gptsh caches data that can be fetched again (such as model lists) in.
+pub fn cache_dir() -> Option<PathBuf> {
+    if cfg!(windows) {
+        std::env::var_os("LOCALAPPDATA").map(|dir| PathBuf::from(dir).join("gptsh").join("cache"))
+    } else {
+        std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .map(|dir| dir.join("gptsh"))
+    }
+}
+
+/// Replace a leading `~` in a path with the home directory.
+pub fn expand_tilde(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with(['/', '\\']) => {
+            PathBuf::from(home).join(rest.trim_start_matches(['/', '\\']))
+        }
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Expand a leading `~` or environment variable, like `$HOME` or `${XDG_CONFIG_HOME}`.
+pub fn expand_path(path: &str) -> PathBuf {
+    let Some(rest) = path.strip_prefix('$') else {
+        return expand_tilde(path);
+    };
+    let (name, rest) = match rest.strip_prefix('{') {
+        Some(rest) => match rest.split_once('}') {
+            Some(split) => split,
+            None => return PathBuf::from(path),
+        },
+        None => {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            rest.split_at(end)
+        }
+    };
+    match std::env::var_os(name) {
+        Some(value) => PathBuf::from(value).join(rest.trim_start_matches(['/', '\\'])),
+        None => PathBuf::from(path),
+    }
+}
+
+/// Replace the home directory at the start of a path with `~`, for showing it.
+pub fn contract_tilde(path: &Path) -> String {
+    let rest = std::env::var_os("HOME").and_then(|home| path.strip_prefix(home).ok());
+    match rest {
+        Some(rest) if rest.as_os_str().is_empty() => "~".to_owned(),
+        Some(rest) => Path::new("~").join(rest).display().to_string(),
+        None => path.display().to_string(),
+    }
+}
+
+/// Get the path of the config file, which may not exist. `$GPTSH_CONFIG` overrides the default.
+pub fn config_path() -> Option<PathBuf> {
+    env_config_path().or_else(|| config_dir().map(|dir| dir.join("config.json")))
+}
+
+/// Get the config file set with `$GPTSH_CONFIG`, if any.
+fn env_config_path() -> Option<PathBuf> {
+    std::env::var_os("GPTSH_CONFIG")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Get an optional string field from a JSON object, erroring if it has the wrong type.
+pub(crate) fn get_str(json: &Value, key: &str) -> Result<Option<String>> {
+    match json.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => bail!("`{key}` must be a string"),
+    }
+}
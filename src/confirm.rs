@@ -0,0 +1,177 @@
+//! The prompt for confirming a generated command, with keys that can be changed in the config.
+//!
+//! The `confirm` object in the config file can contain:
+//!
+//! - `default`: the answer given by pressing Enter. Without one, Enter does nothing.
+//! - `wait_for_newline`: require pressing Enter after the key, instead of answering immediately.
+//! - `accept` and `reject`: the characters that answer yes and no, e.g. `"jJ"`. Each defaults to
+//!   `y`/`n` if only the other one is set.
+//! - `safe_commands`: `true`, or an array of programs. Commands that only run these programs, and
+//!   don't redirect output to files, get a compact `[run] y/N` prompt instead, answered with a
+//!   single key. `true` uses the read-only `ls`, `cat`, `pwd`, and `echo`.
+
+use crate::{
+    allowlist::{writes_files, Allowlist},
+    config::get_str,
+};
+use anyhow::{anyhow, bail, Result};
+use dialoguer::console::{Key, Term};
+use serde_json::Value;
+use std::io;
+
+/// The programs that `safe_commands: true` allows, which only read.
+pub const DEFAULT_SAFE_COMMANDS: &[&str] = &["ls", "cat", "pwd", "echo"];
+
+/// How a command is confirmed.
+#[derive(Debug, Clone, Default)]
+pub struct Confirm {
+    pub default: Option<bool>,
+    pub wait_for_newline: bool,
+    pub accept: Option<String>,
+    pub reject: Option<String>,
+    /// The programs whose commands get the compact prompt, if it's enabled.
+    pub safe_commands: Option<Allowlist>,
+}
+
+impl Confirm {
+    /// Parse the settings from the `confirm` object in the config file.
+    pub fn from_json(json: &Value) -> Result<Self> {
+        if !json.is_object() {
+            bail!("`confirm` must be an object");
+        }
+        let get_bool = |key| match json.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::Bool(value)) => Ok(Some(*value)),
+            Some(_) => bail!("`{key}` must be a boolean"),
+        };
+        let get_keys = |key| match get_str(json, key)? {
+            Some(keys) if keys.is_empty() => bail!("`{key}` must have at least one character"),
+            keys => Ok(keys),
+        };
+
+        let confirm = Self {
+            default: get_bool("default")?,
+            wait_for_newline: get_bool("wait_for_newline")?.unwrap_or(false),
+            accept: get_keys("accept")?,
+            reject: get_keys("reject")?,
+            safe_commands: match json.get("safe_commands") {
+                None | Some(Value::Null | Value::Bool(false)) => None,
+                Some(Value::Bool(true)) => Some(Allowlist {
+                    programs: DEFAULT_SAFE_COMMANDS
+                        .iter()
+                        .map(|&p| p.to_owned())
+                        .collect(),
+                }),
+                Some(programs) => Some(Allowlist::from_json(programs).map_err(|_| {
+                    anyhow!("`safe_commands` must be a boolean or an array of strings")
+                })?),
+            },
+        };
+        let (accept, reject) = confirm.keys();
+        if accept.chars().any(|c| reject.contains(c)) {
+            bail!("`accept` and `reject` can't share characters");
+        }
+        Ok(confirm)
+    }
+
+    /// Ask a yes or no question.
+    pub fn interact(&self, prompt: &str) -> io::Result<bool> {
+        if self.accept.is_none() && self.reject.is_none() {
+            // dialoguer's prompt handles the default keys
+            let mut confirm = dialoguer::Confirm::new();
+            confirm
+                .with_prompt(prompt)
+                .wait_for_newline(self.wait_for_newline);
+            if let Some(default) = self.default {
+                confirm.default(default);
+            }
+            return confirm.interact();
+        }
+
+        let (accept, reject) = self.keys();
+        let label = |keys: &str, default| {
+            let key = keys.chars().next().unwrap_or_default();
+            if self.default == Some(default) {
+                key.to_uppercase().to_string()
+            } else {
+                key.to_string()
+            }
+        };
+        let term = Term::stderr();
+        term.write_str(&format!(
+            "{prompt} [{}/{}] ",
+            label(accept, true),
+            label(reject, false)
+        ))?;
+
+        loop {
+            let answer = if self.wait_for_newline {
+                let line = term.read_line()?;
+                let mut chars = line.trim().chars();
+                match (chars.next(), chars.next()) {
+                    (None, _) => self.default,
+                    (Some(c), None) => self.answer(c),
+                    _ => None,
+                }
+            } else {
+                match term.read_key()? {
+                    Key::Enter => self.default,
+                    Key::Char(c) => self.answer(c),
+                    _ => None,
+                }
+            };
+
+            if let Some(answer) = answer {
+                if !self.wait_for_newline {
+                    term.write_line(&label(if answer { accept } else { reject }, answer))?;
+                }
+                return Ok(answer);
+            }
+        }
+    }
+
+    /// Check whether a command can be confirmed with the compact prompt, because it only runs safe
+    /// programs and doesn't write to files.
+    pub fn is_safe(&self, command: &str) -> bool {
+        self.safe_commands
+            .as_ref()
+            .is_some_and(|safe| safe.check(command).is_ok() && !writes_files(command))
+    }
+
+    /// Ask whether to run a safe command with a compact prompt like `[run] y/N`, answered by a
+    /// single key. Any key but an accept key means no.
+    pub fn interact_compact(&self, label: &str) -> io::Result<bool> {
+        let (accept, reject) = self.keys();
+        let first = |keys: &str| keys.chars().next().unwrap_or_default();
+        let term = Term::stderr();
+        term.write_str(&format!(
+            "{label} {}/{} ",
+            first(accept),
+            first(reject).to_uppercase()
+        ))?;
+
+        let answer = matches!(term.read_key()?, Key::Char(c) if accept.contains(c));
+        term.write_line(&first(if answer { accept } else { reject }).to_string())?;
+        Ok(answer)
+    }
+
+    /// Get the answer a key gives, if any.
+    fn answer(&self, key: char) -> Option<bool> {
+        let (accept, reject) = self.keys();
+        if accept.contains(key) {
+            Some(true)
+        } else if reject.contains(key) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// Get the accept and reject characters.
+    fn keys(&self) -> (&str, &str) {
+        (
+            self.accept.as_deref().unwrap_or("yY"),
+            self.reject.as_deref().unwrap_or("nN"),
+        )
+    }
+}
@@ -0,0 +1,560 @@
+//! Detection of generated commands that need more care than usual before they're run.
+//!
+//! This catches commands that touch gptsh's own files, like its config, history, or executable,
+//! which a command could break or delete without the user noticing. The check only looks at the
+//! paths that appear in a command, so it can't catch paths that are built at runtime.
+//!
+//! It also catches commands matching [dangerous patterns](DangerPatterns), like wiping a disk,
+//! which the `safety` object in the config can add to or replace:
+//!
+//! ```json
+//! "safety": {
+//!     "patterns": ["*deploy --env=prod*", "*kubectl delete *"],
+//!     "replace": false
+//! }
+//! ```
+//!
+//! The patterns are globs, not regular expressions: `*` matches any text and `?` any character,
+//! and nothing else is special. Patterns that look like regular expressions, like `^rm .*`, are
+//! rejected, since they'd never match what was meant.
+
+use crate::config::{config_dir, data_dir, expand_path};
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::{
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+
+/// One of gptsh's own files or directories.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OwnFile {
+    ConfigDir,
+    DataDir,
+    History,
+    Executable,
+}
+
+impl fmt::Display for OwnFile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::ConfigDir => "config directory",
+            Self::DataDir => "data directory",
+            Self::History => "history file",
+            Self::Executable => "executable",
+        })
+    }
+}
+
+/// The locations of gptsh's own files, for checking whether commands touch them.
+pub struct OwnFiles {
+    paths: Vec<(OwnFile, PathBuf)>,
+}
+
+impl OwnFiles {
+    /// Find gptsh's files, including the history file at `history` if history is enabled.
+    pub fn find(history: Option<&Path>) -> Self {
+        let paths = [
+            (OwnFile::ConfigDir, config_dir()),
+            (OwnFile::DataDir, data_dir()),
+            (OwnFile::History, history.map(Path::to_owned)),
+            (OwnFile::Executable, std::env::current_exe().ok()),
+        ];
+        Self {
+            paths: paths
+                .into_iter()
+                .filter_map(|(file, path)| Some((file, normalize(&path?))))
+                .collect(),
+        }
+    }
+
+    /// Get the first of gptsh's files that a command mentions, along with the path it mentions.
+    /// Relative paths are resolved against the current directory.
+    pub fn touched_by(&self, command: &str) -> Option<(OwnFile, PathBuf)> {
+        let cwd = std::env::current_dir().ok()?;
+        let words = shell_words::split(command)
+            .unwrap_or_else(|_| command.split_whitespace().map(str::to_owned).collect());
+
+        for word in &words {
+            for arg in path_candidates(word) {
+                let path = expand_path(arg);
+                // Bare words like `ls` would resolve to the current directory, so relative paths
+                // only count if they look like paths or name something that exists
+                if path.is_relative() && !arg.contains(['/', '\\']) && !cwd.join(&path).exists() {
+                    continue;
+                }
+                let path = normalize(&cwd.join(path));
+                if let Some((file, _)) = self.paths.iter().find(|(_, own)| path.starts_with(own)) {
+                    return Some((*file, path));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// The patterns of commands that are dangerous unless the config replaces them. They're kept to
+/// commands that can destroy a system or its data, so that they're rarely matched by accident.
+pub const DEFAULT_PATTERNS: &[&str] = &[
+    "*rm -rf /",
+    "*rm -rf / *",
+    "*rm -rf ~",
+    "*rm -rf ~/",
+    "*--no-preserve-root*",
+    "*mkfs*",
+    "*dd *of=/dev/*",
+    "*>*/dev/sd*",
+    "*:(){*",
+    "*chmod -R 777 /",
+];
+
+/// Patterns of commands that are dangerous enough to always confirm by typing a word.
+///
+/// A pattern has to match the whole command, where `*` matches any text and `?` any single
+/// character. Runs of whitespace count as a single space in both, so spacing doesn't matter.
+/// To match a command anywhere in a line, start and end the pattern with `*`. Patterns are glob
+/// patterns rather than regular expressions, since that's enough for commands and easier to get
+/// right.
+///
+/// Commands are matched with the flags of `rm` normalized (see [`normalize_rm`]), so a pattern
+/// like `*rm -rf /` also catches `rm -r -f /*`.
+#[derive(Debug, Clone)]
+pub struct DangerPatterns {
+    pub patterns: Vec<String>,
+}
+
+impl Default for DangerPatterns {
+    fn default() -> Self {
+        Self {
+            patterns: DEFAULT_PATTERNS.iter().map(|&p| p.to_owned()).collect(),
+        }
+    }
+}
+
+impl DangerPatterns {
+    /// Parse the `safety` object of the config. Its `patterns` are added to the default ones,
+    /// unless `replace` is true.
+    pub fn from_json(json: &Value) -> Result<Self> {
+        if !json.is_object() {
+            bail!("`safety` must be an object");
+        }
+        let replace = match json.get("replace") {
+            None | Some(Value::Null) => false,
+            Some(Value::Bool(replace)) => *replace,
+            Some(_) => bail!("`replace` must be a boolean"),
+        };
+        let patterns = match json.get("patterns") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::Array(patterns)) => patterns
+                .iter()
+                .map(|pattern| pattern.as_str().map(str::to_owned))
+                .collect::<Option<_>>()
+                .context("`patterns` must be an array of strings")?,
+            Some(_) => bail!("`patterns` must be an array of strings"),
+        };
+        for pattern in &patterns {
+            check_pattern(pattern).with_context(|| format!("invalid pattern `{pattern}`"))?;
+        }
+
+        let mut danger = if replace {
+            Self {
+                patterns: Vec::new(),
+            }
+        } else {
+            Self::default()
+        };
+        danger.patterns.extend(patterns);
+        Ok(danger)
+    }
+
+    /// Get the first pattern a command matches, if any.
+    pub fn matching(&self, command: &str) -> Option<&str> {
+        let command: Vec<char> = normalize_rm(command).chars().collect();
+        self.patterns
+            .iter()
+            .find(|pattern| {
+                let pattern: Vec<char> = squeeze_whitespace(pattern).chars().collect();
+                glob_match(&pattern, &command)
+            })
+            .map(String::as_str)
+    }
+}
+
+/// Reject patterns that would match nothing or every command, which are always mistakes.
+fn check_pattern(pattern: &str) -> Result<()> {
+    if pattern.trim().is_empty() {
+        bail!("patterns can't be empty");
+    }
+    if pattern.chars().all(|c| c == '*' || c.is_whitespace()) {
+        bail!("the pattern would match every command");
+    }
+    if looks_like_regex(pattern) {
+        bail!(
+            "patterns are globs, not regular expressions: `*` matches any text, `?` any single \
+             character, and everything else only itself, as in `*rm -rf *`"
+        );
+    }
+    Ok(())
+}
+
+/// Regular expression syntax that doesn't turn up in commands: character classes like `\s`, and
+/// groups with flags like `(?i)`.
+const REGEX_SYNTAX: &[&str] = &["\\b", "\\B", "\\d", "\\D", "\\s", "\\S", "\\w", "\\W", "(?"];
+
+/// Check whether a pattern is probably meant as a regular expression, which would silently never
+/// match: it's anchored with `^` or `$`, uses syntax from [`REGEX_SYNTAX`], only ever has `*` after
+/// `.`, repeats something with `+` or `{n}`, or has a `[...]` class or an `(a|b)` alternation.
+/// Patterns like `*rm .*` are fine, since `.*` there is the shell glob for hidden files, and so
+/// are ones like `*g++ *`, `*${files[0]}*`, and `*echo $(a|b)*`.
+fn looks_like_regex(pattern: &str) -> bool {
+    let stars = pattern.matches('*').count();
+    pattern.starts_with('^')
+        || pattern.ends_with('$')
+        || REGEX_SYNTAX.iter().any(|syntax| pattern.contains(syntax))
+        || (stars > 0 && pattern.matches(".*").count() == stars)
+        || has_regex_group(pattern)
+}
+
+/// Check for a repetition of `.` or a group (as in `.+`, `[rf]+`, or `.{3}`), a `[...]` character
+/// class, or an `(a|b)` alternation.
+fn has_regex_group(pattern: &str) -> bool {
+    let chars: Vec<char> = pattern.chars().collect();
+    chars.iter().enumerate().any(|(i, &c)| {
+        let before = i.checked_sub(1).map(|i| chars[i]);
+        let rest = &chars[i + 1..];
+        match c {
+            '+' => matches!(before, Some('.' | ']' | ')')),
+            '{' => matches!(before, Some('.' | ']' | ')')) && is_repetition(rest),
+            // Not after a name, as in the `${files[0]}` of an array
+            '[' => {
+                !before.is_some_and(|c| c.is_alphanumeric() || c == '_')
+                    && group_end(rest, ']').is_some_and(|end| end > 0)
+            }
+            // Not after `$`, as in the `$(a|b)` of a command substitution
+            '(' => {
+                before != Some('$')
+                    && group_end(rest, ')').is_some_and(|end| rest[..end].contains(&'|'))
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Find where a group closed by `close` ends in the text after its opening, if it's closed without
+/// any whitespace in it.
+fn group_end(rest: &[char], close: char) -> Option<usize> {
+    let end = rest.iter().position(|&c| c == close || c.is_whitespace())?;
+    (rest[end] == close).then_some(end)
+}
+
+/// Check whether the text after a `{` is a count of repetitions, like `3}` or `2,5}`.
+fn is_repetition(rest: &[char]) -> bool {
+    group_end(rest, '}').is_some_and(|end| {
+        let count = &rest[..end];
+        count.first().is_some_and(char::is_ascii_digit)
+            && count.iter().all(|&c| c.is_ascii_digit() || c == ',')
+    })
+}
+
+/// Trim text and replace each run of whitespace in it with a single space.
+fn squeeze_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Rewrite the ways of giving `rm` its recursive and force flags as a single `-rf`, whatever their
+/// order or spelling (`-fr`, `-r -f`, `--recursive`), with its other flags moved after its
+/// arguments. The `*` of arguments ending in `/*` is dropped too, since removing everything in a
+/// directory is as bad as removing it.
+/// Whitespace is squeezed like [`squeeze_whitespace`] does.
+fn normalize_rm(command: &str) -> String {
+    let mut words: Vec<String> = Vec::new();
+    let mut rest = command.split_whitespace().peekable();
+    while let Some(word) = rest.next() {
+        words.push(word.to_owned());
+        if word != "rm" && !word.ends_with("/rm") {
+            continue;
+        }
+
+        let (mut recursive, mut force) = (false, false);
+        let mut flags = Vec::new();
+        while let Some(flag) = rest.next_if(|w| w.starts_with('-') && w.len() > 1 && *w != "--") {
+            match flag {
+                "--recursive" => recursive = true,
+                "--force" => force = true,
+                _ if flag.starts_with("--") => flags.push(flag.to_owned()),
+                _ => {
+                    let mut other = String::new();
+                    for c in flag[1..].chars() {
+                        match c {
+                            'r' | 'R' => recursive = true,
+                            'f' => force = true,
+                            _ => other.push(c),
+                        }
+                    }
+                    if !other.is_empty() {
+                        flags.push(format!("-{other}"));
+                    }
+                }
+            }
+        }
+        match (recursive, force) {
+            (true, true) => words.push("-rf".to_owned()),
+            (true, false) => words.push("-r".to_owned()),
+            (false, true) => words.push("-f".to_owned()),
+            (false, false) => {}
+        }
+
+        // The arguments, up to the end of the command
+        while let Some(arg) = rest.next_if(|w| !matches!(*w, ";" | "&&" | "||" | "|" | "&")) {
+            words.push(
+                arg.strip_suffix("/*")
+                    .map_or(arg.to_owned(), |dir| format!("{dir}/")),
+            );
+        }
+        words.extend(flags);
+    }
+    words.join(" ")
+}
+
+/// Match text against a glob pattern where `*` is any text and `?` any character.
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    // Where to resume after the last `*` if the rest doesn't match: the pattern after it, and the
+    // text after what it has matched so far
+    let mut star: Option<(usize, usize)> = None;
+    let (mut p, mut t) = (0, 0);
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                // Let the `*` match one more character and try again
+                Some((star_p, star_t)) => {
+                    star = Some((star_p, star_t + 1));
+                    p = star_p;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Get the parts of a word that could be paths: the word without any redirection or separators
+/// around it, and the value of an `--option=value`.
+fn path_candidates(word: &str) -> Vec<&str> {
+    let word = word.trim_start_matches(|c: char| c.is_ascii_digit());
+    let word = word
+        .trim_start_matches(['<', '>', '&', '(', '|'])
+        .trim_end_matches([';', '&', '|', ')']);
+    let mut candidates = vec![word];
+    if let Some((option, value)) = word.split_once('=') {
+        if option.starts_with('-') && !value.is_empty() {
+            candidates.push(value);
+        }
+    }
+    candidates.retain(|candidate| !candidate.is_empty() && !candidate.starts_with('-'));
+    candidates
+}
+
+/// Resolve `.` and `..` in a path without touching the filesystem, since the path might not exist.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// gptsh's files as if the config directory were `config` in `dir`.
+    fn own_files(dir: &Path, history: &Path) -> OwnFiles {
+        let mut own = OwnFiles::find(Some(history));
+        own.paths.retain(|(file, _)| *file != OwnFile::ConfigDir);
+        own.paths.push((OwnFile::ConfigDir, dir.join("config")));
+        own
+    }
+
+    #[test]
+    fn flags_own_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = dir.path().join("history.jsonl");
+        let own = own_files(dir.path(), &history);
+        let config = dir.path().join("config");
+        let exe = std::env::current_exe().unwrap();
+
+        let touched = |command: String| own.touched_by(&command).map(|(file, _)| file);
+        assert_eq!(
+            touched(format!("rm -r {}", config.display())),
+            Some(OwnFile::ConfigDir)
+        );
+        assert_eq!(
+            touched(format!("echo x >{}/config.json", config.display())),
+            Some(OwnFile::ConfigDir)
+        );
+        assert_eq!(
+            touched(format!("tool --config={}/a/../b", config.display())),
+            Some(OwnFile::ConfigDir)
+        );
+        assert_eq!(
+            touched(format!("truncate -s 0 '{}'", history.display())),
+            Some(OwnFile::History)
+        );
+        assert_eq!(
+            touched(format!("cp /dev/null {}", exe.display())),
+            Some(OwnFile::Executable)
+        );
+    }
+
+    #[test]
+    fn ignores_unrelated_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = dir.path().join("history.jsonl");
+        let own = own_files(dir.path(), &history);
+
+        assert_eq!(own.touched_by("ls -la"), None);
+        let configuration = dir.path().join("configuration");
+        assert_eq!(
+            own.touched_by(&format!("rm -r {}", configuration.display())),
+            None
+        );
+        let other = dir.path().join("history.jsonl.bak");
+        assert_eq!(own.touched_by(&format!("rm {}", other.display())), None);
+    }
+
+    fn glob(pattern: &str, text: &str) -> bool {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let text: Vec<char> = text.chars().collect();
+        glob_match(&pattern, &text)
+    }
+
+    #[test]
+    fn matches_globs() {
+        assert!(glob("ls", "ls"));
+        assert!(!glob("ls", "ls -la"));
+        assert!(glob("ls*", "ls -la"));
+        assert!(glob("*-la", "ls -la"));
+        assert!(glob("*rm*", "sudo rm x"));
+        assert!(glob("l?", "ls"));
+        assert!(!glob("l?", "l"));
+        assert!(glob("*a*b*", "xaybz"));
+        assert!(!glob("*a*b", "xaybz"));
+        assert!(glob("**", ""));
+        assert!(!glob("?", ""));
+    }
+
+    #[test]
+    fn matches_default_patterns() {
+        let danger = DangerPatterns::default();
+        for command in [
+            "rm -rf /",
+            "sudo rm  -rf   /",
+            "rm -rf /*",
+            "rm -fr /",
+            "rm -r -f /",
+            "rm -Rf /",
+            "rm --recursive --force /",
+            "rm -rf ~",
+            "rm -rf ~/*",
+            "rm -rfv / && echo done",
+            "rm -rf / --no-preserve-root",
+            "mkfs.ext4 /dev/sda1",
+            "dd if=/dev/zero of=/dev/sda",
+            ":(){ :|:& };:",
+        ] {
+            assert!(danger.matching(command).is_some(), "{command}");
+        }
+        for command in [
+            "rm -rf build",
+            "rm -rf /tmp/build",
+            "rm -rf /home/me/*",
+            "rm -f ~/notes.txt",
+            "rm -r ~/old",
+            "ls /",
+            "echo /* && rm x",
+        ] {
+            assert_eq!(danger.matching(command), None, "{command}");
+        }
+    }
+
+    #[test]
+    fn parses_patterns() {
+        let danger = DangerPatterns::from_json(&serde_json::json!({
+            "patterns": ["*kubectl delete *"],
+        }))
+        .unwrap();
+        assert!(danger.matching("kubectl delete pod x").is_some());
+        assert!(danger.matching("rm -rf /").is_some());
+
+        let danger = DangerPatterns::from_json(&serde_json::json!({
+            "patterns": ["*kubectl delete *"],
+            "replace": true,
+        }))
+        .unwrap();
+        assert_eq!(danger.patterns, vec!["*kubectl delete *"]);
+    }
+
+    #[test]
+    fn rejects_regular_expressions() {
+        for pattern in [
+            "^rm -rf /",
+            "rm -rf /$",
+            "\\brm\\b",
+            "rm\\s+-rf",
+            ".*rm -rf.*",
+            "*rm -rf \\S*",
+            "*\\wrm*",
+            "(?i)*rm -rf*",
+            "*rm -[rf]* /",
+            "*rm -rf .+",
+            "*(rm|mv) /*",
+            "*dd if=.{3}*",
+            "*rm -rf (/|~)*",
+        ] {
+            let json = serde_json::json!({"patterns": [pattern]});
+            let err = DangerPatterns::from_json(&json).unwrap_err();
+            let message = format!("{err:#}");
+            assert!(message.contains("not regular expressions"), "{pattern}");
+            assert!(message.contains("`?` any single character"), "{pattern}");
+        }
+        let json = serde_json::json!({"patterns": [
+            "*rm -rf .*",
+            "*rm *.*",
+            "*echo $HOME*",
+            "*g++ *",
+            "*rm ${files[0]}*",
+            "*curl * | sh*",
+            "*echo $(a|b)*",
+            "*[ -f x ]*",
+            "*echo {a,b}*",
+            "*find * -exec rm {} \\;*",
+            "*rm -rf /",
+        ]});
+        assert!(DangerPatterns::from_json(&json).is_ok());
+    }
+
+    #[test]
+    fn rejects_empty_or_match_everything_patterns() {
+        for pattern in ["", "  ", "*", "* *", "**"] {
+            let json = serde_json::json!({"patterns": [pattern]});
+            assert!(DangerPatterns::from_json(&json).is_err(), "{pattern:?}");
+        }
+        assert!(DangerPatterns::from_json(&serde_json::json!({"patterns": "*x*"})).is_err());
+        assert!(DangerPatterns::from_json(&serde_json::json!({"patterns": [1]})).is_err());
+    }
+}
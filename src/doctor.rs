@@ -0,0 +1,96 @@
+//! `gptsh doctor`, which checks the setup and prints a report that can be shared when filing an
+//! issue.
+
+use crate::{transport::Http, Endpoint};
+use colored::Colorize;
+use serde_json::Value;
+
+/// The result of a single check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Pass,
+    Fail,
+}
+
+/// A check and what it found.
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+/// The checks run so far, in order.
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn pass(&mut self, name: &'static str, detail: impl Into<String>) {
+        self.add(name, Status::Pass, detail);
+    }
+
+    pub fn fail(&mut self, name: &'static str, detail: impl Into<String>) {
+        self.add(name, Status::Fail, detail);
+    }
+
+    fn add(&mut self, name: &'static str, status: Status, detail: impl Into<String>) {
+        self.checks.push(Check {
+            name,
+            status,
+            detail: detail.into(),
+        });
+    }
+
+    /// Check that the API at `models_url` (e.g. `https://api.openai.com/v1/models`) can be reached
+    /// and accepts the key, by listing the models, which costs nothing.
+    pub fn check_api(&mut self, endpoint: &Endpoint, models_url: &str) {
+        let json = match Http::new(endpoint).and_then(|http| http.get(models_url)) {
+            Ok(json) => json,
+            Err(err) => {
+                let unreachable = err
+                    .downcast_ref::<reqwest::Error>()
+                    .is_some_and(|err| err.is_connect() || err.is_timeout());
+                if unreachable {
+                    self.fail("base URL", format!("{models_url} can't be reached: {err}"));
+                } else {
+                    self.pass("base URL", format!("{models_url} is reachable"));
+                    self.fail("API key", format!("unexpected response: {err:#}"));
+                }
+                return;
+            }
+        };
+
+        self.pass("base URL", format!("{models_url} is reachable"));
+        match json.get("data").and_then(Value::as_array) {
+            Some(models) => self.pass(
+                "API key",
+                format!("accepted ({} models listed)", models.len()),
+            ),
+            None => {
+                let message = json
+                    .pointer("/error/message")
+                    .and_then(Value::as_str)
+                    .map_or_else(|| json.to_string(), str::to_owned);
+                self.fail("API key", format!("rejected: {message}"));
+            }
+        }
+    }
+
+    /// Whether every check passed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.status == Status::Pass)
+    }
+
+    /// Print a line for each check.
+    pub fn print(&self) {
+        for check in &self.checks {
+            let status = match check.status {
+                Status::Pass => "pass".green(),
+                Status::Fail => "FAIL".red().bold(),
+            };
+            println!("[{status}] {}: {}", check.name.bold(), check.detail);
+        }
+    }
+}
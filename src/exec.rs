@@ -0,0 +1,453 @@
+//! Execution of generated commands.
+
+use crate::platform::{command_flag, shell};
+use std::{
+    collections::VecDeque,
+    io::{self, ErrorKind, Read, Write},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// How often to check whether a command with a timeout has finished.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Set by [`disable`], after which no command can be run.
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Prevent any command from being run for the rest of the process, whatever else allows it.
+pub fn disable() {
+    DISABLED.store(true, Ordering::SeqCst);
+}
+
+/// Error if running commands has been disabled.
+pub(crate) fn ensure_enabled() -> io::Result<()> {
+    if DISABLED.load(Ordering::SeqCst) {
+        return Err(io::Error::other("running commands is disabled"));
+    }
+    Ok(())
+}
+
+/// The process ID of the command that's running, or 0 if none is, for [`interrupt`].
+static RUNNING: AtomicU32 = AtomicU32::new(0);
+/// Whether the running command is in its own process group, where Ctrl+C in the terminal doesn't
+/// reach it.
+static OWN_GROUP: AtomicBool = AtomicBool::new(false);
+
+/// Interrupt the running command, if there is one, as if Ctrl+C had been pressed in it. Returns
+/// whether a command was running. This is meant to be called from a Ctrl+C handler, which would
+/// otherwise exit gptsh along with the command.
+pub fn interrupt() -> bool {
+    let pid = RUNNING.load(Ordering::SeqCst);
+    if pid == 0 {
+        return false;
+    }
+    // Commands in the terminal's process group get the interrupt from the terminal themselves
+    #[cfg(unix)]
+    if OWN_GROUP.load(Ordering::SeqCst) {
+        // SAFETY: kill has no memory safety preconditions
+        unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGINT) };
+    }
+    true
+}
+
+/// Check whether a command exited because it was interrupted with Ctrl+C.
+pub fn was_interrupted(outcome: Outcome) -> bool {
+    let Outcome::Exited(status) = outcome else {
+        return false;
+    };
+    #[cfg(unix)]
+    {
+        std::os::unix::process::ExitStatusExt::signal(&status) == Some(libc::SIGINT)
+    }
+    #[cfg(windows)]
+    {
+        // STATUS_CONTROL_C_EXIT
+        status.code() == Some(0xC000013Au32 as i32)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = status;
+        false
+    }
+}
+
+/// Marks a command as running for [`interrupt`] until it's dropped.
+struct Running;
+
+impl Running {
+    fn start(child: &Child, own_group: bool) -> Self {
+        OWN_GROUP.store(own_group, Ordering::SeqCst);
+        RUNNING.store(child.id(), Ordering::SeqCst);
+        Self
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.store(0, Ordering::SeqCst);
+    }
+}
+
+/// How a command finished.
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome {
+    Exited(ExitStatus),
+    /// The command ran for longer than the timeout and was killed.
+    TimedOut,
+}
+
+/// Create a process that runs a command in the shell.
+pub fn shell_command(command: &str) -> Command {
+    let shell = shell();
+    let mut cmd = Command::new(shell);
+    cmd.arg(command_flag(shell)).arg(command);
+    cmd
+}
+
+/// Run a command in the shell, killing it if it runs for longer than `timeout`.
+pub fn run(command: &str, timeout: Option<Duration>) -> io::Result<Outcome> {
+    run_command(shell_command(command), timeout)
+}
+
+/// Run a process made by [`shell_command`], killing it if it runs for longer than `timeout`.
+pub fn run_command(mut cmd: Command, timeout: Option<Duration>) -> io::Result<Outcome> {
+    ensure_enabled()?;
+    // Run a command with a timeout in its own process group, so anything it spawns can be killed
+    // with it
+    #[cfg(unix)]
+    if timeout.is_some() {
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+    }
+
+    wait(&mut cmd.spawn()?, timeout)
+}
+
+/// The output captured from a command by [`run_captured`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Captured {
+    /// The end of everything the command wrote to stdout and stderr, in the order it was written.
+    pub output: String,
+    /// Whether earlier output was dropped to keep within the limit.
+    pub truncated: bool,
+}
+
+/// Run a command in the shell like [`run`], showing its output as it's written while also capturing
+/// up to the last `limit` bytes of it.
+pub fn run_captured(
+    command: &str,
+    timeout: Option<Duration>,
+    limit: usize,
+) -> io::Result<(Outcome, Captured)> {
+    ensure_enabled()?;
+    let mut cmd = shell_command(command);
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    #[cfg(unix)]
+    if timeout.is_some() {
+        std::os::unix::process::CommandExt::process_group(&mut cmd, 0);
+    }
+
+    let mut child = cmd.spawn()?;
+    // Read both pipes at once, so the command can't block on a full one while the other is read
+    let tail = Arc::new(Mutex::new(Tail::new(limit)));
+    let stdout = child
+        .stdout
+        .take()
+        .map(|pipe| tee_in_background(pipe, io::stdout, &tail));
+    let stderr = child
+        .stderr
+        .take()
+        .map(|pipe| tee_in_background(pipe, io::stderr, &tail));
+    let outcome = wait(&mut child, timeout)?;
+
+    for reader in [stdout, stderr].into_iter().flatten() {
+        let _ = reader.join();
+    }
+    let tail = tail.lock().unwrap();
+    Ok((outcome, tail.captured()))
+}
+
+/// The last bytes written to a pair of pipes, up to a limit.
+struct Tail {
+    bytes: VecDeque<u8>,
+    limit: usize,
+    truncated: bool,
+}
+
+impl Tail {
+    fn new(limit: usize) -> Self {
+        Self {
+            bytes: VecDeque::new(),
+            limit,
+            truncated: false,
+        }
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        self.bytes.extend(bytes);
+        if self.bytes.len() > self.limit {
+            self.bytes.drain(..self.bytes.len() - self.limit);
+            self.truncated = true;
+        }
+    }
+
+    fn captured(&self) -> Captured {
+        let bytes: Vec<u8> = self.bytes.iter().copied().collect();
+        // Dropping earlier output can leave part of a character at the start
+        let start = if self.truncated {
+            bytes
+                .iter()
+                .position(|&b| b & 0xC0 != 0x80)
+                .unwrap_or(bytes.len())
+        } else {
+            0
+        };
+        Captured {
+            output: String::from_utf8_lossy(&bytes[start..]).into_owned(),
+            truncated: self.truncated,
+        }
+    }
+}
+
+/// Copy everything from a pipe to one of gptsh's own outputs on another thread, adding it to
+/// `tail` as well.
+fn tee_in_background<W: Write + 'static>(
+    mut pipe: impl Read + Send + 'static,
+    output: fn() -> W,
+    tail: &Arc<Mutex<Tail>>,
+) -> JoinHandle<()> {
+    let tail = Arc::clone(tail);
+    thread::spawn(move || {
+        let mut buf = [0; 8192];
+        loop {
+            let n = match pipe.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                // Whatever was read before the error is still kept
+                Err(_) => break,
+            };
+            let mut output = output();
+            let _ = output.write_all(&buf[..n]).and_then(|()| output.flush());
+            tail.lock().unwrap().extend(&buf[..n]);
+        }
+    })
+}
+
+/// Wait for a command to finish, killing it if it runs for longer than `timeout`.
+fn wait(child: &mut Child, timeout: Option<Duration>) -> io::Result<Outcome> {
+    // Commands are only put in their own process group when they have a timeout. The group is
+    // given the terminal if gptsh has it, so that the command can still read from it.
+    #[cfg(unix)]
+    let terminal = timeout.and_then(|_| Terminal::give(child));
+    #[cfg(unix)]
+    let own_group = timeout.is_some() && terminal.is_none();
+    #[cfg(not(unix))]
+    let own_group = timeout.is_some();
+    let _running = Running::start(child, own_group);
+    let Some(timeout) = timeout else {
+        return child.wait().map(Outcome::Exited);
+    };
+
+    // On Windows the command is put in a job object, so that it can be killed along with everything
+    // it started. If that fails, only the shell is killed.
+    #[cfg(windows)]
+    let job = job::Job::assign(child).ok();
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Outcome::Exited(status));
+        }
+        if Instant::now() >= deadline {
+            #[cfg(windows)]
+            if let Some(job) = &job {
+                job.terminate()?;
+            }
+            kill(child)?;
+            return Ok(Outcome::TimedOut);
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// The terminal, given to the process group of a command while it runs, so that reading from the
+/// terminal doesn't stop it with SIGTTIN. It's taken back for gptsh's own group when dropped.
+#[cfg(unix)]
+struct Terminal;
+
+#[cfg(unix)]
+impl Terminal {
+    /// Give the terminal on stdin to the process group a child leads, if gptsh's group has it.
+    fn give(child: &Child) -> Option<Self> {
+        let pid = child.id() as libc::pid_t;
+        // SAFETY: None of these have memory safety preconditions
+        unsafe {
+            if libc::isatty(libc::STDIN_FILENO) != 1
+                || libc::tcgetpgrp(libc::STDIN_FILENO) != libc::getpgrp()
+                || libc::tcsetpgrp(libc::STDIN_FILENO, pid) != 0
+            {
+                return None;
+            }
+            // The command may have read from the terminal before it was given the terminal, and
+            // been stopped for it
+            libc::kill(-pid, libc::SIGCONT);
+        }
+        Some(Self)
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // SAFETY: None of these have memory safety preconditions. gptsh's group is in the
+        // background until the call, where changing the foreground group raises SIGTTOU, so it's
+        // ignored meanwhile.
+        unsafe {
+            let previous = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+            libc::tcsetpgrp(libc::STDIN_FILENO, libc::getpgrp());
+            libc::signal(libc::SIGTTOU, previous);
+        }
+    }
+}
+
+/// Kill a command started with a timeout, along with its process group on Unix. On Windows its job
+/// object is terminated first by [`wait`].
+fn kill(child: &mut Child) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        // SAFETY: kill has no memory safety preconditions. The child is its own process group
+        // leader, so its pid is also the process group id.
+        if unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    // Elsewhere only the shell itself is killed. Killing a shell that has already exited is fine.
+    #[cfg(not(unix))]
+    child.kill()?;
+
+    child.wait()?;
+    Ok(())
+}
+
+/// Windows job objects, which group a process with every process it starts, so that they can be
+/// killed together like a process group on Unix.
+#[cfg(windows)]
+mod job {
+    use std::{
+        ffi::c_void,
+        io,
+        os::windows::io::{AsRawHandle, RawHandle},
+        process::Child,
+        ptr,
+    };
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn CreateJobObjectW(attributes: *mut c_void, name: *const u16) -> RawHandle;
+        fn AssignProcessToJobObject(job: RawHandle, process: RawHandle) -> i32;
+        fn TerminateJobObject(job: RawHandle, exit_code: u32) -> i32;
+        fn CloseHandle(handle: RawHandle) -> i32;
+    }
+
+    /// A job object, which is closed when it's dropped.
+    pub(super) struct Job(RawHandle);
+
+    impl Job {
+        /// Create a job with a child in it. Processes the child starts from then on are in the
+        /// job too, but any it started before being assigned aren't, since the child isn't
+        /// started suspended.
+        pub(super) fn assign(child: &Child) -> io::Result<Self> {
+            // SAFETY: Both arguments may be null, for the default security and an unnamed job
+            let handle = unsafe { CreateJobObjectW(ptr::null_mut(), ptr::null()) };
+            if handle.is_null() {
+                return Err(io::Error::last_os_error());
+            }
+            let job = Self(handle);
+            // SAFETY: The job handle is open until the job is dropped, and the process handle
+            // until the child is
+            if unsafe { AssignProcessToJobObject(job.0, child.as_raw_handle()) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(job)
+        }
+
+        /// Kill every process in the job.
+        pub(super) fn terminate(&self) -> io::Result<()> {
+            // SAFETY: The handle is open until the job is dropped
+            if unsafe { TerminateJobObject(self.0, 1) } == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+    }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            // SAFETY: The handle is open, and isn't used again
+            unsafe { CloseHandle(self.0) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn captures_output_that_isnt_utf8() {
+        let mut tail = Tail::new(1024);
+        tail.extend(b"caf\xc3\xa9 \xff\xfe done");
+        assert_eq!(tail.captured().output, "café \u{fffd}\u{fffd} done");
+    }
+
+    #[test]
+    fn keeps_the_end_of_long_output() {
+        let mut tail = Tail::new(8);
+        tail.extend(b"1234");
+        tail.extend(b"5678");
+        assert_eq!(
+            tail.captured(),
+            Captured {
+                output: "12345678".to_owned(),
+                truncated: false,
+            }
+        );
+        tail.extend(b"9\n");
+        assert_eq!(
+            tail.captured(),
+            Captured {
+                output: "3456789\n".to_owned(),
+                truncated: true,
+            }
+        );
+    }
+
+    #[test]
+    fn drops_characters_cut_by_the_limit() {
+        let mut tail = Tail::new(4);
+        tail.extend("aé€".as_bytes());
+        // Only the last byte of `é` is within the limit
+        assert_eq!(tail.captured().output, "€");
+        assert!(tail.captured().truncated);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn runs_commands_with_output_that_isnt_utf8() {
+        let (outcome, captured) = run_captured("printf 'a\\377b'; printf '\\200' >&2", None, 1024)
+            .expect("the command should run");
+        assert!(matches!(outcome, Outcome::Exited(status) if status.success()));
+        assert!(!captured.truncated);
+        // stdout and stderr are read at the same time, so they can be captured in either order
+        let mut chars: Vec<char> = captured.output.chars().collect();
+        chars.sort_unstable();
+        assert_eq!(chars, ['a', 'b', '\u{fffd}', '\u{fffd}']);
+    }
+}
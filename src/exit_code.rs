@@ -0,0 +1,78 @@
+//! The exit codes gptsh finishes with, so that scripts can tell from `$?` why it failed:
+//!
+//! | Code      | Meaning                                                                       |
+//! |-----------|-------------------------------------------------------------------------------|
+//! | 0         | Success: the answer was shown, or the command was run and exited with 0       |
+//! | 1         | Any other error, including invalid arguments                                  |
+//! | 2         | The API key is missing or was rejected                                        |
+//! | 3         | The API couldn't be reached, or didn't reply in time                          |
+//! | 4         | The API returned an error, or a reply that couldn't be read                   |
+//! | 5         | The command wasn't run: it was declined, not allowed, or cut off              |
+//! | 124       | The command timed out and was killed                                          |
+//! | 128 + N   | The command was killed by signal N                                            |
+//! | Other     | The command's own exit code                                                   |
+//!
+//! A command that exits with 1–5 itself can't be told apart from gptsh failing, so scripts that
+//! need to should check the command's exit code in gptsh's `--json` output instead. Only a single
+//! prompt sets these; the REPL always exits with 0 unless it fails to start.
+
+use crate::{exec::Outcome, ApiError, MalformedResponse, MissingApiKey, Unauthorized};
+use std::fmt;
+
+pub const SUCCESS: u8 = 0;
+pub const FAILURE: u8 = 1;
+pub const API_KEY: u8 = 2;
+pub const NETWORK: u8 = 3;
+pub const API_ERROR: u8 = 4;
+pub const DECLINED: u8 = 5;
+/// The same as `timeout(1)` uses.
+pub const TIMED_OUT: u8 = 124;
+
+/// An error that only sets the exit code, for failures that have already been reported.
+#[derive(Debug)]
+pub struct Exit(pub u8);
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "exit code {}", self.0)
+    }
+}
+
+impl std::error::Error for Exit {}
+
+/// The exit code for an error.
+pub fn for_error(err: &anyhow::Error) -> u8 {
+    for cause in err.chain() {
+        if let Some(Exit(code)) = cause.downcast_ref() {
+            return *code;
+        }
+        if cause.is::<MissingApiKey>() || cause.is::<Unauthorized>() {
+            return API_KEY;
+        }
+        if cause.is::<ApiError>() || cause.is::<MalformedResponse>() {
+            return API_ERROR;
+        }
+        if let Some(err) = cause.downcast_ref::<reqwest::Error>() {
+            // A reply that isn't JSON came from the API, so it's the API's error
+            return if err.is_decode() { API_ERROR } else { NETWORK };
+        }
+    }
+    FAILURE
+}
+
+/// The exit code for a command that was run.
+pub fn for_outcome(outcome: Outcome) -> u8 {
+    let status = match outcome {
+        Outcome::Exited(status) => status,
+        Outcome::TimedOut => return TIMED_OUT,
+    };
+    if let Some(code) = status.code() {
+        // Codes that don't fit only happen on Windows, where they're usually NTSTATUS failures
+        return u8::try_from(code).unwrap_or(FAILURE);
+    }
+    #[cfg(unix)]
+    if let Some(signal) = std::os::unix::process::ExitStatusExt::signal(&status) {
+        return 128u8.saturating_add(signal as u8);
+    }
+    FAILURE
+}
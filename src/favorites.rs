@@ -0,0 +1,140 @@
+//! Saved commands, for `gptsh favorites`, kept with a description and tags so they can be found
+//! again later.
+//!
+//! Favorites are stored in `favorites.json` in the data directory, as an object keyed by name:
+//!
+//! ```json
+//! {
+//!     "disk-usage": {
+//!         "command": "du -sh * | sort -h",
+//!         "description": "Sizes of everything in the current directory",
+//!         "tags": ["files", "disk"]
+//!     }
+//! }
+//! ```
+
+use crate::config::{data_dir, get_str};
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Map, Value};
+use std::{collections::BTreeMap, fs, io::ErrorKind, path::PathBuf};
+
+/// A saved command.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Favorite {
+    pub command: String,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+}
+
+impl Favorite {
+    fn from_json(json: &Value) -> Result<Self> {
+        if !json.is_object() {
+            bail!("favorite must be an object");
+        }
+        let tags = match json.get("tags") {
+            None | Some(Value::Null) => Vec::new(),
+            Some(Value::Array(tags)) => tags
+                .iter()
+                .map(|tag| tag.as_str().map(str::to_owned))
+                .collect::<Option<_>>()
+                .context("`tags` must be an array of strings")?,
+            Some(_) => bail!("`tags` must be an array of strings"),
+        };
+        Ok(Self {
+            command: get_str(json, "command")?.context("favorite has no `command`")?,
+            description: get_str(json, "description")?,
+            tags,
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        let mut json = json!({"command": self.command});
+        if let Some(description) = &self.description {
+            json["description"] = json!(description);
+        }
+        if !self.tags.is_empty() {
+            json["tags"] = json!(self.tags);
+        }
+        json
+    }
+
+    /// Whether the favorite has a tag, ignoring case.
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+}
+
+/// The saved favorites, sorted by name.
+#[derive(Debug, Clone, Default)]
+pub struct Favorites {
+    pub favorites: BTreeMap<String, Favorite>,
+}
+
+impl Favorites {
+    /// Get the path of the favorites file.
+    pub fn path() -> Option<PathBuf> {
+        data_dir().map(|dir| dir.join("favorites.json"))
+    }
+
+    /// Load the favorites, or none if nothing has been saved yet.
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to read {}", path.display()));
+            }
+        };
+        let json: Value = serde_json::from_str(&text)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        let Some(object) = json.as_object() else {
+            bail!("{} must contain an object", path.display());
+        };
+        let mut favorites = BTreeMap::new();
+        for (name, value) in object {
+            let favorite = Favorite::from_json(value)
+                .with_context(|| format!("invalid favorite `{name}` in {}", path.display()))?;
+            favorites.insert(name.clone(), favorite);
+        }
+        Ok(Self { favorites })
+    }
+
+    /// Write the favorites back to the file.
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path().context("couldn't find a data directory for the favorites")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let json: Map<String, Value> = self
+            .favorites
+            .iter()
+            .map(|(name, favorite)| (name.clone(), favorite.to_json()))
+            .collect();
+        fs::write(&path, format!("{:#}\n", Value::Object(json)))
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// The favorites with a tag, or all of them if `tag` is `None`.
+    pub fn tagged<'a>(
+        &'a self,
+        tag: Option<&'a str>,
+    ) -> impl Iterator<Item = (&'a String, &'a Favorite)> {
+        self.favorites
+            .iter()
+            .filter(move |(_, favorite)| tag.is_none_or(|tag| favorite.has_tag(tag)))
+    }
+}
+
+/// Split a comma-separated list of tags, as entered when editing a favorite.
+pub fn parse_tags(text: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in text.split(',').map(str::trim).filter(|tag| !tag.is_empty()) {
+        if !tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+            tags.push(tag.to_owned());
+        }
+    }
+    tags
+}
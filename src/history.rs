@@ -0,0 +1,215 @@
+//! Persistence of the REPL conversation history, so that it can be continued in a later session.
+//!
+//! The history is stored either as a single JSON array of messages, which is rewritten after each
+//! turn, or as JSONL with one message per line, which is only ever appended to. The system message
+//! isn't stored, since it's regenerated for each session.
+
+use crate::message::{ChatMessage, Role};
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// How the history file is serialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryFormat {
+    /// A single JSON array.
+    Json,
+    /// One JSON message per line.
+    Jsonl,
+}
+
+impl HistoryFormat {
+    pub fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "json" => Self::Json,
+            "jsonl" => Self::Jsonl,
+            _ => bail!("unknown history format `{s}` (expected json or jsonl)"),
+        })
+    }
+
+    /// Guess the format of a file from its extension.
+    fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "json" => Some(Self::Json),
+            "jsonl" | "ndjson" => Some(Self::Jsonl),
+            _ => None,
+        }
+    }
+
+    /// Guess the format of a file from its contents.
+    fn from_contents(text: &str) -> Self {
+        if text.trim_start().starts_with('[') {
+            Self::Json
+        } else {
+            Self::Jsonl
+        }
+    }
+}
+
+/// A file the conversation history is saved to.
+pub struct History {
+    path: PathBuf,
+    format: HistoryFormat,
+    /// Number of messages already in the file, which don't need to be appended again.
+    saved: usize,
+}
+
+impl History {
+    /// Open a history file, returning it along with the messages already saved in it. If `format`
+    /// isn't given, it's detected from the file extension, then from the contents. Any system
+    /// messages in the file are left out, since the current one replaces them.
+    pub fn open(path: PathBuf, format: Option<HistoryFormat>) -> Result<(Self, Vec<ChatMessage>)> {
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("failed to read history file {}", path.display()))
+            }
+        };
+
+        let format = format
+            .or_else(|| HistoryFormat::from_extension(&path))
+            .unwrap_or_else(|| HistoryFormat::from_contents(&text));
+        let mut messages = parse(&text, format)
+            .with_context(|| format!("failed to parse history file {}", path.display()))?;
+        messages.retain(|message| message.role != Role::System);
+
+        let history = Self {
+            path,
+            format,
+            saved: messages.len(),
+        };
+        Ok((history, messages))
+    }
+
+    /// Save the conversation, given every message after the system message.
+    pub fn save(&mut self, messages: &[ChatMessage]) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        match self.format {
+            HistoryFormat::Json => {
+                let messages: Vec<Value> = messages.iter().map(ChatMessage::to_json).collect();
+                fs::write(&self.path, serde_json::to_string_pretty(&messages)?)?
+            }
+            HistoryFormat::Jsonl => {
+                let mut file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?;
+                for message in messages.iter().skip(self.saved) {
+                    writeln!(file, "{}", message.to_json())?;
+                }
+            }
+        }
+        self.saved = messages.len();
+
+        Ok(())
+    }
+
+    /// Remove every saved message, so that the next save starts the file over.
+    pub fn clear(&mut self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Ok(()) => {}
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        self.saved = 0;
+        Ok(())
+    }
+}
+
+/// Get the messages to send for a conversation when only the last `turns` turns are kept: the
+/// system message, then everything from the `turns`th-last user message on.
+pub fn window(messages: &[ChatMessage], turns: usize) -> Vec<ChatMessage> {
+    let Some((system, rest)) = messages.split_first() else {
+        return Vec::new();
+    };
+    let start = rest
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, message)| message.role == Role::User)
+        .nth(turns.saturating_sub(1))
+        .map_or(0, |(i, _)| i);
+
+    let mut window = Vec::with_capacity(rest.len() - start + 1);
+    window.push(system.clone());
+    window.extend_from_slice(&rest[start..]);
+    window
+}
+
+/// Parse the messages in a history file.
+fn parse(text: &str, format: HistoryFormat) -> Result<Vec<ChatMessage>> {
+    if text.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let messages: Vec<Value> = match format {
+        HistoryFormat::Json => serde_json::from_str(text)?,
+        HistoryFormat::Jsonl => text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?,
+    };
+    messages.iter().map(ChatMessage::from_json).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation() -> Vec<ChatMessage> {
+        vec![
+            ChatMessage::user("list files"),
+            ChatMessage::assistant("[shell] ls"),
+        ]
+    }
+
+    #[test]
+    fn leaves_out_saved_system_messages() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let mut saved = vec![ChatMessage::system("old instructions")];
+        saved.extend(conversation());
+        let lines: String = saved.iter().map(|m| format!("{}\n", m.to_json())).collect();
+        fs::write(&path, lines).unwrap();
+
+        let (mut history, messages) = History::open(path.clone(), None).unwrap();
+        assert_eq!(messages, conversation());
+
+        // New messages are still appended after the ones in the file
+        let mut next = messages;
+        next.push(ChatMessage::user("print the date"));
+        history.save(&next).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().count(), 4);
+        assert!(text.ends_with(&format!("{}\n", next[2].to_json())));
+    }
+
+    #[test]
+    fn clear_starts_the_file_over() {
+        for format in [HistoryFormat::Json, HistoryFormat::Jsonl] {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("history");
+            let (mut history, _) = History::open(path.clone(), Some(format)).unwrap();
+            history.save(&conversation()).unwrap();
+
+            history.clear().unwrap();
+            let (_, saved) = History::open(path.clone(), Some(format)).unwrap();
+            assert!(saved.is_empty());
+
+            let next = vec![ChatMessage::user("print the date")];
+            history.save(&next).unwrap();
+            let (_, saved) = History::open(path, Some(format)).unwrap();
+            assert_eq!(saved, next);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! User-defined hook scripts that run around generated commands, for logging or policy checks.
+//!
+//! Hooks are run in the shell with the generated command on stdin and in `$GPTSH_COMMAND`:
+//!
+//! - `pre_command` runs before a confirmed command. If it exits with a non-zero status, the
+//!   command isn't run.
+//! - `post_command` runs after a command finishes, with its exit code in `$GPTSH_EXIT_CODE` (empty
+//!   if it was killed by a signal) and `$GPTSH_TIMED_OUT` set to `1` if it was killed for running
+//!   longer than the timeout.
+
+use crate::{
+    config::get_str,
+    exec::{shell_command, Outcome},
+};
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::{
+    io::Write,
+    process::{ExitStatus, Stdio},
+};
+
+/// The configured hook scripts.
+#[derive(Default)]
+pub struct Hooks {
+    pub pre_command: Option<String>,
+    pub post_command: Option<String>,
+}
+
+impl Hooks {
+    /// Parse the hooks from the `hooks` object in the config file.
+    pub fn from_json(json: &Value) -> Result<Self> {
+        if !json.is_object() {
+            bail!("`hooks` must be an object");
+        }
+        Ok(Self {
+            pre_command: get_str(json, "pre_command")?,
+            post_command: get_str(json, "post_command")?,
+        })
+    }
+
+    /// Run the `pre_command` hook, returning whether the command is allowed to run.
+    pub fn pre_command(&self, command: &str) -> Result<bool> {
+        match &self.pre_command {
+            Some(hook) => Ok(run_hook(hook, command, &[])
+                .context("failed to run the pre_command hook")?
+                .success()),
+            None => Ok(true),
+        }
+    }
+
+    /// Run the `post_command` hook with the outcome of the command.
+    pub fn post_command(&self, command: &str, outcome: Outcome) -> Result<()> {
+        let Some(hook) = &self.post_command else {
+            return Ok(());
+        };
+
+        let (exit_code, timed_out) = match outcome {
+            Outcome::Exited(status) => (status.code().map(|c| c.to_string()), false),
+            Outcome::TimedOut => (None, true),
+        };
+        let vars = [
+            ("GPTSH_EXIT_CODE", exit_code.unwrap_or_default()),
+            (
+                "GPTSH_TIMED_OUT",
+                if timed_out { "1" } else { "0" }.to_owned(),
+            ),
+        ];
+        run_hook(hook, command, &vars).context("failed to run the post_command hook")?;
+        Ok(())
+    }
+}
+
+/// Run a hook script with the command on stdin and the given extra environment variables.
+fn run_hook(hook: &str, command: &str, vars: &[(&str, String)]) -> Result<ExitStatus> {
+    let mut child = shell_command(hook)
+        .env("GPTSH_COMMAND", command)
+        .envs(vars.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::piped())
+        .spawn()?;
+
+    // The hook may exit without reading stdin, which isn't an error
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = writeln!(stdin, "{command}");
+    }
+    Ok(child.wait()?)
+}
@@ -0,0 +1,119 @@
+//! Shell integration, printed by `gptsh init <shell>`: a widget bound to Ctrl-G that replaces the
+//! command line with the command generated for it, so it can be edited before running it.
+//!
+//! The widgets call `gptsh --print-only`, which prints only the generated command.
+
+/// The shells that integration scripts are available for.
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+const BASH: &str = r#"# gptsh integration for bash. Add this to ~/.bashrc:
+#   eval "$(gptsh init bash)"
+_gptsh_widget() {
+    [ -n "$READLINE_LINE" ] || return
+    local command
+    command=$(gptsh --print-only --shell bash -- "$READLINE_LINE") || return
+    if [ -n "$command" ]; then
+        READLINE_LINE=$command
+        READLINE_POINT=${#READLINE_LINE}
+    fi
+}
+bind -x '"\C-g": _gptsh_widget'
+"#;
+
+const ZSH: &str = r#"# gptsh integration for zsh. Add this to ~/.zshrc:
+#   eval "$(gptsh init zsh)"
+_gptsh_widget() {
+    [[ -n $BUFFER ]] || return
+    local command
+    command=$(gptsh --print-only --shell zsh -- "$BUFFER") || return
+    if [[ -n $command ]]; then
+        BUFFER=$command
+        CURSOR=${#BUFFER}
+    fi
+    zle reset-prompt
+}
+zle -N _gptsh_widget
+bindkey '^G' _gptsh_widget
+"#;
+
+const FISH: &str = r#"# gptsh integration for fish. Add this to ~/.config/fish/config.fish:
+#   gptsh init fish | source
+function _gptsh_widget
+    set -l line (commandline | string collect)
+    test -n "$line"; or return
+    set -l command (gptsh --print-only --shell fish -- "$line" | string collect); or return
+    if test -n "$command"
+        commandline --replace -- $command
+    end
+    commandline -f repaint
+end
+bind \cg _gptsh_widget
+"#;
+
+/// Get the integration script for a shell, if there is one.
+pub fn script(shell: &str) -> Option<&'static str> {
+    match shell {
+        "bash" => Some(BASH),
+        "zsh" => Some(ZSH),
+        "fish" => Some(FISH),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{ErrorKind, Write},
+        process::{Command, Stdio},
+    };
+
+    #[test]
+    fn has_a_script_for_each_shell() {
+        for shell in SHELLS {
+            let script = script(shell).unwrap();
+            assert!(script.contains("gptsh --print-only"), "{shell}");
+            assert!(script.contains(&format!("--shell {shell} --")), "{shell}");
+            assert!(script.contains("_gptsh_widget"), "{shell}");
+        }
+        assert_eq!(script("cmd"), None);
+        assert_eq!(script(""), None);
+    }
+
+    /// Check a script's syntax with `shell -n`. Returns false if the shell isn't installed.
+    fn check_syntax(shell: &str, script: &str) -> bool {
+        let child = Command::new(shell)
+            .arg("-n")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(err) if err.kind() == ErrorKind::NotFound => return false,
+            Err(err) => panic!("failed to run {shell}: {err}"),
+        };
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(script.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(
+            output.status.success(),
+            "{shell} -n failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        true
+    }
+
+    #[test]
+    fn scripts_are_valid_syntax() {
+        for shell in SHELLS {
+            if !check_syntax(shell, script(shell).unwrap()) {
+                eprintln!("{shell} isn't installed, so its script wasn't checked");
+            }
+        }
+    }
+}
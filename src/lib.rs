@@ -0,0 +1,599 @@
+//! The core of gptsh: translating natural language into shell commands using an OpenAI-compatible
+//! Chat API. This can be used to embed gptsh's behavior in other tools:
+//!
+//! ```no_run
+//! use gptsh::{Endpoint, Gptsh, PromptRules, Response};
+//!
+//! let gptsh = Gptsh::new(&Endpoint::openai("sk-..."), "gpt-3.5-turbo", PromptRules::default())?;
+//! match gptsh.ask("list all files in this directory")? {
+//!     Response::Command(command) => println!("would run: {command}"),
+//!     Response::Answer(answer) => println!("{answer}"),
+//! }
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+
+pub mod allowlist;
+pub mod attachments;
+pub mod audit;
+pub mod base64;
+pub mod batch;
+pub mod completion;
+pub mod config;
+pub mod confirm;
+pub mod danger;
+pub mod doctor;
+pub mod exec;
+pub mod exit_code;
+pub mod favorites;
+pub mod history;
+pub mod hooks;
+pub mod integration;
+pub mod lint;
+pub mod locale;
+pub mod message;
+pub mod models;
+pub mod platform;
+pub mod preset;
+pub mod preview;
+pub mod pricing;
+pub mod progress;
+pub mod prompt;
+pub mod quoting;
+pub mod repl;
+pub mod response;
+mod responses;
+pub mod schema;
+pub mod secrets;
+pub mod session_log;
+pub mod sessions;
+pub mod temperature;
+pub mod template;
+pub mod tokens;
+pub mod tools;
+mod transport;
+pub mod wrap;
+
+pub use completion::{Completion, Usage};
+pub use message::{ChatMessage, Role};
+pub use platform::shell;
+pub use preset::{Api, AuthScheme};
+pub use prompt::{
+    effect_message, explain_message, plan_message, system_message, PromptRules, EXTRACTION_MESSAGE,
+};
+pub use response::{classify, Response};
+
+use anyhow::{Context, Result};
+use serde_json::{json, Map, Value};
+use std::{
+    borrow::Cow,
+    fmt,
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use transport::{Http, Recorder, Transport};
+
+/// Where to send requests to, and how to authenticate.
+pub struct Endpoint {
+    /// Full URL of the chat completions (or responses) endpoint.
+    pub url: String,
+    /// Which API the endpoint speaks.
+    pub api: Api,
+    /// Name of the header the API key is sent in.
+    pub auth_header: String,
+    pub auth_scheme: AuthScheme,
+    /// The API key, which is required unless the auth scheme is [`AuthScheme::None`].
+    pub api_key: Option<String>,
+    /// Accept any TLS certificate, even invalid or self-signed ones. This makes connections open
+    /// to interception, so it's only for testing against local servers.
+    pub insecure: bool,
+}
+
+impl Endpoint {
+    /// The OpenAI API with the given API key.
+    pub fn openai(api_key: impl Into<String>) -> Self {
+        Self {
+            url: "https://api.openai.com/v1/chat/completions".to_owned(),
+            api: Api::Chat,
+            auth_header: "Authorization".to_owned(),
+            auth_scheme: AuthScheme::Bearer,
+            api_key: Some(api_key.into()),
+            insecure: false,
+        }
+    }
+}
+
+/// An error response returned by the API.
+#[derive(Debug)]
+pub struct ApiError(pub Value);
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the API returned an error:\n{:#}", self.0)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// An error response saying that the API key is missing, invalid, or expired (HTTP 401).
+#[derive(Debug)]
+pub struct Unauthorized(pub Value);
+
+impl fmt::Display for Unauthorized {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0.pointer("/error/message").and_then(Value::as_str) {
+            Some(message) => write!(f, "the API key was rejected: {message}"),
+            None => write!(f, "the API key was rejected:\n{:#}", self.0),
+        }
+    }
+}
+
+impl std::error::Error for Unauthorized {}
+
+/// A reply from the API that isn't valid JSON, e.g. because a gateway cut it off.
+#[derive(Debug)]
+pub struct MalformedResponse {
+    /// The HTTP status the reply came with.
+    pub status: u16,
+    /// The start of the reply, for telling what it was.
+    pub snippet: String,
+    pub error: serde_json::Error,
+}
+
+/// How much of a malformed reply to show.
+const MALFORMED_SNIPPET_CHARS: usize = 200;
+
+impl MalformedResponse {
+    pub(crate) fn new(status: u16, text: &str, error: serde_json::Error) -> Self {
+        let mut snippet: String = text.trim().chars().take(MALFORMED_SNIPPET_CHARS).collect();
+        if snippet.len() < text.trim().len() {
+            snippet.push('…');
+        }
+        Self {
+            status,
+            snippet,
+            error,
+        }
+    }
+
+    /// Whether the reply came with a success status, so that it was probably cut off on the way,
+    /// rather than being an error page from a proxy or gateway.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+impl fmt::Display for MalformedResponse {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_success() {
+            write!(f, "the API's reply isn't valid JSON:\n{}", self.snippet)
+        } else {
+            write!(
+                f,
+                "the API returned HTTP {} with a reply that isn't JSON:\n{}",
+                self.status, self.snippet
+            )
+        }
+    }
+}
+
+impl std::error::Error for MalformedResponse {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+/// No API key was supplied, and the environment variable it's read from isn't set.
+#[derive(Debug)]
+pub struct MissingApiKey(pub String);
+
+impl fmt::Display for MissingApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "an API key was not found in the {} environment variable and was not supplied as an argument",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for MissingApiKey {}
+
+/// The most tokens a prediction of a command's effect can take, which is plenty for one line.
+const EFFECT_MAX_TOKENS: u64 = 100;
+
+/// A client for translating prompts into answers or shell commands.
+pub struct Gptsh {
+    transport: Transport,
+    recorder: Option<Recorder>,
+    /// Behind a mutex so that it can be replaced mid-session.
+    api_key: Mutex<Option<String>>,
+    api: Api,
+    model: String,
+    /// Behind a mutex so that it can be changed mid-session.
+    temperature: Mutex<Option<f64>>,
+    max_tokens: Option<u64>,
+    extra_body: Map<String, Value>,
+    rules: PromptRules,
+    system_message: String,
+    /// Prompt fragments from `--system-dir`, with their placeholders not yet filled in so that
+    /// they can be rendered for other shells.
+    system_fragments: Option<String>,
+    /// Whether the fragments replace the built-in rules instead of following them.
+    replace_rules: bool,
+    /// A system message that replaces the whole generated one, for every shell.
+    custom_system_message: Option<String>,
+    /// Lines added to the end of the system message, kept to add to the messages for other shells.
+    system_append: Vec<String>,
+    send_system_message: bool,
+    /// The total usage of every request so far.
+    session_usage: Mutex<Usage>,
+    /// How long each successful request so far took, in order.
+    session_latencies: Mutex<Vec<Duration>>,
+}
+
+impl Gptsh {
+    /// Create a client that sends requests to `endpoint` using `model`.
+    pub fn new(endpoint: &Endpoint, model: impl Into<String>, rules: PromptRules) -> Result<Self> {
+        Ok(Self {
+            transport: Transport::Http(Http::new(endpoint)?),
+            recorder: None,
+            api_key: Mutex::new(endpoint.api_key.clone()),
+            api: endpoint.api,
+            model: model.into(),
+            temperature: Mutex::new(None),
+            max_tokens: None,
+            extra_body: Map::new(),
+            rules,
+            system_message: system_message(shell(), rules),
+            system_fragments: None,
+            replace_rules: false,
+            custom_system_message: None,
+            system_append: Vec::new(),
+            send_system_message: true,
+            session_usage: Mutex::default(),
+            session_latencies: Mutex::default(),
+        })
+    }
+
+    /// Record every request and response to a JSONL file, with the API key redacted.
+    pub fn record(mut self, path: &Path) -> Result<Self> {
+        self.recorder = Some(Recorder::create(path)?);
+        Ok(self)
+    }
+
+    /// Instead of sending requests, respond with the responses in a file made by
+    /// [`record`](Self::record), in order.
+    pub fn replay(mut self, path: &Path) -> Result<Self> {
+        self.transport = Transport::replay(path)?;
+        Ok(self)
+    }
+
+    /// Limit replies to this many tokens instead of the API's default.
+    pub fn max_tokens(mut self, max_tokens: u64) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    /// Add fields to every request body, overriding any that gptsh sets itself. This allows setting
+    /// provider-specific options, but fields the API doesn't expect can break requests.
+    pub fn extra_body(mut self, extra_body: Map<String, Value>) -> Self {
+        self.extra_body.extend(extra_body);
+        self
+    }
+
+    /// Sample with this temperature instead of the API's default.
+    pub fn temperature(self, temperature: f64) -> Self {
+        self.set_temperature(temperature);
+        self
+    }
+
+    /// Change the temperature of later requests.
+    pub fn set_temperature(&self, temperature: f64) {
+        *self.temperature.lock().unwrap() = Some(temperature);
+    }
+
+    /// The temperature requests are sampled with, or `None` for the API's default.
+    pub fn current_temperature(&self) -> Option<f64> {
+        *self.temperature.lock().unwrap()
+    }
+
+    /// Authenticate later requests with a different API key, e.g. after the old one expired.
+    pub fn set_api_key(&self, api_key: &str) -> Result<()> {
+        self.transport.set_api_key(api_key)?;
+        *self.api_key.lock().unwrap() = Some(api_key.to_owned());
+        Ok(())
+    }
+
+    /// Add a line to the end of the system message, after the built-in rules, so that it can extend
+    /// or reinforce them.
+    pub fn append_to_system_message(mut self, text: impl Into<String>) -> Self {
+        self.system_append.push(text.into());
+        self.system_message = self.system_message_for(shell());
+        self
+    }
+
+    /// Add prompt fragments, e.g. from [`prompt::read_fragments`], to the system message after the
+    /// built-in rules, or in place of them if `replace_rules` is set. `{shell}` and `{os}` in them
+    /// are filled in. Lines from [`append_to_system_message`](Self::append_to_system_message)
+    /// still come after them.
+    pub fn system_fragments(mut self, fragments: impl Into<String>, replace_rules: bool) -> Self {
+        self.system_fragments = Some(fragments.into());
+        self.replace_rules = replace_rules;
+        self.system_message = self.system_message_for(shell());
+        self
+    }
+
+    /// Replace the whole system message, including the built-in rules, fragments, and appended
+    /// lines, e.g. with a version of it edited by the user.
+    pub fn replace_system_message(mut self, system_message: impl Into<String>) -> Self {
+        let system_message = system_message.into();
+        self.system_message = system_message.clone();
+        self.custom_system_message = Some(system_message);
+        self
+    }
+
+    /// Build the system message for generating commands for `shell`.
+    fn system_message_for(&self, shell: &str) -> String {
+        if let Some(system_message) = &self.custom_system_message {
+            return system_message.clone();
+        }
+        let mut parts = Vec::new();
+        if !self.replace_rules {
+            parts.push(system_message(shell, self.rules));
+        }
+        if let Some(fragments) = &self.system_fragments {
+            parts.push(prompt::render_fragments(fragments, shell));
+        }
+        parts.extend(self.system_append.iter().cloned());
+        parts.join("\n")
+    }
+
+    /// Leave system messages out of the conversation, so prompts are sent alone. Without the system
+    /// message the model isn't told to mark commands with `[shell]`, so they're unlikely to be
+    /// recognized. Requests with their own instructions, like [`explain`](Self::explain), still
+    /// send them.
+    pub fn without_system_message(mut self) -> Self {
+        self.send_system_message = false;
+        self
+    }
+
+    /// The rules commands are generated with, for classifying replies.
+    pub fn rules(&self) -> PromptRules {
+        self.rules
+    }
+
+    /// The model requests are sent to.
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// The total token usage of every request this client has made, as far as the API reported it.
+    pub fn session_usage(&self) -> Usage {
+        *self.session_usage.lock().unwrap()
+    }
+
+    /// How long each request this client has made took to be answered, in order. Requests that
+    /// failed aren't included.
+    pub fn session_latencies(&self) -> Vec<Duration> {
+        self.session_latencies.lock().unwrap().clone()
+    }
+
+    /// The system message that starts every conversation.
+    pub fn system_message(&self) -> &str {
+        &self.system_message
+    }
+
+    /// Ask a single question, without any prior conversation.
+    pub fn ask(&self, prompt: &str) -> Result<Response> {
+        Ok(self.complete(prompt)?.response(self.rules))
+    }
+
+    /// Ask a single question, without any prior conversation, getting the full completion.
+    pub fn complete(&self, prompt: &str) -> Result<Completion> {
+        self.complete_with_system(&self.system_message, prompt)
+    }
+
+    /// Ask a single question, sending the system message even if it's left out of the
+    /// conversation, for follow-up requests that rely on commands being marked with `[shell]`.
+    pub fn ask_with_system_message(&self, prompt: &str) -> Result<Response> {
+        let messages = single_prompt(&self.system_message, prompt);
+        Ok(self
+            .send(self.body(&messages, self.max_tokens))?
+            .response(self.rules))
+    }
+
+    /// Ask a single question, with any command generated for `shell` instead of the current shell.
+    pub fn ask_for_shell(&self, shell: &str, prompt: &str) -> Result<Response> {
+        let system_message = self.system_message_for(shell);
+        Ok(self
+            .complete_with_system(&system_message, prompt)?
+            .response(self.rules))
+    }
+
+    /// Ask the model for a numbered plan of the steps for a task, in prose and without commands, to
+    /// approve before asking for the commands.
+    pub fn plan(&self, prompt: &str) -> Result<String> {
+        let messages = single_prompt(&plan_message(shell()), prompt);
+        let completion = self.send(self.body(&messages, self.max_tokens))?;
+        Ok(completion.content.trim().to_owned())
+    }
+
+    /// Ask the model to explain what a command does, in plain text. Nothing is run.
+    pub fn explain(&self, command: &str) -> Result<String> {
+        let messages = single_prompt(&explain_message(shell()), command);
+        let completion = self.send(self.body(&messages, self.max_tokens))?;
+        Ok(completion.content.trim().to_owned())
+    }
+
+    /// Ask the model to predict what a command would change, in one line. This is only the model's
+    /// estimate, so it should be shown as one.
+    pub fn predict_effect(&self, command: &str) -> Result<String> {
+        let messages = single_prompt(&effect_message(shell()), command);
+        // Reasoning models spend tokens on reasoning first, so limiting them could leave no reply
+        let max_tokens = (!models::is_reasoning_model(&self.model)).then_some(EFFECT_MAX_TOKENS);
+        let completion = self.send(self.body(&messages, max_tokens))?;
+        Ok(completion
+            .content
+            .lines()
+            .find(|line| !line.trim().is_empty())
+            .unwrap_or_default()
+            .trim()
+            .to_owned())
+    }
+
+    /// Ask for data matching a JSON Schema, instead of a command or answer. The reply is checked
+    /// against the schema, since not every model enforces it.
+    pub fn extract(&self, prompt: &str, schema: &Value) -> Result<Value> {
+        let mut body = self.body(&single_prompt(EXTRACTION_MESSAGE, prompt), self.max_tokens);
+        // Anything in `extra_body` still takes precedence
+        if let Some(body) = body.as_object_mut() {
+            body.entry("response_format").or_insert_with(|| {
+                json!({
+                    "type": "json_schema",
+                    "json_schema": {"name": "response", "schema": schema},
+                })
+            });
+        }
+        let completion = self.send(body)?;
+        let value: Value = serde_json::from_str(completion.content.trim())
+            .context("the reply isn't valid JSON")?;
+        schema::validate(&value, schema).context("the reply doesn't match the schema")?;
+        Ok(value)
+    }
+
+    fn complete_with_system(&self, system_message: &str, prompt: &str) -> Result<Completion> {
+        self.chat(&single_prompt(system_message, prompt))
+    }
+
+    /// Estimate how many input tokens [`complete`](Self::complete) would send for a prompt,
+    /// including the system message. See [`tokens`] for how accurate it is.
+    pub fn estimate_prompt_tokens(&self, prompt: &str) -> u64 {
+        let body = self.body(
+            &self.conversation(&single_prompt(&self.system_message, prompt)),
+            self.max_tokens,
+        );
+        let messages = body["messages"].as_array().map_or(&[][..], Vec::as_slice);
+        tokens::estimate_messages(
+            messages
+                .iter()
+                .map(|message| message["content"].as_str().unwrap_or_default()),
+        )
+    }
+
+    /// The request body that [`complete`](Self::complete) would send for a prompt, with the API
+    /// key and anything else that looks like a secret redacted.
+    pub fn request(&self, prompt: &str) -> Value {
+        let mut body = self.body(
+            &self.conversation(&single_prompt(&self.system_message, prompt)),
+            self.max_tokens,
+        );
+        if self.api == Api::Responses {
+            body = responses::request(&body);
+        }
+        secrets::redact_json(&mut body, self.api_key.lock().unwrap().as_deref());
+        body
+    }
+
+    fn body(&self, messages: &[ChatMessage], max_tokens: Option<u64>) -> Value {
+        let system_role = models::system_role(&self.model);
+        let messages: Vec<Value> = messages
+            .iter()
+            .map(|message| match message.role {
+                // Sent in whichever role the model expects instructions in
+                Role::System | Role::Developer => ChatMessage {
+                    role: system_role,
+                    ..message.clone()
+                }
+                .to_json(),
+                _ => message.to_json(),
+            })
+            .collect();
+        let mut body = json!({
+            "model": self.model,
+            "messages": messages
+        });
+        // Reasoning models reject a temperature, and take the token limit under a different name
+        let reasoning = models::is_reasoning_model(&self.model);
+        if let Some(temperature) = self.current_temperature().filter(|_| !reasoning) {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(max_tokens) = max_tokens {
+            let key = if reasoning {
+                "max_completion_tokens"
+            } else {
+                "max_tokens"
+            };
+            body[key] = json!(max_tokens);
+        }
+        if self.rules.tools {
+            body["tools"] = tools::definitions(shell());
+        }
+        // Merged last, so that they can override anything
+        for (key, value) in &self.extra_body {
+            body[key] = value.clone();
+        }
+        body
+    }
+
+    /// Send the conversation history and get the model's reply. If the API returns an error, it is
+    /// returned as an [`ApiError`], or as [`Unauthorized`] if the API key was rejected.
+    pub fn chat(&self, messages: &[ChatMessage]) -> Result<Completion> {
+        self.send(self.body(&self.conversation(messages), self.max_tokens))
+    }
+
+    /// Like [`chat`](Self::chat), but with a different token limit, e.g. to regenerate a reply
+    /// that was cut off.
+    pub fn chat_with_max_tokens(
+        &self,
+        messages: &[ChatMessage],
+        max_tokens: u64,
+    ) -> Result<Completion> {
+        self.send(self.body(&self.conversation(messages), Some(max_tokens)))
+    }
+
+    /// The messages of a conversation to send, without the system message if it's left out.
+    fn conversation<'a>(&self, messages: &'a [ChatMessage]) -> Cow<'a, [ChatMessage]> {
+        if self.send_system_message {
+            return Cow::Borrowed(messages);
+        }
+        messages
+            .iter()
+            .filter(|message| !matches!(message.role, Role::System | Role::Developer))
+            .cloned()
+            .collect()
+    }
+
+    fn send(&self, body: Value) -> Result<Completion> {
+        // Requests are always built for Chat Completions, and only translated here
+        let body = match self.api {
+            Api::Chat => body,
+            Api::Responses => responses::request(&body),
+        };
+        let start = Instant::now();
+        let resp_json = self.transport.send(&body)?;
+        let latency = start.elapsed();
+        if let Some(recorder) = &self.recorder {
+            recorder.record(&body, &resp_json, self.api_key.lock().unwrap().as_deref())?;
+        }
+        let resp_json = match self.api {
+            Api::Chat => resp_json,
+            Api::Responses => responses::response(resp_json),
+        };
+
+        let completion = Completion::from_json(resp_json, latency)?;
+        if let Some(usage) = completion.usage {
+            self.session_usage.lock().unwrap().add(usage);
+        }
+        self.session_latencies.lock().unwrap().push(latency);
+        Ok(completion)
+    }
+}
+
+/// The messages for a prompt without any prior conversation.
+fn single_prompt(system_message: &str, prompt: &str) -> [ChatMessage; 2] {
+    [
+        ChatMessage::system(system_message),
+        ChatMessage::user(prompt),
+    ]
+}
@@ -0,0 +1,100 @@
+//! Checking generated commands with [ShellCheck](https://www.shellcheck.net/) for `--lint`, to
+//! catch quoting and portability problems before they're run. The warnings are only shown; they
+//! never stop a command from being run.
+//!
+//! Only the shells ShellCheck understands are checked (`sh`, `bash`, `dash`, and `ksh`), and
+//! nothing is checked if `shellcheck` isn't installed.
+
+use anyhow::{bail, Context, Result};
+use std::{
+    fmt,
+    io::{ErrorKind, Write},
+    path::Path,
+    process::{Child, Command, Stdio},
+};
+
+/// The shells ShellCheck can check commands for.
+const SUPPORTED_SHELLS: &[&str] = &["sh", "bash", "dash", "ksh"];
+
+/// A problem ShellCheck found in a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub line: usize,
+    pub column: usize,
+    /// `error`, `warning`, `note`, or `style`.
+    pub level: String,
+    /// The description, ending with the check's code, e.g. `[SC2086]`.
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}: {}: {}",
+            self.line, self.column, self.level, self.message
+        )
+    }
+}
+
+/// Check a command in the syntax of `shell`, or return `None` if the shell isn't supported or
+/// ShellCheck isn't installed.
+pub fn lint(command: &str, shell: &str) -> Option<Result<Vec<Warning>>> {
+    let shell = Path::new(shell).file_name()?.to_str()?;
+    if !SUPPORTED_SHELLS.contains(&shell) {
+        return None;
+    }
+
+    let child = Command::new("shellcheck")
+        .args([&format!("--shell={shell}"), "--format=gcc", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let child = match child {
+        Ok(child) => child,
+        Err(err) if err.kind() == ErrorKind::NotFound => return None,
+        Err(err) => return Some(Err(err).context("failed to run shellcheck")),
+    };
+    Some(check(child, command))
+}
+
+fn check(mut child: Child, command: &str) -> Result<Vec<Warning>> {
+    // Dropping stdin closes it, so that shellcheck sees the end of the command
+    child
+        .stdin
+        .take()
+        .context("shellcheck has no stdin")?
+        .write_all(command.as_bytes())?;
+    let output = child.wait_with_output()?;
+
+    // Exit code 1 means warnings were found, and anything else besides 0 means it failed
+    match output.status.code() {
+        Some(0 | 1) => {}
+        _ => bail!(
+            "shellcheck failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(parse_line)
+        .collect())
+}
+
+/// Parse a line of ShellCheck's `gcc` output, like
+/// `-:1:6: warning: Double quote to prevent globbing and word splitting. [SC2086]`.
+fn parse_line(line: &str) -> Option<Warning> {
+    let rest = line.strip_prefix("-:")?;
+    let mut parts = rest.splitn(4, ':');
+    let line = parts.next()?.parse().ok()?;
+    let column = parts.next()?.parse().ok()?;
+    let level = parts.next()?.trim().to_owned();
+    let message = parts.next()?.trim().to_owned();
+    Some(Warning {
+        line,
+        column,
+        level,
+        message,
+    })
+}
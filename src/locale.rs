@@ -0,0 +1,314 @@
+//! Translations of the interactive interface strings. The system prompt is always in English.
+
+/// Interface strings for a single language.
+pub struct Strings {
+    /// Confirmation prompt before running a command, with `{dir}` replaced by the directory it will
+    /// run in.
+    pub run_command: &'static str,
+    /// Confirmation prompt before running a command with elevated privileges, with `{dir}` replaced
+    /// like in `run_command`.
+    pub run_command_elevated: &'static str,
+    /// Label of the compact prompt for confirming safe commands, which is followed by the keys.
+    pub run_compact: &'static str,
+    /// The word that has to be typed to confirm running a command with elevated privileges.
+    pub confirm_word: &'static str,
+    /// Startup warning shown when running with elevated privileges.
+    pub elevated_warning: &'static str,
+    /// Shown instead of running a command with elevated privileges when nobody can confirm it.
+    pub elevated_not_interactive: &'static str,
+    /// Shown on every run with --insecure.
+    pub insecure_warning: &'static str,
+    /// Shown instead of the confirmation prompt when reading prompts from a script without --yes.
+    pub not_running: &'static str,
+    /// Shown instead of the confirmation prompt with --no-exec.
+    pub execution_disabled: &'static str,
+    /// Shown before the reason a command isn't on the allowlist.
+    pub not_allowed: &'static str,
+    /// Shown instead of running a command that was cut off by the token limit.
+    pub truncated_command: &'static str,
+    /// Prompt for regenerating a command that was cut off with a higher token limit.
+    pub regenerate: &'static str,
+    /// Shown before the path of one of gptsh's own files that a command touches.
+    pub self_modifying: &'static str,
+    /// Prompt for confirming a command that touches gptsh's own files, by typing `confirm_word`.
+    pub run_self_modifying: &'static str,
+    /// Shown before the dangerous pattern a command matches.
+    pub dangerous_command: &'static str,
+    /// Shown when the pre_command hook prevents a command from running.
+    pub vetoed: &'static str,
+    /// Shown before the exit status when a running command is interrupted with Ctrl+C.
+    pub interrupted: &'static str,
+    /// Shown when a command is killed for running longer than --exec-timeout.
+    pub timed_out: &'static str,
+    /// Shown with the elapsed time while a command runs for a while.
+    pub running: &'static str,
+    /// Shown before the path of the file previewed by --preview.
+    pub preview_header: &'static str,
+    /// Shown by --preview when the command doesn't change the file.
+    pub preview_unchanged: &'static str,
+    /// Shown by --preview when the file is too large to diff.
+    pub preview_too_large: &'static str,
+    /// Shown before the error when --preview fails.
+    pub preview_failed: &'static str,
+    /// Shown before the model's estimate of a command's side effects with --preview-effect.
+    pub estimated_effect: &'static str,
+    /// Shown before the error when the side effects couldn't be estimated.
+    pub effect_failed: &'static str,
+    /// Shown before the warnings ShellCheck found in a command with --lint.
+    pub lint_warnings: &'static str,
+    /// Shown before the error when ShellCheck couldn't check a command.
+    pub lint_failed: &'static str,
+    /// Shown before the error when a command couldn't be started.
+    pub run_failed: &'static str,
+    /// Shown by --compare for shells that the model didn't generate a command for.
+    pub no_command: &'static str,
+    /// Shown above the new system message after changing it with /system.
+    pub system_updated: &'static str,
+    /// Shown before the kinds of secrets found in a REPL prompt.
+    pub secret_warning: &'static str,
+    /// Confirmation prompt before sending a REPL prompt that contains secrets.
+    pub send_anyway: &'static str,
+    /// Shown before the branch name after /branch or /checkout.
+    pub switched_branch: &'static str,
+    /// Shown when the rest of several commands aren't run because one wasn't run successfully.
+    pub remaining_commands: &'static str,
+    /// Shown above how each command finished with --continue-on-error.
+    pub step_results: &'static str,
+    /// How a command that timed out finished, in the list of results.
+    pub step_timed_out: &'static str,
+    /// How a command that wasn't run finished, in the list of results.
+    pub step_not_run: &'static str,
+    /// Shown when a command is longer than --max-command-length.
+    pub long_command: &'static str,
+    /// Option for opening a command that's too long in the editor.
+    pub open_in_editor: &'static str,
+    /// Option for not running a command.
+    pub cancel: &'static str,
+    /// Shown before the problem when a command's quoting looks broken.
+    pub malformed_command: &'static str,
+    /// Prompt for sending a command with broken quoting back to the model to be fixed.
+    pub fix_command: &'static str,
+    /// Prompt for sending a REPL prompt again after the request fails.
+    pub retry: &'static str,
+    /// Prompt for carrying out the plan the model wrote with --plan.
+    pub approve_plan: &'static str,
+    /// Shown when the model's reply is empty or only whitespace.
+    pub empty_response: &'static str,
+    /// Prompt for a new API key when the API rejects the current one mid-session. An empty answer
+    /// gives up.
+    pub new_api_key: &'static str,
+    /// Prompt for choosing the session to continue with `gptsh resume`.
+    pub pick_session: &'static str,
+    /// Shown by `gptsh resume` when no sessions have been saved.
+    pub no_sessions: &'static str,
+    /// Shown by /errors when no requests have failed.
+    pub no_errors: &'static str,
+    /// Shown by /stats before any requests have been answered.
+    pub no_requests: &'static str,
+    /// After the number of requests in /stats.
+    pub requests: &'static str,
+    /// Before the latencies in /stats.
+    pub latency: &'static str,
+    /// Shown once the REPL conversation has more than `{turns}` replies.
+    pub long_conversation: &'static str,
+    /// Shown by /clear.
+    pub conversation_cleared: &'static str,
+    /// Shown by /less before the model has replied.
+    pub no_reply: &'static str,
+    /// Shown before the number given to /show or /rerun when there's no reply with that number.
+    pub no_such_reply: &'static str,
+    /// Shown by /rerun when the reply is an answer rather than a command.
+    pub no_command_in_reply: &'static str,
+    /// Shown by /temp when the temperature is changed.
+    pub temperature_set: &'static str,
+    /// Shown by /temp when the API's default temperature is used.
+    pub default_temperature: &'static str,
+    /// Prompt for choosing a model with --pick-model.
+    pub pick_model: &'static str,
+    /// Shown by `gptsh favorites` when there are no favorites (with the tag).
+    pub no_favorites: &'static str,
+    /// Prompts for the fields of a favorite with `gptsh favorites edit`.
+    pub favorite_command: &'static str,
+    pub favorite_description: &'static str,
+    pub favorite_tags: &'static str,
+    /// Prompt for also deleting the history with `gptsh clean --all`.
+    pub delete_history: &'static str,
+    /// Shown by `gptsh clean` for each removed file, with `{path}` and `{size}` replaced.
+    pub removed_file: &'static str,
+    /// Shown by `gptsh clean` when there are no files to remove.
+    pub nothing_to_remove: &'static str,
+    /// Shown by `gptsh clean` after removing files, with `{size}` replaced by their total size.
+    pub freed_space: &'static str,
+    /// REPL input prompt.
+    pub repl_prompt: &'static str,
+    /// After the number of tokens used in the status line.
+    pub tokens: &'static str,
+    /// Shown above the error JSON when the API returns an error.
+    pub api_error: &'static str,
+}
+
+const EN: Strings = Strings {
+    run_command: "Run command in {dir}?",
+    run_command_elevated: "Run command in {dir} with elevated privileges? Type \"yes\" to confirm",
+    run_compact: "[run]",
+    confirm_word: "yes",
+    elevated_warning: "Warning: gptsh is running as root/administrator. Generated commands will run with elevated privileges, and you will have to type \"yes\" to run them.",
+    elevated_not_interactive: "Not running the command with elevated privileges, since nobody can confirm it; pass --allow-elevated along with --yes to run it anyway.",
+    insecure_warning: "Warning: TLS certificates are not verified (--insecure). Anyone on the network can read and change requests, including the API key.",
+    not_running: "Not running the command; pass --yes to run commands from a script.",
+    execution_disabled: "(execution disabled)",
+    not_allowed: "Not running the command:",
+    truncated_command: "The reply was cut off by the token limit, so the command is incomplete and won't be run.",
+    regenerate: "Regenerate it with a higher token limit?",
+    self_modifying: "Warning: this command touches gptsh's own files:",
+    run_self_modifying: "Run it anyway? Type \"yes\" to confirm",
+    dangerous_command: "Warning: this command looks dangerous, since it matches the pattern:",
+    vetoed: "The pre_command hook prevented the command from running.",
+    interrupted: "The command was interrupted:",
+    timed_out: "The command timed out and was killed.",
+    running: "running",
+    preview_header: "Changes to",
+    preview_unchanged: "(no changes)",
+    preview_too_large: "(the file is too large to show the changes)",
+    preview_failed: "Couldn't preview the changes:",
+    estimated_effect: "Estimated effect (may be wrong):",
+    effect_failed: "Couldn't estimate the effect:",
+    lint_warnings: "ShellCheck found possible problems:",
+    lint_failed: "Couldn't check the command with ShellCheck:",
+    run_failed: "Couldn't run the command:",
+    no_command: "(no command)",
+    system_updated: "The system message is now:",
+    secret_warning: "Warning: your prompt looks like it contains secrets:",
+    send_anyway: "Send it anyway?",
+    switched_branch: "Switched to branch",
+    remaining_commands: "Not running the remaining commands.",
+    step_results: "Results:",
+    step_timed_out: "timed out",
+    step_not_run: "not run",
+    long_command: "Warning: the command is too long to review here, so it won't be run without opening it first.",
+    open_in_editor: "Open it in the editor",
+    cancel: "Cancel",
+    malformed_command: "Warning: the command looks malformed:",
+    fix_command: "Ask the model to fix it?",
+    retry: "Try again?",
+    approve_plan: "Carry out this plan?",
+    empty_response: "(empty response)",
+    new_api_key: "The API key was rejected. Enter a new key (empty to cancel)",
+    pick_session: "Session to resume",
+    no_sessions: "There are no saved sessions yet. Start one with --session <NAME>.",
+    no_errors: "(no failed requests)",
+    no_requests: "(no requests yet)",
+    requests: "requests",
+    latency: "Latency",
+    long_conversation: "This conversation is over {turns} turns long, and the model may lose track of it. Use /clear to start over, or --history-window to only send the latest messages.",
+    conversation_cleared: "Conversation cleared.",
+    no_reply: "(no replies yet)",
+    no_such_reply: "There is no reply numbered",
+    no_command_in_reply: "That reply doesn't have a command to run.",
+    temperature_set: "Temperature set to",
+    default_temperature: "(the API's default temperature)",
+    pick_model: "Model",
+    no_favorites: "(no favorites)",
+    favorite_command: "Command",
+    favorite_description: "Description",
+    favorite_tags: "Tags (comma-separated)",
+    delete_history: "Delete the conversation history, saved sessions, and log of executed commands above?",
+    removed_file: "Removed {path} ({size})",
+    nothing_to_remove: "Nothing to remove",
+    freed_space: "Freed {size}",
+    repl_prompt: "?",
+    tokens: "tokens",
+    api_error: "The API returned an error:",
+};
+
+const DE: Strings = Strings {
+    run_command: "Befehl in {dir} ausführen?",
+    run_command_elevated: "Befehl in {dir} mit erhöhten Rechten ausführen? Zum Bestätigen \"ja\" eingeben",
+    run_compact: "[ausführen]",
+    confirm_word: "ja",
+    elevated_warning: "Warnung: gptsh läuft als root/Administrator. Generierte Befehle werden mit erhöhten Rechten ausgeführt, und zum Ausführen muss \"ja\" eingegeben werden.",
+    elevated_not_interactive: "Der Befehl wird nicht mit erhöhten Rechten ausgeführt, da niemand ihn bestätigen kann; zum Ausführen --allow-elevated zusammen mit --yes angeben.",
+    insecure_warning: "Warnung: TLS-Zertifikate werden nicht geprüft (--insecure). Jeder im Netzwerk kann Anfragen mitlesen und verändern, einschließlich des API-Schlüssels.",
+    not_running: "Der Befehl wird nicht ausgeführt; mit --yes werden Befehle aus einem Skript ausgeführt.",
+    execution_disabled: "(Ausführung deaktiviert)",
+    not_allowed: "Der Befehl wird nicht ausgeführt:",
+    truncated_command: "Die Antwort wurde beim Token-Limit abgeschnitten, daher ist der Befehl unvollständig und wird nicht ausgeführt.",
+    regenerate: "Mit einem höheren Token-Limit neu generieren?",
+    self_modifying: "Warnung: Dieser Befehl betrifft gptshs eigene Dateien:",
+    run_self_modifying: "Trotzdem ausführen? Zum Bestätigen \"ja\" eingeben",
+    dangerous_command: "Warnung: Dieser Befehl sieht gefährlich aus, da er auf dieses Muster passt:",
+    vetoed: "Der pre_command-Hook hat die Ausführung des Befehls verhindert.",
+    interrupted: "Der Befehl wurde unterbrochen:",
+    timed_out: "Der Befehl hat das Zeitlimit überschritten und wurde beendet.",
+    running: "läuft",
+    preview_header: "Änderungen an",
+    preview_unchanged: "(keine Änderungen)",
+    preview_too_large: "(die Datei ist zu groß, um die Änderungen anzuzeigen)",
+    preview_failed: "Die Änderungen konnten nicht angezeigt werden:",
+    estimated_effect: "Geschätzte Auswirkung (kann falsch sein):",
+    effect_failed: "Die Auswirkung konnte nicht geschätzt werden:",
+    lint_warnings: "ShellCheck hat mögliche Probleme gefunden:",
+    lint_failed: "Der Befehl konnte nicht mit ShellCheck geprüft werden:",
+    run_failed: "Der Befehl konnte nicht ausgeführt werden:",
+    no_command: "(kein Befehl)",
+    system_updated: "Die Systemnachricht lautet jetzt:",
+    secret_warning: "Warnung: Deine Eingabe scheint Geheimnisse zu enthalten:",
+    send_anyway: "Trotzdem senden?",
+    switched_branch: "Gewechselt zu Zweig",
+    remaining_commands: "Die restlichen Befehle werden nicht ausgeführt.",
+    step_results: "Ergebnisse:",
+    step_timed_out: "Zeitlimit überschritten",
+    step_not_run: "nicht ausgeführt",
+    long_command: "Warnung: Der Befehl ist zu lang, um ihn hier zu prüfen, und wird nicht ausgeführt, ohne ihn vorher zu öffnen.",
+    open_in_editor: "Im Editor öffnen",
+    cancel: "Abbrechen",
+    malformed_command: "Warnung: Der Befehl scheint fehlerhaft zu sein:",
+    fix_command: "Das Modell bitten, ihn zu korrigieren?",
+    retry: "Erneut versuchen?",
+    approve_plan: "Diesen Plan ausführen?",
+    empty_response: "(leere Antwort)",
+    new_api_key: "Der API-Schlüssel wurde abgelehnt. Neuen Schlüssel eingeben (leer zum Abbrechen)",
+    pick_session: "Fortzusetzende Sitzung",
+    no_sessions: "Es gibt noch keine gespeicherten Sitzungen. Mit --session <NAME> eine beginnen.",
+    no_errors: "(keine fehlgeschlagenen Anfragen)",
+    no_requests: "(noch keine Anfragen)",
+    requests: "Anfragen",
+    latency: "Latenz",
+    long_conversation: "Diese Unterhaltung ist über {turns} Runden lang, und das Modell könnte den Überblick verlieren. Mit /clear neu beginnen, oder mit --history-window nur die letzten Nachrichten senden.",
+    conversation_cleared: "Unterhaltung gelöscht.",
+    no_reply: "(noch keine Antworten)",
+    no_such_reply: "Es gibt keine Antwort mit der Nummer",
+    no_command_in_reply: "Diese Antwort enthält keinen Befehl zum Ausführen.",
+    temperature_set: "Temperatur gesetzt auf",
+    default_temperature: "(Standardtemperatur der API)",
+    pick_model: "Modell",
+    no_favorites: "(keine Favoriten)",
+    favorite_command: "Befehl",
+    favorite_description: "Beschreibung",
+    favorite_tags: "Tags (durch Kommas getrennt)",
+    delete_history: "Den obigen Gesprächsverlauf, die gespeicherten Sitzungen und das Protokoll der ausgeführten Befehle löschen?",
+    removed_file: "{path} entfernt ({size})",
+    nothing_to_remove: "Nichts zu entfernen",
+    freed_space: "{size} freigegeben",
+    repl_prompt: "?",
+    tokens: "Tokens",
+    api_error: "Die API hat einen Fehler zurückgegeben:",
+};
+
+/// Get the strings for a language, given as a code like `de` or a locale like `de_DE.UTF-8`.
+/// Falls back to English for unknown languages.
+pub fn strings(lang: &str) -> &'static Strings {
+    let code = lang.split(['_', '-', '.']).next().unwrap_or_default();
+    match code.to_ascii_lowercase().as_str() {
+        "de" => &DE,
+        _ => &EN,
+    }
+}
+
+/// Get the language from the environment, following the usual POSIX precedence.
+pub fn env_lang() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .into_iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|lang| !lang.is_empty())
+}
@@ -3,180 +3,2475 @@
 //! If you describe a task that can be accomplished with a shell command, it will instead generate
 //! a command for the shell/OS you are using and ask you for verification before running the command.
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 use colored::Colorize;
-use reqwest::{
-    blocking::Client,
-    header::{HeaderMap, HeaderValue},
+use gptsh::{
+    attachments, audit, batch, classify,
+    config::{cache_dir, config_path, contract_tilde, default_history_path, Config, HistoryConfig},
+    danger::OwnFiles,
+    doctor,
+    exec::{self, Captured, Outcome},
+    exit_code::{self, Exit},
+    favorites::{self, Favorite, Favorites},
+    history::{self, History, HistoryFormat},
+    integration, lint, locale, models,
+    platform::{self, is_elevated},
+    preset::Preset,
+    preview::{self, DiffLine, Preview},
+    pricing,
+    progress::Progress,
+    prompt, quoting,
+    repl::{self, Branches, ErrorLog, LengthWarning, ReplCommand, MAIN_BRANCH},
+    response::{split_commands, split_explanation, split_reason},
+    secrets::find_secrets,
+    session_log::SessionLog,
+    sessions,
+    temperature::TemperaturePresets,
+    template::Template,
+    wrap, Api, ApiError, AuthScheme, ChatMessage, Completion, Endpoint, Gptsh, MissingApiKey,
+    PromptRules, Response, Role, Unauthorized, Usage,
 };
 use serde_json::json;
-use std::process::Command;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    io::{ErrorKind, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    process::{Command, Stdio},
+    time::Duration,
+};
+
+const DEFAULT_MODEL: &str = "gpt-3.5-turbo";
+const DEFAULT_CHAT_PATH: &str = "/chat/completions";
+const DEFAULT_RESPONSES_PATH: &str = "/responses";
+/// How many tokens to assume a reply was cut off at when the API doesn't report it and no limit was
+/// set, for picking the limit to regenerate it with.
+const DEFAULT_CUT_OFF_TOKENS: u64 = 512;
+/// How many turns a REPL conversation can have before warning that it's getting long, unless the
+/// config sets `max_turns`.
+const DEFAULT_MAX_TURNS: u64 = 50;
+/// The most shells that can be compared at once, since each one is a separate request.
+const MAX_COMPARE_SHELLS: usize = 5;
 
-const URL: &str = "https://api.openai.com/v1/chat/completions";
+/// The exit codes, shown at the end of `--help` (see the `exit_code` module).
+const EXIT_CODES: &str = "\
+Exit codes:
+  0        Success
+  1        Any other error, including invalid arguments
+  2        The API key is missing or was rejected
+  3        The API couldn't be reached, or didn't reply in time
+  4        The API returned an error
+  5        The command wasn't run (declined, not allowed, or cut off)
+  124      The command timed out
+  128 + N  The command was killed by signal N
+  Other    The command's own exit code";
 
 /// Command-line arguments.
 #[derive(Parser)]
+#[command(after_long_help = EXIT_CODES)]
 struct Args {
-    /// The prompt. If no prompt is specified, enters a REPL.
+    /// The prompt. If no prompt is specified, enters a REPL. `gptsh init <bash|zsh|fish>` prints a
+    /// script that integrates gptsh with the shell instead, `gptsh doctor` checks the setup,
+    /// `gptsh history [N]` lists the last N commands that were run, `gptsh clean [--all]` removes
+    /// cached files (and with --all, the history), `gptsh favorites` lists saved commands
+    /// (see `gptsh favorites add|edit|remove` and `gptsh favorites list --tag <TAG>`), and
+    /// `gptsh resume` picks a saved --session to continue.
+    #[arg(group = "input")]
     prompt: Option<Vec<String>>,
-    /// API key, defaults to $OPENAI_API_KEY.
+    /// Use a prompt template from the config instead of a prompt.
+    #[arg(
+        short,
+        long,
+        value_name = "NAME",
+        group = "input",
+        conflicts_with = "repl"
+    )]
+    template: Option<String>,
+    /// Read the prompt from a file instead of the arguments, or from stdin if it's `-`, so that
+    /// long prompts don't need quoting. `@path` mentions in it are attached as usual.
+    #[arg(long, value_name = "PATH", group = "input", conflicts_with = "repl")]
+    prompt_file: Option<PathBuf>,
+    /// Explain what a command does instead of generating one, e.g. one found online. The command
+    /// is never run.
+    #[arg(
+        long,
+        value_name = "COMMAND",
+        group = "input",
+        conflicts_with_all = ["repl", "json", "compare", "repeat", "plan", "tools", "schema", "print_only"]
+    )]
+    explain_command: Option<String>,
+    /// Fill a placeholder in the template. A single placeholder without a value is filled from
+    /// stdin if it isn't a terminal.
+    #[arg(long = "var", value_name = "NAME=VALUE", value_parser = parse_var, requires = "template")]
+    vars: Vec<(String, String)>,
+    /// Fill the placeholder from stdin with the input encoded as base64 when it isn't text, e.g.
+    /// for binary files. Without this, input that isn't valid UTF-8 is an error.
+    #[arg(long, requires = "template")]
+    stdin_base64: bool,
+    /// API key, defaults to $OPENAI_API_KEY (or the preset's key variable).
     #[arg(short, long)]
     key: Option<String>,
-    /// Which OpenAI model to use.
-    #[arg(short, long, default_value_t = String::from("gpt-3.5-turbo"))]
-    model: String,
-}
-
-fn main() -> Result<()> {
-    let args = Args::parse();
-    let api_key = args.key.unwrap_or(std::env::var("OPENAI_API_KEY").context("an API key was not found in the OPENAI_API_KEY environment variable and was not supplied as an argument")?);
-
-    // Create HTTP client with the API key in the headers
-    let client = Client::builder()
-        .default_headers({
-            let mut headers = HeaderMap::new();
-            let mut value = HeaderValue::from_str(&format!("Bearer {api_key}"))?;
-            value.set_sensitive(true); // API key is sensitive
-            headers.insert("Authorization", value);
-            headers
-        })
-        .build()?;
-
-    // Helper function to send the request and extract the output given a JSON object containing the conversation history
-    let get_output = |messages: serde_json::Value| -> Result<Result<String, serde_json::Value>> {
-        let resp = client
-            .post(URL)
-            .json(&json!({
-                "model": args.model,
-                "messages": messages
-            }))
-            .send()?;
-
-        let resp_json: serde_json::Value = resp.json()?;
-        let output = resp_json
-            .get("choices")
-            .and_then(|v| {
-                v.get(0).and_then(|v| {
-                    v.get("message").and_then(|v| {
-                        v.get("content")
-                            .and_then(|v| v.as_str().map(|s| s.to_owned()))
-                    })
-                })
-            })
-            // Return response JSON if the server returns an error
-            .ok_or(resp_json);
-
-        Ok(output)
-    };
-
-    // Helper function to print the response, or ask the user to execute it if it's a shell command
-    let handle_output = |output: &str| -> Result<()> {
-        // Check for [shell] tag, which marks that a response is a shell command
-        if output.trim().starts_with("[shell]") {
-            // Prompt user for verification before running the command
-            let command = output.strip_prefix("[shell]").unwrap().trim();
-            println!("{}", command.green());
-
-            let confirm = dialoguer::Confirm::new()
-                .with_prompt("Run command?")
-                .interact()?;
-            if confirm {
-                // We don't care about the exit status
-                let _ = Command::new(shell()).arg(command).status();
+    /// Which model to use. Defaults to $GPTSH_MODEL, then the template's or preset's model, then
+    /// gpt-3.5-turbo.
+    #[arg(short, long)]
+    model: Option<String>,
+    /// Choose the model from the ones the API lists before starting. The list is cached for a day.
+    #[arg(long, conflicts_with_all = ["model", "replay", "json"])]
+    pick_model: bool,
+    /// Limit replies to this many tokens. Commands cut off by the limit are never run.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    max_tokens: Option<u64>,
+    /// Sample with a named temperature: precise (0.0), balanced (0.7), creative (1.2), or one from
+    /// the config's `temperature_presets`. Overrides a template's temperature.
+    #[arg(long, value_name = "PRESET")]
+    preset_temp: Option<String>,
+    /// A JSON object of fields to add to each request, e.g. '{"logit_bias": {...}}', overriding the
+    /// config's `extra_body` and any fields gptsh sets itself. Fields the API doesn't expect can
+    /// break requests.
+    #[arg(long, value_name = "JSON", value_parser = parse_extra_body)]
+    extra_body: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Read the config from this file instead of the default location (or $GPTSH_CONFIG). Unlike
+    /// the default config file, it must exist.
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Provider preset: openai, azure, ollama, openrouter, groq, or one defined in the config.
+    #[arg(long, default_value_t = String::from("openai"))]
+    preset: String,
+    /// Base URL of the API, overriding the preset's.
+    #[arg(long)]
+    base_url: Option<String>,
+    /// Path of the chat completions endpoint, appended to the base URL, overriding the preset's.
+    /// Defaults to /chat/completions, or /responses with `--api responses`.
+    #[arg(long, value_name = "PATH")]
+    chat_path: Option<String>,
+    /// Which API to use: chat (Chat Completions, which most providers support) or responses
+    /// (OpenAI's Responses API).
+    #[arg(long, value_name = "API", value_parser = Api::parse, default_value = "chat")]
+    api: Api,
+    /// Header to send the API key in, e.g. `x-api-key` or `api-key`, overriding the preset's.
+    #[arg(long, value_name = "NAME")]
+    auth_header: Option<String>,
+    /// How to send the API key: bearer (`Bearer <key>`), raw (the key alone), or none, overriding
+    /// the preset's.
+    #[arg(long, value_name = "SCHEME", value_parser = AuthScheme::parse)]
+    auth_scheme: Option<AuthScheme>,
+    /// Don't verify the API's TLS certificate, e.g. for a local gateway with a self-signed one.
+    /// Anyone on the network path can then read and change requests, including the API key.
+    #[arg(long)]
+    insecure: bool,
+    /// Don't warn or require typing "yes" to confirm commands when running as root/administrator.
+    #[arg(long = "i-know-what-im-doing")]
+    i_know_what_im_doing: bool,
+    /// Allow generated commands to span multiple lines.
+    #[arg(long)]
+    allow_multiline_commands: bool,
+    /// Allow comments in generated commands.
+    #[arg(long)]
+    allow_comments: bool,
+    /// Allow the model to explain generated commands.
+    #[arg(long)]
+    allow_explanations: bool,
+    /// Don't send the system message, only the prompts. Commands are unlikely to be recognized,
+    /// since the model isn't told to mark them with [shell].
+    #[arg(long)]
+    no_system: bool,
+    /// Add a line to the end of the system message, after the built-in rules. Can be repeated.
+    #[arg(long, value_name = "TEXT", conflicts_with = "no_system")]
+    system_append: Vec<String>,
+    /// Add every .txt and .md file in a directory to the system message, sorted by file name, after
+    /// the built-in rules and before any --system-append lines. `{shell}` and `{os}` in the files
+    /// are filled in.
+    #[arg(long, value_name = "DIR", conflicts_with = "no_system")]
+    system_dir: Option<PathBuf>,
+    /// Use the files from --system-dir in place of the built-in rules. Commands are only
+    /// recognized if the files tell the model to mark them with [shell].
+    #[arg(long, requires = "system_dir")]
+    replace_system: bool,
+    /// Open the system message in $VISUAL or $EDITOR before sending anything, and use it as saved
+    /// for this run. Closing the editor without saving keeps it as it was.
+    #[arg(long, conflicts_with = "no_system")]
+    edit_system: bool,
+    /// Have the model run commands by calling a tool, and send it their output so it can continue.
+    /// The model has to support tool calling.
+    #[arg(long, conflicts_with_all = ["compare", "json"])]
+    tools: bool,
+    /// Have the model write a numbered plan of the steps first, and only ask for the commands once
+    /// the plan is approved. The commands are confirmed one by one as usual.
+    #[arg(
+        long,
+        requires = "input",
+        conflicts_with_all = ["compare", "dry_request", "count_tokens", "json", "print_only", "repeat", "schema", "tools"]
+    )]
+    plan: bool,
+    /// Have the model give a one-line reason for each command, shown above it.
+    #[arg(long)]
+    why: bool,
+    /// Enter the REPL even if stdin isn't a terminal, reading one prompt per line.
+    #[arg(long, conflicts_with = "prompt")]
+    repl: bool,
+    /// Fail instead of entering the REPL if no prompt is given, for use in scripts.
+    #[arg(long, conflicts_with = "repl")]
+    once: bool,
+    /// Run generated commands without asking for confirmation.
+    #[arg(short, long)]
+    yes: bool,
+    /// With --yes, also run commands as root/administrator when there's nobody to type "yes" to
+    /// confirm them, as with --json, --quiet, or a scripted REPL. Without it they aren't run.
+    #[arg(long, requires = "yes")]
+    allow_elevated: bool,
+    /// Never run generated commands, even with --yes; only print them. Also enabled by setting
+    /// $GPTSH_NO_EXEC to 1.
+    #[arg(long)]
+    no_exec: bool,
+    /// Show answers (but not commands) in $PAGER, or `less -R` if it isn't set.
+    #[arg(long)]
+    pager: bool,
+    /// Show the command for each of these shells side by side, without running anything.
+    #[arg(long, value_delimiter = ',', value_name = "SHELLS", requires = "input")]
+    compare: Vec<String>,
+    /// Send the prompt N times and print each reply, numbered, without running anything, to see
+    /// how much the replies vary. Works with any temperature, but 0 gives little variety.
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "input",
+        conflicts_with_all = ["compare", "dry_request", "count_tokens", "json", "print_only", "tools"],
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    repeat: Option<u64>,
+    /// Kill generated commands that run for longer than this many seconds.
+    #[arg(long, value_name = "SECS")]
+    exec_timeout: Option<u64>,
+    /// Commands longer than this many characters are never run without opening them in the editor
+    /// first, even with --yes, since they're too long to review in the confirmation prompt.
+    #[arg(long, value_name = "N", default_value_t = 2000)]
+    max_command_length: usize,
+    /// Before confirming a command that edits a file in place (`sed -i`, `perl -pi`) or redirects
+    /// over one (`>`, `>>`), run it against a copy of the file and show the changes.
+    #[arg(long)]
+    preview: bool,
+    /// Before confirming a command, ask the model for a one-line estimate of its side effects, such
+    /// as files deleted or network requests, with a short extra request.
+    #[arg(long)]
+    preview_effect: bool,
+    /// Before confirming a command, check it with ShellCheck and show any warnings, if ShellCheck
+    /// is installed and the shell is sh, bash, dash, or ksh. Commands are still run either way.
+    #[arg(long)]
+    lint: bool,
+    /// Only send the last N turns of the REPL conversation, or the history continued with
+    /// --append-history, (plus the system message) to the model. The rest are still kept in the
+    /// history.
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    history_window: Option<u64>,
+    /// Continue the saved REPL history (`history` in the config) with a single prompt, and save the
+    /// prompt and reply to it, so that separate invocations share context.
+    #[arg(long, requires = "input", conflicts_with_all = ["compare", "dry_request"])]
+    append_history: bool,
+    /// Save the REPL conversation as a named session instead of the `history` in the config,
+    /// continuing it if it already exists. `gptsh resume` lists the sessions to pick one.
+    #[arg(long, value_name = "NAME")]
+    session: Option<String>,
+    /// Generate a command or answer for each line of this file, without running anything.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["input", "repl", "compare", "json", "meta_out", "dry_request", "tools"]
+    )]
+    batch: Option<PathBuf>,
+    /// How many --batch requests to send at once.
+    #[arg(long, value_name = "N", default_value_t = 1, requires = "batch", value_parser = clap::value_parser!(u64).range(1..))]
+    concurrency: u64,
+    /// Record every request and response to this file, for reproducing issues.
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+    /// Replay the responses recorded with --record instead of calling the API.
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+    /// Shell to generate and run commands for, e.g. `pwsh` or `cmd`. Defaults to pwsh or
+    /// powershell on Windows, zsh on macOS, and bash elsewhere.
+    #[arg(long)]
+    shell: Option<String>,
+    /// Version of the shell to tell the model about, for when it can't be detected from
+    /// `<shell> --version`.
+    #[arg(long, value_name = "VERSION")]
+    shell_version: Option<String>,
+    /// Language of the interface, e.g. `de`. Defaults to $LANG.
+    #[arg(long)]
+    lang: Option<String>,
+    /// When to color output: auto (when stdout is a terminal that supports it, and $NO_COLOR isn't
+    /// set), always, or never.
+    #[arg(long, value_name = "WHEN", default_value = "auto", value_parser = ["auto", "always", "never"])]
+    color: String,
+    /// Write the model used, token usage, latency, and what happened to any generated command to
+    /// this file as JSON.
+    #[arg(
+        long,
+        value_name = "FILE",
+        requires = "input",
+        conflicts_with = "compare"
+    )]
+    meta_out: Option<PathBuf>,
+    /// Print the request body that would be sent, with secrets redacted, instead of sending it.
+    #[arg(long, requires = "input", conflicts_with = "compare")]
+    dry_request: bool,
+    /// Print an estimate of how many tokens the prompt is, with the system message and any
+    /// attached files, and what they cost as input, instead of sending it.
+    #[arg(long, requires = "input", conflicts_with_all = ["compare", "dry_request"])]
+    count_tokens: bool,
+    /// Print a single JSON object describing the result instead of formatted output, without
+    /// colors or prompts. Commands are only run with --yes, with their output sent to stderr.
+    #[arg(
+        long,
+        requires = "input",
+        conflicts_with_all = ["compare", "dry_request", "pager"]
+    )]
+    json: bool,
+    /// Print only the generated command, without running it, for the shell integration from
+    /// `gptsh init <shell>`. Answers are printed to stderr instead.
+    #[arg(
+        long,
+        requires = "input",
+        conflicts_with_all = ["compare", "dry_request", "json", "pager", "tools"]
+    )]
+    print_only: bool,
+    /// Only print the reply (and the output of any command that's run), without warnings,
+    /// previews, or prompts, for embedding gptsh's output in other tools. Commands are only run
+    /// with --yes.
+    #[arg(
+        short,
+        long,
+        requires = "input",
+        conflicts_with_all = ["compare", "json", "print_only", "raw"]
+    )]
+    quiet: bool,
+    /// Also print each reply exactly as the model sent it to stderr, before it's handled, for
+    /// debugging how it was interpreted.
+    #[arg(long)]
+    raw: bool,
+    /// When a reply has several commands, keep running the rest after one fails or isn't run, and
+    /// list how each of them finished at the end.
+    #[arg(long)]
+    continue_on_error: bool,
+    /// Show a status line above the REPL prompt with the model, working directory, tokens used,
+    /// and cost so far. It can also be turned on and off with /status.
+    #[arg(long)]
+    status_bar: bool,
+    /// Append a plain-text copy of the session to this file as it happens: prompts, answers,
+    /// commands, and how the commands finished, without colors.
+    #[arg(long, value_name = "PATH")]
+    log_file: Option<PathBuf>,
+    /// Ask for data matching the JSON Schema in this file instead of a command, and print it as
+    /// JSON. The reply is checked against the schema. Needs a model that supports structured
+    /// outputs.
+    #[arg(
+        long,
+        value_name = "FILE",
+        value_parser = parse_schema,
+        requires = "input",
+        conflicts_with_all = ["compare", "tools", "print_only", "dry_request", "append_history"]
+    )]
+    schema: Option<serde_json::Value>,
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        // Already reported
+        Err(err) if err.is::<Exit>() => ExitCode::from(exit_code::for_error(&err)),
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            ExitCode::from(exit_code::for_error(&err))
+        }
+    }
+}
+
+fn run() -> Result<()> {
+    platform::default_sigpipe();
+
+    // `gptsh init <shell>`, `gptsh history [N]`, `gptsh clean [--all]`, `gptsh favorites ...`, and
+    // `gptsh resume` are checked for by hand, since subcommands would stop prompts from starting
+    // with those words
+    let argv: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(count) = history_count(&argv) {
+        return print_executed(count?);
+    }
+    if let Some(result) = favorites_command(&argv) {
+        return result;
+    }
+    match argv.as_slice() {
+        [clean] if clean == "clean" => return clean_files(false),
+        [clean, all] if clean == "clean" && all == "--all" => return clean_files(true),
+        _ => {}
+    }
+    if let [init, shell] = argv.as_slice() {
+        if init == "init" {
+            if let Some(script) = integration::script(shell) {
+                print!("{script}");
+                return Ok(());
+            }
+        }
+    }
+
+    // Resuming a session is the same as starting the REPL with it
+    let args = match argv.as_slice() {
+        [resume] if resume == "resume" => match pick_session()? {
+            Some(name) => Args::try_parse_from(["gptsh", "--session", &name]),
+            None => return Ok(()),
+        },
+        _ => Args::try_parse(),
+    };
+    // Invalid arguments exit with 1 instead of clap's 2, which is for a missing API key
+    let args = args.unwrap_or_else(|err| {
+        if !err.use_stderr() {
+            err.exit();
+        }
+        let _ = err.print();
+        std::process::exit(exit_code::FAILURE.into());
+    });
+    // Checked after parsing, so that the flags choosing the API apply to the checks
+    if matches!(args.prompt.as_deref(), Some([word]) if word == "doctor") {
+        return doctor(args);
+    }
+    if args.once
+        && args.prompt.is_none()
+        && args.prompt_file.is_none()
+        && args.explain_command.is_none()
+        && args.template.is_none()
+        && args.batch.is_none()
+    {
+        bail!("no prompt was given, and --once prevents entering the REPL");
+    }
+    if args.compare.len() > MAX_COMPARE_SHELLS {
+        bail!("at most {MAX_COMPARE_SHELLS} shells can be compared at once");
+    }
+
+    let mut config = Config::load(args.config.as_deref())?;
+    if let Some(name) = &args.session {
+        config.history = Some(HistoryConfig {
+            path: sessions::path(name)?,
+            format: Some(HistoryFormat::Json),
+        });
+    }
+    let preset = Preset::resolve(&args.preset, &config)?;
+    let template = match &args.template {
+        Some(name) => Some(
+            config
+                .templates
+                .remove(name)
+                .with_context(|| format!("there is no template named `{name}`"))?,
+        ),
+        None => None,
+    };
+
+    // Explicit flags take precedence over the preset
+    let base_url = args.base_url.or(preset.base_url).with_context(|| {
+        format!(
+            "the `{}` preset has no base URL; supply one with --base-url",
+            args.preset
+        )
+    })?;
+    let base_url = base_url.trim_end_matches('/');
+    // A preset's path is for Chat Completions, so it's only used with that
+    let chat_path = match args.api {
+        Api::Chat => args
+            .chat_path
+            .or(preset.chat_path)
+            .unwrap_or_else(|| DEFAULT_CHAT_PATH.to_owned()),
+        Api::Responses => args
+            .chat_path
+            .unwrap_or_else(|| DEFAULT_RESPONSES_PATH.to_owned()),
+    };
+    let separator = if chat_path.starts_with('/') { "" } else { "/" };
+    let url = format!("{base_url}{separator}{chat_path}");
+    // The environment variable allows switching models per shell without editing the config
+    let model = args
+        .model
+        .or_else(|| std::env::var("GPTSH_MODEL").ok().filter(|m| !m.is_empty()))
+        .or(template.as_ref().and_then(|t| t.model.clone()))
+        .or(preset.model)
+        .unwrap_or_else(|| DEFAULT_MODEL.to_owned());
+
+    let auth_scheme = args
+        .auth_scheme
+        .or(preset.auth_scheme)
+        .unwrap_or(AuthScheme::Bearer);
+    let api_key = match (args.key, auth_scheme) {
+        (_, AuthScheme::None) => None,
+        (Some(key), _) => Some(key),
+        // Replayed sessions don't talk to the API
+        (None, _) if args.replay.is_some() => None,
+        (None, _) => {
+            let key_env = preset.key_env.as_deref().unwrap_or("OPENAI_API_KEY");
+            Some(std::env::var(key_env).map_err(|_| MissingApiKey(key_env.to_owned()))?)
+        }
+    };
+    let endpoint = Endpoint {
+        url,
+        api: args.api,
+        auth_header: args
+            .auth_header
+            .or(preset.auth_header)
+            .unwrap_or_else(|| "Authorization".to_owned()),
+        auth_scheme,
+        api_key,
+        insecure: args.insecure,
+    };
+
+    // In auto mode, colored already checks $NO_COLOR, $CLICOLOR, and whether stdout is a terminal
+    match args.color.as_str() {
+        "always" => colored::control::set_override(true),
+        "never" => colored::control::set_override(false),
+        _ if !platform::enable_ansi() => colored::control::set_override(false),
+        _ => {}
+    }
+    if args.json {
+        colored::control::set_override(false);
+    }
+
+    let strings = locale::strings(&args.lang.or_else(locale::env_lang).unwrap_or_default());
+    if args.insecure && !args.quiet {
+        eprintln!("{}", strings.insecure_warning.red().bold());
+    }
+    let log = args.log_file.as_deref().map(SessionLog::open).transpose()?;
+    let log = |text: &str| {
+        if let Some(log) = &log {
+            log.write(text);
+        }
+    };
+
+    if let Some(shell) = &args.shell {
+        platform::set_shell(shell)?;
+    }
+    if let Some(version) = &args.shell_version {
+        platform::set_shell_version(version);
+    }
+
+    // Picked last, so that the model that would be used otherwise can be preselected
+    let model = if args.pick_model {
+        pick_model(&endpoint, &format!("{base_url}/models"), &model, strings)?
+    } else {
+        model
+    };
+
+    let rules = PromptRules {
+        multiline_commands: args.allow_multiline_commands,
+        comments: args.allow_comments,
+        explanations: args.allow_explanations,
+        reasons: args.why,
+        tools: args.tools,
+    };
+    let mut gptsh = Gptsh::new(&endpoint, model, rules)?;
+    if let Some(path) = &args.record {
+        gptsh = gptsh.record(path)?;
+    }
+    if let Some(path) = &args.replay {
+        gptsh = gptsh.replay(path)?;
+    }
+    if let Some(dir) = &args.system_dir {
+        gptsh = gptsh.system_fragments(prompt::read_fragments(dir)?, args.replace_system);
+    }
+    for text in args.system_append {
+        gptsh = gptsh.append_to_system_message(text);
+    }
+    if args.edit_system {
+        // The editor returns nothing if the file isn't saved
+        let edited = interaction(
+            dialoguer::Editor::new()
+                .extension(".md")
+                .edit(gptsh.system_message()),
+        )?
+        .flatten();
+        if let Some(edited) = edited.filter(|edited| !edited.trim().is_empty()) {
+            gptsh = gptsh.replace_system_message(edited.trim_end());
+        }
+    }
+    if args.no_system {
+        gptsh = gptsh.without_system_message();
+    }
+    // The flag's fields are added second, so they override the config's
+    gptsh = gptsh.extra_body(std::mem::take(&mut config.extra_body));
+    if let Some(extra_body) = args.extra_body {
+        gptsh = gptsh.extra_body(extra_body);
+    }
+    // The model's defaults come first, so that the template and flags override them
+    if let Some(defaults) = config.models.get(gptsh.model()) {
+        if let Some(temperature) = defaults.temperature {
+            gptsh = gptsh.temperature(temperature);
+        }
+        if let Some(max_tokens) = defaults.max_tokens {
+            gptsh = gptsh.max_tokens(max_tokens);
+        }
+    }
+    if let Some(max_tokens) = args.max_tokens {
+        gptsh = gptsh.max_tokens(max_tokens);
+    }
+    if let Some(temperature) = template.as_ref().and_then(|t| t.temperature) {
+        gptsh = gptsh.temperature(temperature);
+    }
+    if let Some(preset) = &args.preset_temp {
+        gptsh = gptsh.temperature(config.temperature_presets.preset(preset)?);
+    }
+
+    let prompt = match (&template, &args.prompt_file) {
+        (Some(template), _) => Some(render_template(template, args.vars, args.stdin_base64)?),
+        (None, Some(path)) => Some(read_prompt_file(path)?),
+        (None, None) => args.prompt.map(|prompt| prompt.join(" ")),
+    };
+    let prompt = prompt
+        .map(|prompt| attachments::attach(&prompt))
+        .transpose()?;
+
+    // Commands run with elevated privileges can do much more damage, so be extra careful
+    let elevated = !args.i_know_what_im_doing && is_elevated();
+    if elevated && !args.quiet {
+        eprintln!("{}", strings.elevated_warning.red().bold());
+    }
+
+    // Paging only makes sense when someone's looking at the output
+    let pager = args.pager && std::io::stdout().is_terminal();
+    // Likewise wrapping, so that piped output is left as it is
+    let width = wrap::width();
+
+    // When driving the REPL from a script there's nobody to confirm commands
+    let scripted = args.repl && !std::io::stdin().is_terminal();
+
+    if let Some(command) = &args.explain_command {
+        return explain_command(&gptsh, command, width, strings);
+    }
+
+    if let Some(path) = &args.batch {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        let prompts: Vec<String> = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_owned)
+            .collect();
+        let results = batch::ask_all(&gptsh, &prompts, args.concurrency as usize);
+        print_batch_report(&prompts, results, strings);
+        return Ok(());
+    }
+
+    let timeout = args.exec_timeout.map(Duration::from_secs);
+    let own_files = OwnFiles::find(
+        config
+            .history
+            .as_ref()
+            .map(|history| history.path.as_path()),
+    );
+
+    // Disabled in the exec module too, so nothing can run even if some path skips the check below
+    let no_exec = args.no_exec || std::env::var_os("GPTSH_NO_EXEC").is_some_and(|v| v == "1");
+    if no_exec {
+        exec::disable();
+    }
+
+    // Refuse to run anything with --no-exec. Otherwise, show a preview of a command if enabled,
+    // then ask the user to confirm it and check the pre_command hook. Returns whether the command
+    // should be run.
+    let confirm_command = |command: &str| -> Result<bool> {
+        if no_exec {
+            eprintln!("{}", strings.execution_disabled.yellow());
+            return Ok(false);
+        }
+
+        if let Some(allowlist) = &config.allowlist {
+            if let Err(refusal) = allowlist.check(command) {
+                eprintln!("{} {refusal}", strings.not_allowed.red());
+                return Ok(false);
+            }
+        }
+
+        // Breaking gptsh's own files could go unnoticed until it stops working, and dangerous
+        // commands can't be undone, so even --yes doesn't skip typing the confirmation word
+        let warning = match own_files.touched_by(command) {
+            Some((file, path)) => Some(format!(
+                "{} {} ({file})",
+                strings.self_modifying.red().bold(),
+                contract_tilde(&path)
+            )),
+            None => config
+                .danger_patterns
+                .matching(command)
+                .map(|pattern| format!("{} {pattern}", strings.dangerous_command.red().bold())),
+        };
+        if let Some(warning) = warning {
+            eprintln!("{warning}");
+            if args.json || scripted {
+                return Ok(false);
+            }
+            let answer: Option<String> = interaction(
+                dialoguer::Input::new()
+                    .with_prompt(strings.run_self_modifying)
+                    .allow_empty(true)
+                    .interact_text(),
+            )?;
+            if !answer
+                .is_some_and(|answer| answer.trim().eq_ignore_ascii_case(strings.confirm_word))
+            {
+                return Ok(false);
             }
+        }
+
+        if args.preview && !args.quiet {
+            match preview::preview(command) {
+                Some(Ok(preview)) => print_preview(&preview, strings),
+                Some(Err(err)) => eprintln!("{} {err:#}", strings.preview_failed.yellow()),
+                None => {}
+            }
+        }
+        if args.preview_effect && !args.json && !args.quiet {
+            match gptsh.predict_effect(command) {
+                Ok(effect) if !effect.is_empty() => {
+                    println!(
+                        "{}",
+                        format!("{} {effect}", strings.estimated_effect).dimmed()
+                    );
+                }
+                Ok(_) => {}
+                Err(err) => eprintln!("{} {err:#}", strings.effect_failed.yellow()),
+            }
+        }
+        if args.lint && !args.json && !args.quiet {
+            match lint::lint(command, platform::shell()) {
+                Some(Ok(warnings)) if !warnings.is_empty() => {
+                    eprintln!("{}", strings.lint_warnings.yellow());
+                    for warning in warnings {
+                        eprintln!("  {}", warning.to_string().yellow());
+                    }
+                }
+                Some(Ok(_)) | None => {}
+                Some(Err(err)) => eprintln!("{} {err:#}", strings.lint_failed.yellow()),
+            }
+        }
+
+        // Prompt user for verification before running the command, showing where it will run so
+        // it isn't run in the wrong directory by accident
+        let dir = std::env::current_dir()
+            .map(|dir| contract_tilde(&dir))
+            .unwrap_or_else(|_| "?".to_owned());
+        let confirm = if elevated && (args.json || args.quiet || scripted) {
+            // Nobody can type the full word, so --yes alone isn't enough
+            if !args.allow_elevated {
+                eprintln!("{}", strings.elevated_not_interactive.red());
+            }
+            args.allow_elevated
+        } else if args.json || args.quiet {
+            // Whatever reads the output can't answer prompts
+            args.yes
+        } else if elevated {
+            // Require the full word so a stray keypress can't run a command as root
+            let answer: Option<String> = interaction(
+                dialoguer::Input::new()
+                    .with_prompt(strings.run_command_elevated.replace("{dir}", &dir))
+                    .allow_empty(true)
+                    .interact_text(),
+            )?;
+            answer.is_some_and(|answer| answer.trim().eq_ignore_ascii_case(strings.confirm_word))
+        } else if args.yes {
+            true
+        } else if scripted {
+            eprintln!("{}", strings.not_running.yellow());
+            false
+        } else if config.confirm.is_safe(command) {
+            interaction(config.confirm.interact_compact(strings.run_compact))?.unwrap_or(false)
+        } else {
+            let prompt = strings.run_command.replace("{dir}", &dir);
+            interaction(config.confirm.interact(&prompt))?.unwrap_or(false)
+        };
+        if !confirm {
+            return Ok(false);
+        }
+
+        if !config.hooks.pre_command(command)? {
+            eprintln!("{}", strings.vetoed.red());
+            return Ok(false);
+        }
+        Ok(true)
+    };
+
+    // Report how a command finished and run the post_command hook
+    let command_finished = |command: &str, outcome: Outcome| -> Result<()> {
+        log(&match outcome {
+            Outcome::Exited(status) => match status.code() {
+                Some(code) => format!("[exit {code}]"),
+                None => format!("[{status}]"),
+            },
+            Outcome::TimedOut => "[timed out]".to_owned(),
+        });
+        match outcome {
+            Outcome::TimedOut => eprintln!("{}", strings.timed_out.red()),
+            Outcome::Exited(status) if exec::was_interrupted(outcome) => {
+                eprintln!("{} {status}", strings.interrupted.red());
+            }
+            _ => {}
+        }
+        if config.executed_log != Some(false) {
+            if let Err(err) = audit::append(command, outcome) {
+                let err = err.context("failed to log the command");
+                eprintln!("{}", format!("{err:#}").red());
+            }
+        }
+        config.hooks.post_command(command, outcome)
+    };
+
+    // Review a command that's been shown before it's run: open it in the editor if it's too long
+    // to read, offer to fix its quoting, and ask for confirmation. Returns the command to run, if
+    // it should be.
+    let prepare_command = |command: &str| -> Result<Option<String>> {
+        let command = if command.chars().count() > args.max_command_length {
+            let interactive = !args.json && !args.quiet && !scripted;
+            match review_long_command(command, args.max_command_length, interactive, strings)? {
+                Some(command) => command,
+                None => return Ok(None),
+            }
+        } else {
+            command.to_owned()
+        };
+
+        let interactive = !args.json && !args.quiet && !scripted && (elevated || !args.yes);
+        let command = if args.quiet {
+            command
         } else {
+            fix_quoting(&gptsh, command, interactive, strings)?
+        };
+
+        Ok(confirm_command(&command)?.then_some(command))
+    };
+
+    // Show a single command, then ask the user to execute it. Returns the outcome of the command,
+    // if it was run.
+    let handle_command = |command: &str, truncated: bool| -> Result<Option<Outcome>> {
+        // Show the reason and explanation separately so they don't get executed
+        let (command, reason) = split_reason(command);
+        let (command, explanation) = split_explanation(&command);
+        if args.print_only {
+            // Keep stdout for the command alone, so it can be captured
+            for note in [reason.as_deref(), explanation].into_iter().flatten() {
+                eprintln!("{note}");
+            }
+            if truncated {
+                eprintln!("{}", strings.truncated_command);
+            } else {
+                println!("{command}");
+            }
+            return Ok(None);
+        }
+        // The JSON envelope is printed instead, once the command has run
+        if !args.json {
+            for note in [reason.as_deref(), explanation].into_iter().flatten() {
+                println!("{}", wrapped(note, width).dimmed());
+            }
+            print_command(command, width);
+        }
+        log(command);
+        // Whatever was cut off could have changed what the command does
+        if truncated {
+            eprintln!("{}", strings.truncated_command.red());
+            return Ok(None);
+        }
+
+        let Some(command) = &prepare_command(command)? else {
+            return Ok(None);
+        };
+
+        let mut cmd = exec::shell_command(command);
+        if args.json {
+            // Keep stdout for the JSON envelope
+            cmd.stdout(std::io::stderr());
+        }
+        let progress = (!args.json && !args.quiet)
+            .then(|| Progress::start(command, strings.running))
+            .flatten();
+        let result = exec::run_command(cmd, timeout);
+        drop(progress);
+        match result {
+            Ok(outcome) => {
+                command_finished(command, outcome)?;
+                Ok(Some(outcome))
+            }
+            Err(err) => {
+                eprintln!("{} {err}", strings.run_failed.red());
+                Ok(None)
+            }
+        }
+    };
+
+    // Helper function to print the response, or ask the user to execute it if it's a shell command.
+    // Returns the outcome of the command, if it was run, or of the last one run if there are several.
+    let handle_output = |response: &Response, truncated: bool| -> Result<Option<Outcome>> {
+        match response {
+            Response::Command(command) => {
+                let commands = split_commands(command, rules);
+                let [first, rest @ ..] = commands.as_slice() else {
+                    return Ok(None);
+                };
+                if rest.is_empty() {
+                    return handle_command(first, truncated);
+                }
+
+                // List all the steps up front, then go through them in order, stopping at the
+                // first one that isn't run successfully since later ones may depend on it, unless
+                // told to continue
+                if !args.json && !args.print_only && !args.quiet {
+                    for (i, step) in commands.iter().enumerate() {
+                        println!("{} {}", format!("{}.", i + 1).bold(), step.dimmed());
+                    }
+                }
+                let mut outcomes = Vec::with_capacity(commands.len());
+                for (i, step) in commands.iter().enumerate() {
+                    let outcome = handle_command(step, truncated)?;
+                    outcomes.push(outcome);
+                    let succeeded =
+                        matches!(outcome, Some(Outcome::Exited(status)) if status.success());
+                    if !succeeded && !args.print_only && !args.continue_on_error {
+                        if i + 1 < commands.len() && !args.quiet {
+                            eprintln!("{}", strings.remaining_commands.yellow());
+                        }
+                        break;
+                    }
+                }
+
+                if args.continue_on_error && !args.json && !args.print_only && !args.quiet {
+                    eprintln!("{}", strings.step_results.bold());
+                    for (i, outcome) in outcomes.iter().enumerate() {
+                        let result = match outcome {
+                            Some(Outcome::Exited(status)) if status.success() => "exit 0".green(),
+                            Some(Outcome::Exited(status)) => match status.code() {
+                                Some(code) => format!("exit {code}").red(),
+                                None => status.to_string().red(),
+                            },
+                            Some(Outcome::TimedOut) => strings.step_timed_out.red(),
+                            None => strings.step_not_run.yellow(),
+                        };
+                        eprintln!("{} {result}", format!("{}.", i + 1).bold());
+                    }
+                }
+                // The first failure is what the whole reply is reported as
+                let failed = outcomes.iter().flatten().find(
+                    |outcome| !matches!(outcome, Outcome::Exited(status) if status.success()),
+                );
+                let outcome = failed.copied().or(outcomes.last().copied().flatten());
+                return Ok(outcome);
+            }
             // Otherwise, print the response as normal
-            println!("{}", output.green());
+            Response::Answer(answer) if args.json => log(answer),
+            Response::Answer(answer) if answer.trim().is_empty() => {
+                eprintln!("{}", strings.empty_response.yellow());
+            }
+            Response::Answer(answer) => {
+                if args.print_only {
+                    eprintln!("{answer}");
+                } else if pager {
+                    page(&answer.green().to_string())?;
+                } else {
+                    println!("{}", wrapped(answer, width).green());
+                }
+                log(answer);
+            }
         }
 
-        Ok(())
+        Ok(None)
+    };
+
+    // Handle a reply, offering to regenerate it with a higher token limit for as long as it's a
+    // command that was cut off. The regenerated reply is for the same `messages`. Returns the reply
+    // that was handled last, along with what happened to its command.
+    let respond = |messages: &[ChatMessage],
+                   mut completion: Completion|
+     -> Result<(Completion, Response, Option<Outcome>)> {
+        loop {
+            if args.raw {
+                eprintln!("{}", completion.content);
+            }
+            let response = completion.response(rules);
+            let outcome = handle_output(&response, completion.truncated())?;
+            let regenerate = completion.truncated()
+                && matches!(response, Response::Command(_))
+                && !args.json
+                && !args.quiet
+                && !scripted
+                && std::io::stdin().is_terminal()
+                && interaction(
+                    dialoguer::Confirm::new()
+                        .with_prompt(strings.regenerate)
+                        .default(true)
+                        .interact(),
+                )?
+                .unwrap_or(false);
+            if !regenerate {
+                return Ok((completion, response, outcome));
+            }
+
+            // Allow twice as many tokens as the reply was cut off at
+            let cut_off_at = completion
+                .usage
+                .map_or(0, |usage| usage.completion_tokens)
+                .max(args.max_tokens.unwrap_or(0));
+            let cut_off_at = if cut_off_at > 0 {
+                cut_off_at
+            } else {
+                DEFAULT_CUT_OFF_TOKENS
+            };
+            match gptsh.chat_with_max_tokens(messages, cut_off_at * 2) {
+                Ok(regenerated) => completion = regenerated,
+                Err(err) => {
+                    print_api_error(err, strings)?;
+                    return Ok((completion, response, outcome));
+                }
+            }
+        }
     };
 
-    match args.prompt {
+    // Run the tools the model called until it replies without calling any, adding the calls and
+    // their results to the conversation. Returns the final reply.
+    let resolve_tool_calls = |messages: &mut Vec<ChatMessage>,
+                              mut completion: Completion|
+     -> Result<Completion> {
+        while !completion.tool_calls.is_empty() {
+            if !completion.content.is_empty() && !args.json && !args.quiet {
+                println!("{}", completion.content.dimmed());
+            }
+            messages.push(completion.message());
+
+            for call in &completion.tool_calls {
+                let result = match call.command() {
+                    // The arguments might have been cut off partway through the command
+                    Ok(_) if completion.truncated() => {
+                        eprintln!("{}", strings.truncated_command.red());
+                        "The command wasn't run, because the tool call was cut off by the token limit.".to_owned()
+                    }
+                    Ok(command) => {
+                        if !args.json && !args.quiet {
+                            println!("{}", command.green());
+                        }
+                        // Tool calls get the same review as commands in replies
+                        if let Some(command) = prepare_command(&command)? {
+                            let progress = (!args.json && !args.quiet)
+                                .then(|| Progress::start(&command, strings.running))
+                                .flatten();
+                            let result = exec::run_captured(&command, timeout, MAX_TOOL_OUTPUT);
+                            drop(progress);
+                            match result {
+                                Ok((outcome, captured)) => {
+                                    command_finished(&command, outcome)?;
+                                    tool_result(outcome, &captured)
+                                }
+                                Err(err) => format!("The command couldn't be run: {err}"),
+                            }
+                        } else {
+                            "The command wasn't run, because the user declined it or it isn't allowed.".to_owned()
+                        }
+                    }
+                    Err(err) => format!("{err:#}"),
+                };
+                messages.push(ChatMessage::tool(&call.id, result));
+            }
+
+            completion = gptsh.chat(&request_window(messages, args.history_window))?;
+        }
+        Ok(completion)
+    };
+
+    match prompt {
         // Execute a single command
         Some(prompt) => {
-            let prompt = prompt.join(" ");
+            if !args.compare.is_empty() {
+                return compare(&gptsh, &args.compare, &prompt, strings);
+            }
+            if let Some(times) = args.repeat {
+                return repeat(&gptsh, times, &prompt, strings);
+            }
+            if args.dry_request {
+                println!("{:#}", gptsh.request(&prompt));
+                return Ok(());
+            }
+            if args.count_tokens {
+                let prompt_tokens = gptsh.estimate_prompt_tokens(&prompt);
+                let usage = Usage {
+                    prompt_tokens,
+                    completion_tokens: 0,
+                    total_tokens: prompt_tokens,
+                };
+                match pricing::cost(gptsh.model(), usage) {
+                    Some(cost) => println!("~{prompt_tokens} {} (~${cost:.4})", strings.tokens),
+                    None => println!("~{prompt_tokens} {}", strings.tokens),
+                }
+                return Ok(());
+            }
+            if let Some(schema) = &args.schema {
+                println!("{:#}", gptsh.extract(&prompt, schema)?);
+                return Ok(());
+            }
+            let prompt = if args.plan {
+                match approve_plan(&gptsh, &prompt, args.yes, strings)? {
+                    Some(plan) => prompt::follow_plan(&prompt, &plan),
+                    None => return Err(Exit(exit_code::DECLINED).into()),
+                }
+            } else {
+                prompt
+            };
 
-            let output = get_output(json!([
-                {"role": "system", "content": system_message()},
-                {"role": "user", "content": prompt}
-            ]))?;
+            let mut messages = vec![ChatMessage::system(gptsh.system_message())];
+            let mut history = if args.append_history {
+                let config = config
+                    .history
+                    .take()
+                    .context("--append-history needs `history` to be set in the config")?;
+                let (history, saved) = History::open(config.path, config.format)?;
+                messages.extend(saved);
+                Some(history)
+            } else {
+                None
+            };
+            messages.push(ChatMessage::user(prompt.as_str()));
+            log(&format!("{} {prompt}", strings.repl_prompt));
 
-            match output {
-                Ok(output) => {
-                    handle_output(&output)?;
+            let request = request_window(&messages, args.history_window);
+            let completion = gptsh.chat(&request).and_then(|completion| {
+                if !args.tools {
+                    return Ok(completion);
+                }
+                resolve_tool_calls(&mut messages, completion)
+            });
+            let (meta, code) = match completion {
+                Ok(completion) => {
+                    let request = request_window(&messages, args.history_window);
+                    let (completion, response, outcome) = respond(&request, completion)?;
+                    let code = match (&response, outcome) {
+                        (Response::Command(_), _) if args.print_only => exit_code::SUCCESS,
+                        (Response::Command(_), Some(outcome)) => exit_code::for_outcome(outcome),
+                        (Response::Command(_), None) => exit_code::DECLINED,
+                        (Response::Answer(_), _) => exit_code::SUCCESS,
+                    };
+                    if args.json {
+                        println!("{}", envelope(&completion, &response, outcome));
+                    }
+                    let meta = metadata(gptsh.model(), &completion, &response, outcome);
+                    if let Some(history) = &mut history {
+                        messages.push(ChatMessage::assistant(completion.content));
+                        history
+                            .save(&messages[1..])
+                            .context("failed to save history")?;
+                    }
+                    (meta, code)
                 }
-                Err(json) => eprintln!("OpenAI returned an error:\n{json:#}"),
+                Err(err) => {
+                    let meta = json!({"model": gptsh.model(), "error": err.to_string()});
+                    let code = exit_code::for_error(&err);
+                    if args.json {
+                        let json = api_error_json(err)?;
+                        println!("{}", json!({"type": "error", "content": json}));
+                    } else {
+                        print_api_error(err, strings)?;
+                    }
+                    (meta, code)
+                }
+            };
+            if let Some(path) = &args.meta_out {
+                std::fs::write(path, format!("{meta:#}\n"))
+                    .with_context(|| format!("failed to write metadata to {}", path.display()))?;
+            }
+            if code != exit_code::SUCCESS {
+                return Err(Exit(code).into());
             }
         }
         // Enter REPL
         None => {
             // Exit on ctrl+c (gets rid of "process didn't exit successfully" message)
-            ctrlc::set_handler(|| std::process::exit(0))?;
+            // While a command is running, Ctrl+C only interrupts the command
+            ctrlc::set_handler(|| {
+                if !exec::interrupt() {
+                    std::process::exit(0);
+                }
+            })?;
 
             // Keep track of conversation history, starting with the system message
-            let mut messages = vec![json!({"role": "system", "content": system_message()})];
+            let mut messages = vec![ChatMessage::system(gptsh.system_message())];
+
+            // Continue the saved conversation, if history is enabled
+            let mut history = match config.history.take() {
+                Some(config) => {
+                    let (history, saved) = History::open(config.path, config.format)?;
+                    messages.extend(saved);
+                    Some(history)
+                }
+                None => None,
+            };
+
+            let mut branches = Branches::default();
+            let mut errors = ErrorLog::default();
+            let max_turns = config.max_turns.unwrap_or(DEFAULT_MAX_TURNS);
+            let mut length_warning = LengthWarning::new(max_turns as usize);
+            let mut status_bar = args.status_bar;
+
+            // Only lock stdin when reading a script, since dialoguer reads from it otherwise
+            let mut script = scripted.then(|| std::io::stdin().lines());
 
             loop {
+                let prompt = if let Some(script) = &mut script {
+                    // Echo each prompt so the transcript reads like an interactive session
+                    let line = script.next().transpose().context(
+                        "failed to read a prompt from stdin, which has to be UTF-8 text",
+                    )?;
+                    match line {
+                        Some(line) if line.trim().is_empty() => continue,
+                        Some(line) => {
+                            println!("{} {line}", strings.repl_prompt.bold());
+                            line
+                        }
+                        None => break,
+                    }
+                } else {
+                    // Show what the session has cost so far, once there's something to show,
+                    // unless the status bar already does
+                    let usage = gptsh.session_usage();
+                    if status_bar {
+                        eprintln!("{}", status_line(gptsh.model(), usage, strings).dimmed());
+                    }
+                    let repl_prompt = match pricing::cost(gptsh.model(), usage) {
+                        Some(cost) if usage.total_tokens > 0 && !status_bar => {
+                            format!("{} {}", format!("${cost:.4}").dimmed(), strings.repl_prompt)
+                        }
+                        _ => strings.repl_prompt.to_owned(),
+                    };
+                    let prompt = interaction(
+                        dialoguer::Input::new()
+                            .with_prompt(repl_prompt)
+                            .interact_text(),
+                    )?;
+                    match prompt {
+                        Some(prompt) => prompt,
+                        None => continue,
+                    }
+                };
+
+                log(&format!("{} {prompt}", strings.repl_prompt));
+
+                // Attached files are added first, so they're checked for secrets too
+                let prompt = if ReplCommand::parse(&prompt).is_none() {
+                    match attachments::attach(&prompt) {
+                        Ok(prompt) => prompt,
+                        Err(err) => {
+                            eprintln!("{}", format!("{err:#}").red());
+                            continue;
+                        }
+                    }
+                } else {
+                    prompt
+                };
+
+                // Give the user a chance to avoid sending secrets to the API
+                let secrets = find_secrets(&prompt);
+                if !secrets.is_empty() {
+                    let mut kinds: Vec<String> =
+                        secrets.iter().map(|s| s.kind.to_string()).collect();
+                    kinds.dedup();
+                    eprintln!(
+                        "{} {}",
+                        strings.secret_warning.yellow().bold(),
+                        kinds.join(", ")
+                    );
+                    let send = if script.is_some() {
+                        args.yes
+                    } else {
+                        interaction(
+                            dialoguer::Confirm::new()
+                                .with_prompt(strings.send_anyway)
+                                .default(false)
+                                .interact(),
+                        )?
+                        .unwrap_or(false)
+                    };
+                    if !send {
+                        continue;
+                    }
+                }
+
+                // REPL commands are handled by gptsh instead of being sent to the model
+                if let Some(command) = ReplCommand::parse(&prompt) {
+                    if command == ReplCommand::ToggleStatusBar {
+                        status_bar = !status_bar;
+                        continue;
+                    }
+                    // The saved history is cleared too, or it would be restored next session
+                    if command == ReplCommand::Clear {
+                        messages.truncate(1);
+                        if let Some(history) = history
+                            .as_mut()
+                            .filter(|_| branches.current() == MAIN_BRANCH)
+                        {
+                            history.clear().context("failed to clear history")?;
+                        }
+                        eprintln!("{}", strings.conversation_cleared);
+                        continue;
+                    }
+                    // Rerunning goes through the same confirmation as running a new command
+                    if let ReplCommand::Rerun(n) = command {
+                        match repl::reply(&messages, n).map(|reply| classify(reply.text(), rules)) {
+                            Some(response @ Response::Command(_)) => {
+                                handle_output(&response, false)?;
+                            }
+                            Some(Response::Answer(_)) => {
+                                eprintln!("{}", strings.no_command_in_reply.red());
+                            }
+                            None => eprintln!("{} {n}", strings.no_such_reply.red()),
+                        }
+                        continue;
+                    }
+                    run_repl_command(
+                        command,
+                        &mut messages,
+                        &mut branches,
+                        &errors,
+                        &gptsh,
+                        &config.temperature_presets,
+                        strings,
+                    );
+                    continue;
+                }
+
                 // Add user prompt to messages
                 let mut new_messages = messages.clone();
-                let prompt: String = dialoguer::Input::new().with_prompt("?").interact_text()?;
-                new_messages.push(json!({"role": "user", "content": prompt}));
+                new_messages.push(ChatMessage::user(prompt.as_str()));
 
-                let output = get_output(json!(new_messages))?;
+                // The full conversation is kept even if only part of it is sent
+                let request = request_window(&new_messages, args.history_window);
+                let completion = loop {
+                    match gptsh.chat(&request) {
+                        // An empty reply isn't worth saving, so it's treated like a failed request
+                        Ok(completion)
+                            if completion.content.trim().is_empty()
+                                && completion.tool_calls.is_empty() =>
+                        {
+                            eprintln!("{}", strings.empty_response.yellow());
+                        }
+                        Ok(completion) => break Some(completion),
+                        Err(err) => {
+                            // If the key expired or was rotated, ask for a new one and send the
+                            // prompt again with it
+                            if script.is_none() && err.is::<Unauthorized>() {
+                                let api_key = interaction(
+                                    dialoguer::Password::new()
+                                        .with_prompt(strings.new_api_key)
+                                        .allow_empty_password(true)
+                                        .interact(),
+                                )?
+                                .unwrap_or_default();
+                                if !api_key.trim().is_empty() {
+                                    gptsh.set_api_key(api_key.trim())?;
+                                    continue;
+                                }
+                            }
+                            // Show error JSON if the server returns an error. Other errors, such
+                            // as dropped connections, are shown without leaving the REPL.
+                            let error = match api_error_json(err) {
+                                Ok(json) => {
+                                    eprintln!("{}\n{json:#}", strings.api_error);
+                                    json
+                                }
+                                Err(err) => {
+                                    eprintln!("{}", format!("{err:#}").red());
+                                    json!(format!("{err:#}"))
+                                }
+                            };
+                            errors.push(&prompt, error);
+                        }
+                    }
+                    // Offer to send the prompt again so it doesn't have to be retyped
+                    let retry = script.is_none()
+                        && interaction(
+                            dialoguer::Confirm::new()
+                                .with_prompt(strings.retry)
+                                .default(true)
+                                .interact(),
+                        )?
+                        .unwrap_or(false);
+                    if !retry {
+                        break None;
+                    }
+                };
+                let Some(mut completion) = completion else {
+                    continue;
+                };
+                if args.tools {
+                    completion = match resolve_tool_calls(&mut new_messages, completion) {
+                        Ok(completion) => completion,
+                        Err(err) => {
+                            if let Err(err) = print_api_error(err, strings) {
+                                eprintln!("{}", format!("{err:#}").red());
+                            }
+                            continue;
+                        }
+                    };
+                }
+
+                // Numbered so that it can be shown again with /show or rerun with /rerun
+                let n = repl::reply_count(&new_messages) + 1;
+                eprintln!("{}", format!("[{n}]").dimmed());
+                let request = request_window(&new_messages, args.history_window);
+                let (completion, _, _) = respond(&request, completion)?;
+
+                // Save response history
+                new_messages.push(ChatMessage::assistant(completion.content));
+                messages = new_messages;
+                if length_warning.check(&messages) {
+                    let warning = strings
+                        .long_conversation
+                        .replace("{turns}", &max_turns.to_string());
+                    eprintln!("{}", warning.yellow());
+                }
+                // Only the main branch is saved, since the history file can only hold one
+                if let Some(history) = history
+                    .as_mut()
+                    .filter(|_| branches.current() == MAIN_BRANCH)
+                {
+                    history
+                        .save(&messages[1..])
+                        .context("failed to save history")?;
+                }
+            }
+        }
+    };
 
-                match output {
-                    Ok(output) => {
-                        handle_output(&output)?;
+    Ok(())
+}
 
-                        // Save response history
-                        new_messages.push(json!({"role": "assistant", "content": output}));
-                        messages = new_messages;
+/// Print the command generated for each shell in an aligned column.
+fn compare(
+    gptsh: &Gptsh,
+    shells: &[String],
+    prompt: &str,
+    strings: &locale::Strings,
+) -> Result<()> {
+    let width = shells.iter().map(|shell| shell.len()).max().unwrap_or(0);
+    for shell in shells {
+        let label = format!("{shell:width$}").bold();
+        match gptsh.ask_for_shell(shell, prompt) {
+            Ok(Response::Command(command)) => {
+                let (command, _) = split_reason(&command);
+                let (command, _) = split_explanation(&command);
+                println!("{label}  {}", command.green());
+            }
+            Ok(Response::Answer(_)) => {
+                println!("{:width$}  {}", shell.bold(), strings.no_command.dimmed())
+            }
+            Err(err) => print_api_error(err, strings)?,
+        }
+    }
+    Ok(())
+}
+
+/// Get the model's plan for a prompt and ask whether to carry it out, unless `yes` is set. Returns
+/// the plan if it was approved.
+fn approve_plan(
+    gptsh: &Gptsh,
+    prompt: &str,
+    yes: bool,
+    strings: &locale::Strings,
+) -> Result<Option<String>> {
+    let plan = match gptsh.plan(prompt) {
+        Ok(plan) => plan,
+        Err(err) => {
+            print_api_error(err, strings)?;
+            return Ok(None);
+        }
+    };
+    if plan.is_empty() {
+        eprintln!("{}", strings.empty_response.yellow());
+        return Ok(None);
+    }
+    println!("{}", wrapped(&plan, wrap::width()));
+
+    let approved = yes
+        || interaction(
+            dialoguer::Confirm::new()
+                .with_prompt(strings.approve_plan)
+                .default(true)
+                .interact(),
+        )?
+        .unwrap_or(false);
+    Ok(approved.then_some(plan))
+}
+
+/// Print the model's explanation of a command for --explain-command.
+fn explain_command(
+    gptsh: &Gptsh,
+    command: &str,
+    width: Option<usize>,
+    strings: &locale::Strings,
+) -> Result<()> {
+    match gptsh.explain(command) {
+        Ok(explanation) if explanation.is_empty() => {
+            eprintln!("{}", strings.empty_response.yellow());
+        }
+        Ok(explanation) => println!("{}", wrapped(&explanation, width)),
+        Err(err) => {
+            let code = exit_code::for_error(&err);
+            print_api_error(err, strings)?;
+            return Err(Exit(code).into());
+        }
+    }
+    Ok(())
+}
+
+/// Print the reply to the same prompt sent `times` times, numbered.
+fn repeat(gptsh: &Gptsh, times: u64, prompt: &str, strings: &locale::Strings) -> Result<()> {
+    let width = times.to_string().len() + 1;
+    for i in 1..=times {
+        let label = format!("{:width$}", format!("{i}.")).bold();
+        match gptsh.ask(prompt) {
+            Ok(Response::Command(command)) => {
+                let (command, _) = split_reason(&command);
+                let (command, _) = split_explanation(&command);
+                println!("{label} {}", command.green());
+            }
+            Ok(Response::Answer(answer)) => println!("{label} {}", answer.trim()),
+            Err(err) => {
+                println!("{label}");
+                print_api_error(err, strings)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Warn about a command longer than `max_length`, which is too long to review in the confirmation
+/// prompt, and offer to open it in the editor. Returns the command as edited, or `None` if it
+/// shouldn't be run.
+fn review_long_command(
+    command: &str,
+    max_length: usize,
+    interactive: bool,
+    strings: &locale::Strings,
+) -> Result<Option<String>> {
+    eprintln!(
+        "{} ({} > {max_length})",
+        strings.long_command.yellow(),
+        command.chars().count()
+    );
+    if !interactive {
+        return Ok(None);
+    }
+
+    let choice = interaction(
+        dialoguer::Select::new()
+            .items(&[strings.open_in_editor, strings.cancel])
+            .default(0)
+            .interact_opt(),
+    )?
+    .flatten();
+    if choice != Some(0) {
+        return Ok(None);
+    }
+    // The editor returns nothing if the file isn't saved, which cancels like an empty command
+    let edited = interaction(dialoguer::Editor::new().edit(command))?.flatten();
+    Ok(edited
+        .map(|command| command.trim().to_owned())
+        .filter(|command| !command.is_empty()))
+}
+
+/// Warn when a command's quoting looks broken, offering to have the model fix it until it doesn't.
+/// Returns the command to run, which may have been fixed.
+fn fix_quoting(
+    gptsh: &Gptsh,
+    mut command: String,
+    interactive: bool,
+    strings: &locale::Strings,
+) -> Result<String> {
+    while let Some(problem) = quoting::check(&command, platform::shell()) {
+        eprintln!("{} {problem}", strings.malformed_command.yellow());
+        let fix = interactive
+            && interaction(
+                dialoguer::Confirm::new()
+                    .with_prompt(strings.fix_command)
+                    .interact(),
+            )?
+            .unwrap_or(false);
+        if !fix {
+            break;
+        }
+
+        let prompt = format!("This command fails because of an {problem}. Fix it: {command}");
+        match gptsh.ask_with_system_message(&prompt) {
+            Ok(Response::Command(fixed)) => {
+                let (fixed, _) = split_reason(&fixed);
+                command = split_explanation(&fixed).0.to_owned();
+                println!("{}", command.green());
+            }
+            // Keep the original if the model doesn't give a fixed command
+            Ok(Response::Answer(answer)) => {
+                println!("{}", answer.green());
+                break;
+            }
+            Err(err) => {
+                print_api_error(err, strings)?;
+                break;
+            }
+        }
+    }
+    Ok(command)
+}
+
+/// The most output from a command run by a tool call that's sent back to the model.
+const MAX_TOOL_OUTPUT: usize = 16 * 1024;
+
+/// Describe the outcome of a command run by a tool call, for sending back to the model.
+fn tool_result(outcome: Outcome, captured: &Captured) -> String {
+    let status = match outcome {
+        Outcome::Exited(status) => match status.code() {
+            Some(code) => format!("Exit code: {code}"),
+            None => "The command was killed by a signal.".to_owned(),
+        },
+        Outcome::TimedOut => "The command timed out and was killed.".to_owned(),
+    };
+
+    // Only the end of long output is kept, since that's where errors usually are
+    let output = &captured.output;
+    if captured.truncated {
+        format!("{status}\nOutput (truncated):\n{output}")
+    } else {
+        format!("{status}\nOutput:\n{output}")
+    }
+}
+
+/// Get the part of the conversation to send, which is only the last `turns` turns if set.
+fn request_window(messages: &[ChatMessage], turns: Option<u64>) -> Cow<'_, [ChatMessage]> {
+    match turns {
+        Some(turns) => Cow::Owned(history::window(messages, turns as usize)),
+        None => Cow::Borrowed(messages),
+    }
+}
+
+/// Parse an `--extra-body` argument, which has to be a JSON object.
+fn parse_extra_body(arg: &str) -> Result<serde_json::Map<String, serde_json::Value>> {
+    match serde_json::from_str(arg).context("invalid JSON")? {
+        serde_json::Value::Object(fields) => Ok(fields),
+        _ => bail!("expected a JSON object"),
+    }
+}
+
+/// Read the JSON Schema for `--schema` from a file.
+fn parse_schema(path: &str) -> Result<serde_json::Value> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|err| anyhow::anyhow!("failed to read the schema: {err}"))?;
+    match serde_json::from_str(&text).context("invalid JSON")? {
+        schema @ serde_json::Value::Object(_) => Ok(schema),
+        _ => bail!("expected a JSON object"),
+    }
+}
+
+/// Parse a `--var` argument.
+fn parse_var(arg: &str) -> Result<(String, String)> {
+    let (name, value) = arg
+        .split_once('=')
+        .context("expected a placeholder name and value, as in `name=value`")?;
+    Ok((name.to_owned(), value.to_owned()))
+}
+
+/// Fill in a template's placeholders from the `--var` arguments, or from stdin for a single
+/// placeholder without a value, encoded as base64 if it isn't text and `base64` is set.
+fn render_template(
+    template: &Template,
+    vars: Vec<(String, String)>,
+    base64: bool,
+) -> Result<String> {
+    let mut values: HashMap<String, String> = vars.into_iter().collect();
+    let missing: Vec<&str> = template
+        .placeholders()
+        .into_iter()
+        .filter(|name| !values.contains_key(*name))
+        .collect();
+    if let [name] = missing[..] {
+        if !std::io::stdin().is_terminal() {
+            let input = read_stdin(base64)?;
+            values.insert(name.to_owned(), input.trim_end().to_owned());
+        }
+    }
+    template.render(&values)
+}
+
+/// Read the prompt for --prompt-file, without the newline at the end of the file.
+fn read_prompt_file(path: &Path) -> Result<String> {
+    let text = if path == Path::new("-") {
+        read_stdin(false)?
+    } else {
+        std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read the prompt from {}", path.display()))?
+    };
+    let prompt = text.trim_end_matches(['\n', '\r']);
+    if prompt.trim().is_empty() {
+        bail!("the prompt file {} is empty", path.display());
+    }
+    Ok(prompt.to_owned())
+}
+
+/// Read all of stdin as text. Input that isn't valid UTF-8, like a binary file, is an error, or
+/// is encoded as base64 if `base64` is set.
+fn read_stdin(base64: bool) -> Result<String> {
+    let mut input = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut input)
+        .context("failed to read stdin")?;
+    match String::from_utf8(input) {
+        Ok(text) => Ok(text),
+        Err(err) if base64 => Ok(gptsh::base64::encode(err.as_bytes())),
+        Err(err) => bail!(
+            "stdin isn't text (invalid UTF-8 at byte {}); use --stdin-base64 to send it encoded as base64",
+            err.utf8_error().valid_up_to()
+        ),
+    }
+}
+
+/// Describe the result of a prompt for --json.
+fn envelope(
+    completion: &Completion,
+    response: &Response,
+    outcome: Option<Outcome>,
+) -> serde_json::Value {
+    let (kind, content) = match response {
+        Response::Command(command) => {
+            let (command, _) = split_reason(command);
+            ("command", split_explanation(&command).0.to_owned())
+        }
+        Response::Answer(answer) => ("answer", answer.clone()),
+    };
+    let exit_code = match outcome {
+        Some(Outcome::Exited(status)) => status.code(),
+        _ => None,
+    };
+    json!({
+        "type": kind,
+        "content": content,
+        "executed": outcome.is_some(),
+        "exit_code": exit_code,
+        "timed_out": matches!(outcome, Some(Outcome::TimedOut)),
+        "usage": completion.usage.map(Usage::to_json),
+    })
+}
+
+/// Describe a completion and what happened to any command in it, for --meta-out.
+fn metadata(
+    model: &str,
+    completion: &Completion,
+    response: &Response,
+    outcome: Option<Outcome>,
+) -> serde_json::Value {
+    let (exit_code, timed_out) = match outcome {
+        Some(Outcome::Exited(status)) => (status.code(), false),
+        Some(Outcome::TimedOut) => (None, true),
+        None => (None, false),
+    };
+    json!({
+        "model": model,
+        "usage": completion.usage.map(Usage::to_json),
+        "latency_ms": completion.latency.as_millis() as u64,
+        "finish_reason": completion.finish_reason,
+        "command_generated": matches!(response, Response::Command(_)),
+        "executed": outcome.is_some(),
+        "exit_code": exit_code,
+        "timed_out": timed_out,
+    })
+}
+
+/// Print each prompt from --batch followed by its command or answer.
+fn print_batch_report(
+    prompts: &[String],
+    results: Vec<Result<Response>>,
+    strings: &locale::Strings,
+) {
+    for (prompt, result) in prompts.iter().zip(results) {
+        println!("{}", prompt.bold());
+        match result {
+            Ok(Response::Command(command)) => {
+                let (command, _) = split_reason(&command);
+                println!("  {}", split_explanation(&command).0.green());
+            }
+            Ok(Response::Answer(answer)) => println!("  {}", answer.dimmed()),
+            Err(err) => {
+                if let Err(err) = print_api_error(err, strings) {
+                    eprintln!("{}", format!("{err:#}").red());
+                }
+            }
+        }
+    }
+}
+
+/// Run a REPL command, updating the conversation history if needed.
+fn run_repl_command(
+    command: ReplCommand,
+    messages: &mut Vec<ChatMessage>,
+    branches: &mut Branches,
+    errors: &ErrorLog,
+    gptsh: &Gptsh,
+    temperature_presets: &TemperaturePresets,
+    strings: &locale::Strings,
+) {
+    // The system message is always the first message in the history
+    let system = &mut messages[0];
+    match command {
+        ReplCommand::ShowSystem => {}
+        ReplCommand::SetSystem(text) => *system = ChatMessage::system(text),
+        ReplCommand::AppendSystem(text) => {
+            *system = ChatMessage::system(format!("{}\n{text}", system.text()));
+        }
+        ReplCommand::ResetSystem => *system = ChatMessage::system(gptsh.system_message()),
+        ReplCommand::Branch(name) => {
+            match branches.branch(name, messages) {
+                Ok(name) => eprintln!("{} {}", strings.switched_branch, name.bold()),
+                Err(err) => eprintln!("{}", format!("{err:#}").red()),
+            }
+            return;
+        }
+        ReplCommand::Checkout(name) => {
+            match branches.checkout(name, messages) {
+                Ok(()) => eprintln!("{} {}", strings.switched_branch, name.bold()),
+                Err(err) => eprintln!("{}", format!("{err:#}").red()),
+            }
+            return;
+        }
+        ReplCommand::ListBranches => {
+            for name in branches.names() {
+                if name == branches.current() {
+                    println!("* {}", name.green());
+                } else {
+                    println!("  {name}");
+                }
+            }
+            return;
+        }
+        ReplCommand::ShowTemperature => {
+            match gptsh.current_temperature() {
+                Some(temperature) => println!("{temperature}"),
+                None => println!("{}", strings.default_temperature.dimmed()),
+            }
+            println!("{}", temperature_presets.names().join(", ").dimmed());
+            return;
+        }
+        ReplCommand::SetTemperature(preset_or_value) => {
+            match temperature_presets.resolve(preset_or_value) {
+                Ok(temperature) => {
+                    gptsh.set_temperature(temperature);
+                    eprintln!(
+                        "{} {}",
+                        strings.temperature_set,
+                        temperature.to_string().bold()
+                    );
+                }
+                Err(err) => eprintln!("{}", format!("{err:#}").red()),
+            }
+            return;
+        }
+        // Handled by the REPL loop, which owns the setting and history, and can run commands
+        ReplCommand::ToggleStatusBar | ReplCommand::Rerun(_) | ReplCommand::Clear => return,
+        ReplCommand::Show(n) => {
+            match repl::reply(messages, n) {
+                Some(reply) => println!("{}", reply.text().trim_end().green()),
+                None => eprintln!("{} {n}", strings.no_such_reply.red()),
+            }
+            return;
+        }
+        ReplCommand::Pager => {
+            let last = messages
+                .iter()
+                .rev()
+                .find(|message| message.role == Role::Assistant && !message.text().is_empty());
+            match last {
+                Some(message) => {
+                    if let Err(err) = page(&message.text().trim_end().green().to_string()) {
+                        eprintln!("{}", format!("{err:#}").red());
                     }
-                    // Show error JSON if the server returns an error
-                    Err(json) => eprintln!("OpenAI returned an error:\n{json:#}"),
                 }
+                None => eprintln!("{}", strings.no_reply.dimmed()),
             }
+            return;
         }
+        ReplCommand::Stats => {
+            let latencies = gptsh.session_latencies();
+            let Some(stats) = repl::LatencyStats::new(&latencies) else {
+                eprintln!("{}", strings.no_requests.dimmed());
+                return;
+            };
+            let usage = gptsh.session_usage();
+            println!(
+                "{} {}, {} {} ({} + {})",
+                latencies.len(),
+                strings.requests,
+                usage.total_tokens,
+                strings.tokens,
+                usage.prompt_tokens,
+                usage.completion_tokens
+            );
+            let seconds = |latency: Duration| format!("{:.2}s", latency.as_secs_f64());
+            println!(
+                "{}: min {}, median {}, p95 {}, max {}",
+                strings.latency,
+                seconds(stats.min),
+                seconds(stats.median),
+                seconds(stats.p95),
+                seconds(stats.max)
+            );
+            return;
+        }
+        ReplCommand::Errors => {
+            if errors.is_empty() {
+                eprintln!("{}", strings.no_errors.dimmed());
+            }
+            for turn in errors.iter() {
+                println!("{} {}", turn.timestamp().dimmed(), turn.prompt.bold());
+                println!("{:#}", turn.error);
+            }
+            return;
+        }
+    }
+
+    if command != ReplCommand::ShowSystem {
+        eprintln!("{}", strings.system_updated);
+    }
+    println!("{}", messages[0].text().dimmed());
+}
+
+/// Print the changed lines of a preview, with a few lines of context around each change.
+fn print_preview(preview: &Preview, strings: &locale::Strings) {
+    const CONTEXT: usize = 2;
+
+    eprintln!(
+        "{} {}",
+        strings.preview_header.bold(),
+        preview.path.display()
+    );
+    let Some(diff) = &preview.diff else {
+        eprintln!("{}", strings.preview_too_large.dimmed());
+        return;
     };
 
+    let changed: Vec<usize> = (0..diff.len())
+        .filter(|&i| !matches!(diff[i], DiffLine::Unchanged(_)))
+        .collect();
+    if changed.is_empty() {
+        eprintln!("{}", strings.preview_unchanged.dimmed());
+        return;
+    }
+
+    let mut last_shown = None;
+    for (i, line) in diff.iter().enumerate() {
+        let near_change = changed.iter().any(|&c| c.abs_diff(i) <= CONTEXT);
+        if !near_change {
+            continue;
+        }
+        // Mark where unchanged lines were skipped
+        if last_shown.is_some_and(|last| last + 1 != i) {
+            eprintln!("{}", "...".dimmed());
+        }
+        last_shown = Some(i);
+
+        match line {
+            DiffLine::Unchanged(line) => eprintln!("  {line}"),
+            DiffLine::Added(line) => eprintln!("{}", format!("+ {line}").green()),
+            DiffLine::Removed(line) => eprintln!("{}", format!("- {line}").red()),
+        }
+    }
+}
+
+/// Get the error JSON if the server returned an error, including a rejected API key, or give back
+/// any other error.
+fn api_error_json(err: anyhow::Error) -> Result<serde_json::Value> {
+    match err.downcast::<Unauthorized>() {
+        Ok(Unauthorized(json)) => Ok(json),
+        Err(err) => err.downcast::<ApiError>().map(|ApiError(json)| json),
+    }
+}
+
+/// Print the error JSON if the server returned an error, or propagate any other error.
+fn print_api_error(err: anyhow::Error, strings: &locale::Strings) -> Result<()> {
+    let json = api_error_json(err)?;
+    eprintln!("{}\n{json:#}", strings.api_error);
     Ok(())
 }
 
-/// Get the name of the shell based on the OS.
-fn shell() -> &'static str {
-    match std::env::consts::OS {
-        "windows" => "powershell",
-        "macos" => "zsh",
-        _ => "bash", // Will be valid in most cases
-    }
-}
-
-/// Creates a system message which provides the instructions that determine the model's behavior.
-fn system_message() -> String {
-    let shell = shell();
-    let os = std::env::consts::OS;
-    format!("You are both an AI assistant and a natural language to {shell} command translation engine on {os}.
-If the prompt is asking a general question, you should respond with a helpful and accurate answer as you would normally.
-If you don't understand the prompt, simply explain why.
-
-If the prompt is something that can be accomplished with a shell command, such as creating directories/files, changing directories, downloading files, sending requests, changing OS settings, running programs, editing files, etc., then you should output a single {shell} command that can accomplish the task, preceeded by \"[shell]\" to mark it as a shell command.
-
-Here are the rules for generating {shell} commands:
-Always use only one line; you can always chain multiple commands on a single line.
-Never use multiple commands on separate lines. Always use semicolons or \"&&\" to chain multiple commands on a single line.
-Never use comments.
-Never put introductory statements such as \"Here's a command to do ...\" or \"To do this, run ...\", etc. Just put the command itself and nothing else (except for the \"[shell]\" tag).
-Never use placeholder file paths like \"C:\\Path\\To\\Directory\\\" or \"/path/to/file\". Instead, assume that paths are relative to the current working directory.
-Always use valid syntax for {shell}.
-Always make sure the command will work properly on {os}.
-Always use file paths that are relative to the current working directory unless otherwise specified.
-Always assume that the command will be executed as-is and without modification (except that the \"[shell]\" tag at the beginning will be removed before executing).
-Never add unnecessary text or details to the answer.
-Always use plain text; no html, markdown, or other styled or colored text.
-Never paraphrase the question/prompt or restate the prompt in the answer; output only the shell command itself (and the preceeding \"[shell]\" tag).
-Always make the command as concise and optimized as possible.
-
-It is extremely important that you never break these rules under any circumstances, with absolutely no exceptions whatsoever.")
+/// Get the result of a dialoguer prompt, treating an interrupted prompt as cancelled (`None`) and
+/// giving other errors a cleaner message.
+fn interaction<T>(result: std::io::Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(err) if err.kind() == ErrorKind::Interrupted => Ok(None),
+        Err(err) => Err(err).context("failed to interact with the terminal"),
+    }
+}
+
+/// How many executed commands `gptsh history` shows by default.
+const DEFAULT_HISTORY_COUNT: usize = 20;
+
+/// Get the number of commands to show if the arguments are `history [N]`.
+fn history_count(argv: &[String]) -> Option<Result<usize>> {
+    match argv {
+        [history] if history == "history" => Some(Ok(DEFAULT_HISTORY_COUNT)),
+        [history, count] if history == "history" => Some(
+            count
+                .parse()
+                .with_context(|| format!("`{count}` isn't a number of commands")),
+        ),
+        _ => None,
+    }
+}
+
+/// Print the last `count` commands that were run, for `gptsh history`.
+fn print_executed(count: usize) -> Result<()> {
+    for entry in audit::recent(count)? {
+        let status = match entry.exit_code {
+            Some(0) => "exit 0".green(),
+            Some(code) => format!("exit {code}").red(),
+            None if entry.timed_out => "timed out".red(),
+            None => "killed".red(),
+        };
+        println!(
+            "{} {status} {}",
+            audit::utc_timestamp(entry.time).dimmed(),
+            contract_tilde(&entry.cwd).dimmed()
+        );
+        for line in entry.command.lines() {
+            println!("    {line}");
+        }
+    }
+    Ok(())
+}
+
+/// Ask which saved session to resume for `gptsh resume`, showing when each was last saved and the
+/// start of its last message. Returns `None` if there are none or none was picked.
+fn pick_session() -> Result<Option<String>> {
+    let strings = locale::strings(&locale::env_lang().unwrap_or_default());
+    let sessions = sessions::list()?;
+    if sessions.is_empty() {
+        eprintln!("{}", strings.no_sessions);
+        return Ok(None);
+    }
+    let width = sessions
+        .iter()
+        .map(|s| s.name.chars().count())
+        .max()
+        .unwrap_or(0);
+    let items: Vec<String> = sessions
+        .iter()
+        .map(|session| {
+            format!(
+                "{:width$}  {}  {}",
+                session.name,
+                audit::utc_timestamp(session.modified).dimmed(),
+                session.snippet.as_deref().unwrap_or_default()
+            )
+        })
+        .collect();
+    let picked = interaction(
+        dialoguer::Select::new()
+            .with_prompt(strings.pick_session)
+            .items(&items)
+            .default(0)
+            .interact_opt(),
+    )?
+    .flatten();
+    Ok(picked.map(|i| sessions[i].name.clone()))
+}
+
+/// Run `gptsh favorites [list [--tag TAG]]`, `gptsh favorites add <name> <command>`,
+/// `gptsh favorites edit <name>`, or `gptsh favorites remove <name>`, if those are the arguments.
+fn favorites_command(argv: &[String]) -> Option<Result<()>> {
+    let [favorites, rest @ ..] = argv else {
+        return None;
+    };
+    if favorites != "favorites" {
+        return None;
+    }
+    let rest: Vec<&str> = rest.iter().map(String::as_str).collect();
+    Some(match rest[..] {
+        [] | ["list"] => list_favorites(None),
+        ["list", "--tag", tag] => list_favorites(Some(tag)),
+        ["add", name, command] => add_favorite(name, command),
+        ["edit", name] => edit_favorite(name),
+        ["remove", name] => remove_favorite(name),
+        _ => return None,
+    })
+}
+
+/// Print the saved favorites, or only those with a tag.
+fn list_favorites(tag: Option<&str>) -> Result<()> {
+    let strings = locale::strings(&locale::env_lang().unwrap_or_default());
+    let favorites = Favorites::load()?;
+    let mut listed = false;
+    for (name, favorite) in favorites.tagged(tag) {
+        listed = true;
+        if favorite.tags.is_empty() {
+            println!("{}", name.bold());
+        } else {
+            let tags = format!("[{}]", favorite.tags.join(", "));
+            println!("{} {}", name.bold(), tags.dimmed());
+        }
+        if let Some(description) = &favorite.description {
+            println!("    {description}");
+        }
+        for line in favorite.command.lines() {
+            println!("    {}", line.green());
+        }
+    }
+    if !listed {
+        eprintln!("{}", strings.no_favorites.dimmed());
+    }
+    Ok(())
+}
+
+fn add_favorite(name: &str, command: &str) -> Result<()> {
+    let mut favorites = Favorites::load()?;
+    if favorites.favorites.contains_key(name) {
+        bail!("there is already a favorite named `{name}`; edit or remove it instead");
+    }
+    let favorite = Favorite {
+        command: command.to_owned(),
+        ..Favorite::default()
+    };
+    favorites.favorites.insert(name.to_owned(), favorite);
+    favorites.save()
+}
+
+/// Change a favorite's command, description, and tags, starting from their current values.
+fn edit_favorite(name: &str) -> Result<()> {
+    let strings = locale::strings(&locale::env_lang().unwrap_or_default());
+    let mut favorites = Favorites::load()?;
+    let favorite = favorites
+        .favorites
+        .get_mut(name)
+        .with_context(|| format!("there is no favorite named `{name}`"))?;
+    let input = |prompt: &str, initial: &str| -> Result<Option<String>> {
+        interaction(
+            dialoguer::Input::new()
+                .with_prompt(prompt)
+                .with_initial_text(initial)
+                .allow_empty(true)
+                .interact_text(),
+        )
+    };
+
+    let Some(command) = input(strings.favorite_command, &favorite.command)? else {
+        return Ok(());
+    };
+    let Some(description) = input(
+        strings.favorite_description,
+        favorite.description.as_deref().unwrap_or_default(),
+    )?
+    else {
+        return Ok(());
+    };
+    let Some(tags) = input(strings.favorite_tags, &favorite.tags.join(", "))? else {
+        return Ok(());
+    };
+    if !command.trim().is_empty() {
+        favorite.command = command.trim().to_owned();
+    }
+    favorite.description = Some(description.trim().to_owned()).filter(|d| !d.is_empty());
+    favorite.tags = favorites::parse_tags(&tags);
+    favorites.save()
+}
+
+fn remove_favorite(name: &str) -> Result<()> {
+    let mut favorites = Favorites::load()?;
+    if favorites.favorites.remove(name).is_none() {
+        bail!("there is no favorite named `{name}`");
+    }
+    favorites.save()
+}
+
+/// Remove the files gptsh keeps on disk for `gptsh clean`: the cache, and with `--all` the
+/// conversation history and the log of executed commands too, after confirming.
+fn clean_files(all: bool) -> Result<()> {
+    let strings = locale::strings(&locale::env_lang().unwrap_or_default());
+    let mut paths: Vec<PathBuf> = cache_dir().into_iter().collect();
+    if all {
+        let history = match Config::load(None)?.history {
+            Some(history) => history.path,
+            None => default_history_path()?,
+        };
+        let kept: Vec<PathBuf> = [Some(history), audit::log_path(), sessions::dir()]
+            .into_iter()
+            .flatten()
+            .filter(|path| path.exists())
+            .collect();
+        if !kept.is_empty() {
+            for path in &kept {
+                println!("{}", contract_tilde(path));
+            }
+            let confirmed = interaction(
+                dialoguer::Confirm::new()
+                    .with_prompt(strings.delete_history)
+                    .default(false)
+                    .interact(),
+            )?
+            .unwrap_or(false);
+            if confirmed {
+                paths.extend(kept);
+            }
+        }
+    }
+
+    let mut freed = 0;
+    for path in paths.iter().filter(|path| path.exists()) {
+        let size = disk_usage(path);
+        let result = if path.is_dir() {
+            std::fs::remove_dir_all(path)
+        } else {
+            std::fs::remove_file(path)
+        };
+        result.with_context(|| format!("failed to remove {}", path.display()))?;
+        let removed = strings
+            .removed_file
+            .replace("{path}", &contract_tilde(path))
+            .replace("{size}", &format_size(size));
+        println!("{removed}");
+        freed += size;
+    }
+    if freed == 0 {
+        println!("{}", strings.nothing_to_remove);
+    } else {
+        println!(
+            "{}",
+            strings.freed_space.replace("{size}", &format_size(freed))
+        );
+    }
+    Ok(())
+}
+
+/// The total size of a file, or of every file in a directory.
+fn disk_usage(path: &Path) -> u64 {
+    let Ok(metadata) = std::fs::symlink_metadata(path) else {
+        return 0;
+    };
+    if !metadata.is_dir() {
+        return metadata.len();
+    }
+    std::fs::read_dir(path)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| disk_usage(&entry.path()))
+        .sum()
+}
+
+/// Format a number of bytes for showing, like `1.5 MiB`.
+fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KiB", "MiB", "GiB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+/// Check the setup and print a report for `gptsh doctor`, erroring if any check fails.
+fn doctor(args: Args) -> Result<()> {
+    let mut report = doctor::Report::default();
+
+    let path = args.config.clone().or_else(config_path);
+    let config = match (path, Config::load(args.config.as_deref())) {
+        (Some(path), Ok(config)) if path.exists() => {
+            report.pass("config", format!("loaded {}", path.display()));
+            config
+        }
+        (_, Ok(config)) => {
+            report.pass("config", "no config file, using the defaults");
+            config
+        }
+        (_, Err(err)) => {
+            report.fail("config", format!("{err:#}"));
+            Config::default()
+        }
+    };
+
+    let endpoint = match Preset::resolve(&args.preset, &config) {
+        Ok(preset) => {
+            let auth_scheme = args
+                .auth_scheme
+                .or(preset.auth_scheme)
+                .unwrap_or(AuthScheme::Bearer);
+            let key_env = preset.key_env.as_deref().unwrap_or("OPENAI_API_KEY");
+            let api_key = match (args.key, auth_scheme) {
+                (_, AuthScheme::None) => None,
+                (Some(key), _) => Some(key),
+                (None, _) => match std::env::var(key_env) {
+                    Ok(key) => Some(key),
+                    Err(_) => {
+                        report.fail("API key", format!("not set in {key_env} or with --key"));
+                        None
+                    }
+                },
+            };
+            match args.base_url.or(preset.base_url) {
+                Some(base_url) => Some((
+                    format!("{}/models", base_url.trim_end_matches('/')),
+                    Endpoint {
+                        url: String::new(),
+                        api: args.api,
+                        auth_header: args
+                            .auth_header
+                            .or(preset.auth_header)
+                            .unwrap_or_else(|| "Authorization".to_owned()),
+                        auth_scheme,
+                        api_key,
+                        insecure: args.insecure,
+                    },
+                )),
+                None => {
+                    report.fail(
+                        "base URL",
+                        format!("the `{}` preset has no base URL", args.preset),
+                    );
+                    None
+                }
+            }
+        }
+        Err(err) => {
+            report.fail("preset", format!("{err:#}"));
+            None
+        }
+    };
+    if let Some((models_url, endpoint)) = &endpoint {
+        // The key is only checked for validity once it's known to be there
+        if endpoint.api_key.is_some() || endpoint.auth_scheme == AuthScheme::None {
+            report.check_api(endpoint, models_url);
+        }
+    }
+
+    if let Some(shell) = &args.shell {
+        platform::set_shell(shell)?;
+    }
+    let shell = platform::shell();
+    match platform::shell_version(shell) {
+        Some(version) => report.pass("shell", format!("{shell} {version}")),
+        None => report.pass("shell", format!("{shell} (version unknown)")),
+    }
+    report.pass(
+        "OS",
+        format!("{} ({})", std::env::consts::OS, std::env::consts::ARCH),
+    );
+
+    if args.insecure {
+        report.fail("TLS", "certificates aren't verified, because of --insecure");
+    }
+
+    if !platform::enable_ansi() {
+        report.fail("colors", "the terminal doesn't support ANSI colors");
+    } else if colored::control::SHOULD_COLORIZE.should_colorize() {
+        report.pass("colors", "enabled");
+    } else {
+        report.pass(
+            "colors",
+            "disabled, since output isn't a terminal or $NO_COLOR is set",
+        );
+    }
+
+    report.print();
+    if !report.passed() {
+        bail!("some checks failed");
+    }
+    Ok(())
+}
+
+/// Let the user choose one of the chat models the API lists, preselecting `current` if it's one.
+fn pick_model(
+    endpoint: &Endpoint,
+    url: &str,
+    current: &str,
+    strings: &locale::Strings,
+) -> Result<String> {
+    let models = models::list(endpoint, url)?;
+    if models.is_empty() {
+        bail!("the API didn't list any chat models");
+    }
+    let default = models.iter().position(|m| m == current).unwrap_or(0);
+    let choice = interaction(
+        dialoguer::Select::new()
+            .with_prompt(strings.pick_model)
+            .items(&models)
+            .default(default)
+            .interact_opt(),
+    )?
+    .flatten()
+    .context("no model was picked")?;
+    Ok(models[choice].clone())
+}
+
+/// Wrap text to `width`, if it's set.
+fn wrapped(text: &str, width: Option<usize>) -> Cow<'_, str> {
+    match width {
+        Some(width) => Cow::Owned(wrap::wrap_text(text, width)),
+        None => Cow::Borrowed(text),
+    }
+}
+
+/// Print a command, wrapping long lines to `width` with a marker at the end of each part that
+/// continues on the next line.
+fn print_command(command: &str, width: Option<usize>) {
+    let Some(width) = width else {
+        println!("{}", command.green());
+        return;
+    };
+    for line in command.lines() {
+        let parts = wrap::wrap_command_line(line, width);
+        let (last, rest) = parts.split_last().unwrap();
+        for part in rest {
+            println!("{}{}", part.green(), wrap::CONTINUATION.dimmed());
+        }
+        println!("{}", last.green());
+    }
+}
+
+/// The line shown above the REPL prompt with --status-bar or /status: the model, working directory,
+/// and what the session has used so far. It's cut off at the terminal width, so it stays one line.
+fn status_line(model: &str, usage: Usage, strings: &locale::Strings) -> String {
+    let dir = std::env::current_dir()
+        .map(|dir| contract_tilde(&dir))
+        .unwrap_or_else(|_| "?".to_owned());
+    let mut parts = vec![
+        model.to_owned(),
+        dir,
+        format!("{} {}", usage.total_tokens, strings.tokens),
+    ];
+    if let Some(cost) = pricing::cost(model, usage) {
+        parts.push(format!("${cost:.4}"));
+    }
+    let line = parts.join(" | ");
+    match wrap::width() {
+        Some(width) if line.chars().count() > width => line.chars().take(width).collect(),
+        _ => line,
+    }
+}
+
+/// Show some text in the user's pager, falling back to printing it if the pager can't be started.
+fn page(text: &str) -> Result<()> {
+    let pager = std::env::var("PAGER").unwrap_or_else(|_| "less -R".to_owned());
+    let mut words = pager.split_whitespace();
+    let child = words.next().and_then(|program| {
+        Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .spawn()
+            .ok()
+    });
+
+    match child {
+        Some(mut child) => {
+            // The pager may exit before reading everything, which isn't an error
+            if let Some(mut stdin) = child.stdin.take() {
+                let _ = writeln!(stdin, "{text}");
+            }
+            child.wait()?;
+        }
+        None => println!("{text}"),
+    }
+
+    Ok(())
 }
@@ -0,0 +1,120 @@
+//! Messages in a conversation, as sent to and received from the chat API.
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Map, Value};
+
+/// Who a message is from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    System,
+    /// Instructions for reasoning models, which take them in place of system messages.
+    Developer,
+    User,
+    Assistant,
+    /// The result of a tool call made by the assistant.
+    Tool,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::System => "system",
+            Self::Developer => "developer",
+            Self::User => "user",
+            Self::Assistant => "assistant",
+            Self::Tool => "tool",
+        }
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "system" => Self::System,
+            "developer" => Self::Developer,
+            "user" => Self::User,
+            "assistant" => Self::Assistant,
+            "tool" => Self::Tool,
+            _ => bail!("unknown message role `{s}`"),
+        })
+    }
+}
+
+/// A message in a conversation. Fields that aren't set are left out when it's serialized.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatMessage {
+    pub role: Role,
+    /// The text of the message, which assistant messages that only call tools don't have.
+    pub content: Option<String>,
+    /// The tool calls requested by an assistant message, as the API returned them.
+    pub tool_calls: Option<Value>,
+    /// The ID of the tool call that a tool message is the result of.
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    /// Create a message with some text.
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: Some(content.into()),
+            tool_calls: None,
+            tool_call_id: None,
+        }
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(Role::System, content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(Role::User, content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(Role::Assistant, content)
+    }
+
+    /// Create a message with the result of the tool call with the ID `tool_call_id`.
+    pub fn tool(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        Self {
+            tool_call_id: Some(tool_call_id.into()),
+            ..Self::new(Role::Tool, content)
+        }
+    }
+
+    /// The text of the message, or an empty string if it doesn't have any.
+    pub fn text(&self) -> &str {
+        self.content.as_deref().unwrap_or_default()
+    }
+
+    pub fn to_json(&self) -> Value {
+        let mut json = Map::new();
+        json.insert("role".to_owned(), json!(self.role.as_str()));
+        // The API requires `content` on every message, even if it's null
+        json.insert("content".to_owned(), json!(self.content));
+        if let Some(tool_calls) = &self.tool_calls {
+            json.insert("tool_calls".to_owned(), tool_calls.clone());
+        }
+        if let Some(tool_call_id) = &self.tool_call_id {
+            json.insert("tool_call_id".to_owned(), json!(tool_call_id));
+        }
+        Value::Object(json)
+    }
+
+    pub fn from_json(json: &Value) -> Result<Self> {
+        let role = json
+            .get("role")
+            .and_then(Value::as_str)
+            .context("message has no `role`")?;
+        let string = |key| match json.get(key) {
+            None | Some(Value::Null) => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(_) => bail!("`{key}` must be a string"),
+        };
+        Ok(Self {
+            role: Role::parse(role)?,
+            content: string("content")?,
+            tool_calls: json.get("tool_calls").filter(|v| !v.is_null()).cloned(),
+            tool_call_id: string("tool_call_id")?,
+        })
+    }
+}
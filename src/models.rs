@@ -0,0 +1,156 @@
+//! Listing the chat models an API offers, for picking one with --pick-model, and the differences
+//! between families of models that requests have to account for.
+//!
+//! Lists are cached for a day per models URL, since they rarely change and fetching them delays
+//! startup.
+
+use crate::{config::cache_dir, transport::Http, ApiError, Endpoint, Role};
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How long a cached model list is used before it's fetched again.
+const CACHE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Parts of model IDs that mark models which can't be used for chat, like embedding, audio, and
+/// image models.
+const NON_CHAT_MARKERS: &[&str] = &[
+    "embed",
+    "whisper",
+    "tts",
+    "transcribe",
+    "realtime",
+    "audio",
+    "dall-e",
+    "image",
+    "moderation",
+    "rerank",
+    "davinci",
+    "babbage",
+];
+
+/// Get the sorted IDs of the chat models listed at `url`, an API's models endpoint (e.g.
+/// `https://api.openai.com/v1/models`), using the cached list if it's recent enough.
+pub fn list(endpoint: &Endpoint, url: &str) -> Result<Vec<String>> {
+    if let Some(models) = cached(url) {
+        return Ok(models);
+    }
+
+    let json = Http::new(endpoint)?
+        .get(url)
+        .context("failed to list the available models")?;
+    let Some(data) = json.get("data").and_then(Value::as_array) else {
+        return Err(ApiError(json).into());
+    };
+    let mut models: Vec<String> = data
+        .iter()
+        .filter_map(|model| model.get("id")?.as_str())
+        .filter(|id| is_chat_model(id))
+        .map(str::to_owned)
+        .collect();
+    models.sort();
+
+    // The cache is only an optimization, so failing to write it isn't an error
+    let _ = store(url, &models);
+    Ok(models)
+}
+
+/// Check whether a model is one of OpenAI's reasoning models, like `o1` or `o3-mini`, which take
+/// instructions as developer messages and don't support some parameters. Provider prefixes like
+/// `openai/` are ignored.
+pub fn is_reasoning_model(model: &str) -> bool {
+    let name = model.rsplit('/').next().unwrap_or(model);
+    let mut chars = name.chars();
+    chars.next() == Some('o') && chars.next().is_some_and(|c| c.is_ascii_digit())
+}
+
+/// Get the role that a model takes the system message in.
+pub fn system_role(model: &str) -> Role {
+    if is_reasoning_model(model) {
+        Role::Developer
+    } else {
+        Role::System
+    }
+}
+
+/// Check whether a model can probably be used for chat, based on its ID.
+fn is_chat_model(id: &str) -> bool {
+    let id = id.to_lowercase();
+    !NON_CHAT_MARKERS.iter().any(|marker| id.contains(marker))
+}
+
+fn cache_path() -> Option<PathBuf> {
+    cache_dir().map(|dir| dir.join("models.json"))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Read the cached model list for `url`, if there is one that hasn't expired.
+fn cached(url: &str) -> Option<Vec<String>> {
+    let text = std::fs::read_to_string(cache_path()?).ok()?;
+    let cache: Value = serde_json::from_str(&text).ok()?;
+    let entry = cache.get(url)?;
+    let fetched_at = entry.get("fetched_at")?.as_u64()?;
+    if now().saturating_sub(fetched_at) > CACHE_TTL.as_secs() {
+        return None;
+    }
+    entry
+        .get("models")?
+        .as_array()?
+        .iter()
+        .map(|model| model.as_str().map(str::to_owned))
+        .collect()
+}
+
+/// Save the model list for `url`, keeping the lists of other APIs.
+fn store(url: &str, models: &[String]) -> Result<()> {
+    let path = cache_path().context("couldn't find a cache directory")?;
+    let mut cache = std::fs::read_to_string(&path)
+        .ok()
+        .and_then(|text| serde_json::from_str(&text).ok())
+        .filter(Value::is_object)
+        .unwrap_or_else(|| json!({}));
+    cache[url] = json!({"fetched_at": now(), "models": models});
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, cache.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognizes_reasoning_models() {
+        for model in ["o1", "o1-mini", "o3-mini", "o4-mini", "openai/o4-mini"] {
+            assert!(is_reasoning_model(model), "{model}");
+            assert_eq!(system_role(model), Role::Developer, "{model}");
+        }
+    }
+
+    #[test]
+    fn doesnt_mistake_other_models_for_reasoning_models() {
+        for model in [
+            "gpt-4o",
+            "gpt-4o-mini",
+            "openai/gpt-4o",
+            "gpt-o1-like",
+            "omni",
+            "",
+        ] {
+            assert!(!is_reasoning_model(model), "{model}");
+            assert_eq!(system_role(model), Role::System, "{model}");
+        }
+    }
+}
@@ -0,0 +1,246 @@
+//! Detection of the shell and privileges of the environment gptsh is running in.
+
+use anyhow::{bail, Result};
+use std::{
+    collections::HashMap,
+    path::Path,
+    process::{Command, Stdio},
+    sync::{Mutex, OnceLock},
+};
+
+/// The shell commands are generated for and run in, chosen on first use.
+static SHELL: OnceLock<String> = OnceLock::new();
+/// The versions of the shells that have been checked, detected on first use, since starting a
+/// shell to ask its version is slow compared to everything else at startup.
+static SHELL_VERSIONS: OnceLock<Mutex<HashMap<String, Option<String>>>> = OnceLock::new();
+
+/// Get the name of the shell: the one chosen with [`set_shell`], or otherwise one based on the OS.
+pub fn shell() -> &'static str {
+    SHELL.get_or_init(|| default_shell(std::env::consts::OS, on_path).to_owned())
+}
+
+/// Use a specific shell instead of the OS's default. Fails if the shell has already been used.
+pub fn set_shell(shell: &str) -> Result<()> {
+    if SHELL.set(shell.to_owned()).is_err() {
+        bail!("the shell must be set before it is used");
+    }
+    Ok(())
+}
+
+/// Get the version of a shell, like `5.2.15`, or `None` if it couldn't be detected. The version of
+/// the shell from [`shell`] can be overridden with [`set_shell_version`].
+pub fn shell_version(shell: &str) -> Option<String> {
+    let versions = SHELL_VERSIONS.get_or_init(Default::default);
+    versions
+        .lock()
+        .unwrap()
+        .entry(shell.to_owned())
+        .or_insert_with(|| detect_version(shell))
+        .clone()
+}
+
+/// Use a specific version for the shell from [`shell`] instead of detecting it.
+pub fn set_shell_version(version: &str) {
+    let versions = SHELL_VERSIONS.get_or_init(Default::default);
+    versions
+        .lock()
+        .unwrap()
+        .insert(shell().to_owned(), Some(version.to_owned()));
+}
+
+/// Ask a shell for its version.
+fn detect_version(shell: &str) -> Option<String> {
+    let mut cmd = Command::new(shell);
+    if command_flag(shell) == "-Command" {
+        cmd.args([
+            "-NoProfile",
+            "-Command",
+            "$PSVersionTable.PSVersion.ToString()",
+        ]);
+    } else if command_flag(shell) == "-c" {
+        cmd.arg("--version");
+    } else {
+        // cmd only reports the version of Windows
+        return None;
+    }
+    let output = cmd
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_version(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// Find the version number in the first line of a shell's version output, like
+/// `GNU bash, version 3.2.57(1)-release` or `zsh 5.9 (x86_64-apple-darwin22.0)`.
+fn parse_version(output: &str) -> Option<String> {
+    let line = output.lines().next()?;
+    line.split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .find(|word| word.contains('.') && word.starts_with(|c: char| c.is_ascii_digit()))
+        .map(|version| version.trim_end_matches('.').to_owned())
+}
+
+/// Pick the shell for an OS, checking whether programs are installed with `installed`.
+fn default_shell(os: &str, installed: impl Fn(&str) -> bool) -> &'static str {
+    match os {
+        // PowerShell 7 is installed alongside Windows PowerShell, so prefer it when it's there
+        "windows" if installed("pwsh") => "pwsh",
+        "windows" => "powershell",
+        "macos" => "zsh",
+        _ => "bash", // Will be valid in most cases
+    }
+}
+
+/// Get the flag that makes a shell run the command given as the next argument.
+pub fn command_flag(shell: &str) -> &'static str {
+    let name = Path::new(shell)
+        .file_stem()
+        .and_then(|name| name.to_str())
+        .unwrap_or(shell)
+        .to_ascii_lowercase();
+    match name.as_str() {
+        "powershell" | "pwsh" => "-Command",
+        "cmd" => "/C",
+        _ => "-c",
+    }
+}
+
+/// Check whether a program can be found in `$PATH`.
+fn on_path(program: &str) -> bool {
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+    std::env::split_paths(&path).any(|dir| {
+        let file = dir.join(program);
+        file.is_file()
+            || file
+                .with_extension(std::env::consts::EXE_EXTENSION)
+                .is_file()
+    })
+}
+
+/// Make the terminal interpret ANSI escape codes, returning whether it will. Windows consoles only
+/// do once virtual terminal processing is enabled, and ones too old to support it show the codes
+/// as text.
+pub fn enable_ansi() -> bool {
+    #[cfg(windows)]
+    {
+        colored::control::set_virtual_terminal(true).is_ok()
+    }
+    #[cfg(not(windows))]
+    {
+        true
+    }
+}
+
+/// Exit quietly when stdout is closed early, as in `gptsh ... | head`, like other Unix programs.
+/// Rust ignores SIGPIPE, so writing to a closed pipe would otherwise make `println!` panic.
+pub fn default_sigpipe() {
+    #[cfg(unix)]
+    // SAFETY: Setting a signal to its default disposition has no memory safety preconditions, and
+    // this runs at startup before any other threads exist.
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+}
+
+/// Check whether gptsh is running as root (Unix) or as an administrator (Windows).
+pub fn is_elevated() -> bool {
+    #[cfg(unix)]
+    {
+        // SAFETY: geteuid has no preconditions and cannot fail
+        unsafe { libc::geteuid() == 0 }
+    }
+    #[cfg(windows)]
+    {
+        // `net session` only succeeds in an elevated prompt
+        std::process::Command::new("net")
+            .arg("session")
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false)
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gets_the_command_flag_for_windows_shells() {
+        assert_eq!(command_flag("cmd"), "/C");
+        assert_eq!(command_flag("cmd.exe"), "/C");
+        assert_eq!(command_flag("CMD.EXE"), "/C");
+        assert_eq!(command_flag("powershell"), "-Command");
+        assert_eq!(command_flag("PowerShell.exe"), "-Command");
+        assert_eq!(command_flag("pwsh"), "-Command");
+        assert_eq!(command_flag("/usr/local/bin/pwsh"), "-Command");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn gets_the_command_flag_for_windows_paths() {
+        assert_eq!(command_flag(r"C:\Windows\System32\cmd.exe"), "/C");
+        assert_eq!(
+            command_flag(r"C:\Program Files\PowerShell\7\pwsh.exe"),
+            "-Command"
+        );
+    }
+
+    #[test]
+    fn gets_the_command_flag_for_unix_shells() {
+        for shell in [
+            "sh",
+            "bash",
+            "zsh",
+            "fish",
+            "/bin/bash",
+            "/opt/homebrew/bin/fish",
+        ] {
+            assert_eq!(command_flag(shell), "-c", "{shell}");
+        }
+    }
+
+    #[test]
+    fn prefers_powershell_7_on_windows() {
+        assert_eq!(
+            default_shell("windows", |program| program == "pwsh"),
+            "pwsh"
+        );
+        assert_eq!(default_shell("windows", |_| false), "powershell");
+    }
+
+    #[test]
+    fn picks_the_default_shell_of_unix_systems() {
+        assert_eq!(default_shell("macos", |_| true), "zsh");
+        assert_eq!(default_shell("linux", |_| true), "bash");
+        assert_eq!(default_shell("linux", |_| false), "bash");
+        assert_eq!(default_shell("freebsd", |_| false), "bash");
+    }
+
+    #[test]
+    fn finds_shell_versions() {
+        assert_eq!(
+            parse_version("GNU bash, version 3.2.57(1)-release (arm64-apple-darwin23)"),
+            Some("3.2.57".to_owned())
+        );
+        assert_eq!(
+            parse_version("zsh 5.9 (x86_64-apple-darwin22.0)\nmore"),
+            Some("5.9".to_owned())
+        );
+        assert_eq!(
+            parse_version("fish, version 3.7.1."),
+            Some("3.7.1".to_owned())
+        );
+        assert_eq!(parse_version("no version here"), None);
+    }
+}
@@ -0,0 +1,153 @@
+//! Provider presets, which bundle the endpoint and auth defaults of known OpenAI-compatible APIs.
+
+use crate::config::{get_str, Config};
+use anyhow::{bail, Result};
+use serde_json::Value;
+
+/// Names of the built-in presets.
+pub const BUILTIN_PRESETS: &[&str] = &["openai", "azure", "ollama", "openrouter", "groq"];
+
+/// How the API key is sent in the auth header.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum AuthScheme {
+    /// `<header>: Bearer <key>`
+    Bearer,
+    /// `<header>: <key>`
+    Raw,
+    /// No auth header is sent and no API key is required.
+    None,
+}
+
+impl AuthScheme {
+    /// Parse `bearer`, `raw`, or `none`.
+    pub fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "bearer" => Self::Bearer,
+            "raw" => Self::Raw,
+            "none" => Self::None,
+            _ => bail!("unknown auth scheme `{s}` (expected bearer, raw or none)"),
+        })
+    }
+}
+
+/// Which API the endpoint speaks.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Api {
+    /// Chat Completions (`/chat/completions`), which almost every provider supports.
+    #[default]
+    Chat,
+    /// OpenAI's Responses API (`/responses`).
+    Responses,
+}
+
+impl Api {
+    /// Parse `chat` or `responses`.
+    pub fn parse(s: &str) -> Result<Self> {
+        Ok(match s {
+            "chat" => Self::Chat,
+            "responses" => Self::Responses,
+            _ => bail!("unknown API `{s}` (expected chat or responses)"),
+        })
+    }
+}
+
+/// Endpoint and auth defaults for a provider. Unset fields fall back to the OpenAI defaults.
+#[derive(Clone, Default)]
+pub struct Preset {
+    /// Base URL of the API, e.g. `https://api.openai.com/v1`.
+    pub base_url: Option<String>,
+    /// Path of the chat completions endpoint, appended to the base URL.
+    pub chat_path: Option<String>,
+    /// Name of the header the API key is sent in.
+    pub auth_header: Option<String>,
+    pub auth_scheme: Option<AuthScheme>,
+    /// Environment variable the API key is read from.
+    pub key_env: Option<String>,
+    /// Model to use when none is specified.
+    pub model: Option<String>,
+}
+
+impl Preset {
+    /// Look up a preset by name. Presets in the config take precedence over built-in ones, with
+    /// any fields they leave unset falling back to the built-in preset of the same name.
+    pub fn resolve(name: &str, config: &Config) -> Result<Self> {
+        let builtin = Self::builtin(name);
+        match (config.presets.get(name), builtin) {
+            (Some(preset), Some(builtin)) => Ok(preset.clone().or(builtin)),
+            (Some(preset), None) => Ok(preset.clone()),
+            (None, Some(builtin)) => Ok(builtin),
+            (None, None) => bail!(
+                "unknown preset `{name}` (built-in presets are {})",
+                BUILTIN_PRESETS.join(", ")
+            ),
+        }
+    }
+
+    /// Get a built-in preset by name.
+    fn builtin(name: &str) -> Option<Self> {
+        let s = |s: &str| Some(s.to_owned());
+        Some(match name {
+            "openai" => Self {
+                base_url: s("https://api.openai.com/v1"),
+                ..Default::default()
+            },
+            // Azure URLs are specific to the resource and deployment, so the base URL must be
+            // supplied with --base-url, e.g. https://<resource>.openai.azure.com/openai/deployments/<deployment>
+            "azure" => Self {
+                chat_path: s("/chat/completions?api-version=2024-06-01"),
+                auth_header: s("api-key"),
+                auth_scheme: Some(AuthScheme::Raw),
+                key_env: s("AZURE_OPENAI_API_KEY"),
+                ..Default::default()
+            },
+            "ollama" => Self {
+                base_url: s("http://localhost:11434/v1"),
+                auth_scheme: Some(AuthScheme::None),
+                model: s("llama3"),
+                ..Default::default()
+            },
+            "openrouter" => Self {
+                base_url: s("https://openrouter.ai/api/v1"),
+                key_env: s("OPENROUTER_API_KEY"),
+                model: s("openai/gpt-3.5-turbo"),
+                ..Default::default()
+            },
+            "groq" => Self {
+                base_url: s("https://api.groq.com/openai/v1"),
+                key_env: s("GROQ_API_KEY"),
+                model: s("llama3-8b-8192"),
+                ..Default::default()
+            },
+            _ => return None,
+        })
+    }
+
+    /// Parse a preset from its JSON representation in the config file.
+    pub fn from_json(json: &Value) -> Result<Self> {
+        if !json.is_object() {
+            bail!("preset must be an object");
+        }
+        Ok(Self {
+            base_url: get_str(json, "base_url")?,
+            chat_path: get_str(json, "chat_path")?,
+            auth_header: get_str(json, "auth_header")?,
+            auth_scheme: get_str(json, "auth_scheme")?
+                .map(|s| AuthScheme::parse(&s))
+                .transpose()?,
+            key_env: get_str(json, "key_env")?,
+            model: get_str(json, "model")?,
+        })
+    }
+
+    /// Fill any unset fields from `other`.
+    fn or(self, other: Self) -> Self {
+        Self {
+            base_url: self.base_url.or(other.base_url),
+            chat_path: self.chat_path.or(other.chat_path),
+            auth_header: self.auth_header.or(other.auth_header),
+            auth_scheme: self.auth_scheme.or(other.auth_scheme),
+            key_env: self.key_env.or(other.key_env),
+            model: self.model.or(other.model),
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Best-effort previews of the changes a command would make to a file.
+//!
+//! A preview is made by running the command against a temporary copy of the file it edits, then
+//! diffing the copy against the original. Only these command shapes are recognized, and only when
+//! they edit a single existing file that's written literally (unquoted) in the command:
+//!
+//! - In-place edits with `sed -i ... <file>` or `perl -pi ... <file>`
+//! - Overwriting redirects, `... > <file>`
+//! - Appending redirects, `... >> <file>`
+//!
+//! Commands that chain several commands (`;`, `&&`, `||`) or use command substitution are never
+//! previewed, and neither is anything that doesn't run in a POSIX-style shell.
+
+use crate::{
+    exec::{ensure_enabled, shell_command},
+    platform::{command_flag, shell},
+};
+use anyhow::{bail, Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+/// Files with more lines than this (before and after the edit, multiplied) aren't diffed, to keep
+/// the diff from taking too long.
+const MAX_DIFF_SIZE: usize = 4_000_000;
+
+/// A line in a diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Unchanged(String),
+    Added(String),
+    Removed(String),
+}
+
+/// The changes a command would make to a file.
+pub struct Preview {
+    /// The file the command edits.
+    pub path: PathBuf,
+    /// The diff of the file, or `None` if it's too large to diff.
+    pub diff: Option<Vec<DiffLine>>,
+}
+
+/// Preview the changes a command would make, or return `None` if it isn't a supported shape.
+pub fn preview(command: &str) -> Option<Result<Preview>> {
+    // Only POSIX-style shells take `-c`
+    if command_flag(shell()) != "-c"
+        || ["&&", "||", ";", "`", "$("]
+            .iter()
+            .any(|s| command.contains(s))
+    {
+        return None;
+    }
+
+    let target = edit_target(command)?;
+    if !Path::new(&target).is_file() {
+        return None;
+    }
+    Some(run_preview(command, &target))
+}
+
+/// Find the file that a command edits, as written in the command.
+fn edit_target(command: &str) -> Option<String> {
+    let words = shell_words::split(command).ok()?;
+
+    // Redirects, either as a separate word (`> file`) or attached to the file name (`>file`)
+    if let Some(i) = words.iter().rposition(|w| w == ">" || w == ">>") {
+        return words.get(i + 1).cloned();
+    }
+    if let Some(word) = words.iter().rev().find(|w| w.starts_with('>')) {
+        return Some(word.trim_start_matches('>').to_owned()).filter(|w| !w.is_empty());
+    }
+
+    // In-place edits, where the file is the last argument
+    let in_place = match words.first()?.as_str() {
+        "sed" => words
+            .iter()
+            .any(|w| w.starts_with("-i") || w == "--in-place"),
+        "perl" => words
+            .iter()
+            .any(|w| w.starts_with('-') && !w.starts_with("--") && w.contains('i')),
+        _ => false,
+    };
+    if in_place && words.len() > 2 {
+        return words.last().cloned();
+    }
+
+    None
+}
+
+/// Run the command against a temporary copy of `target` and diff the result.
+fn run_preview(command: &str, target: &str) -> Result<Preview> {
+    // The target must appear literally so it can be swapped for the copy
+    let Some(index) = command.rfind(target) else {
+        bail!("can't preview a command that quotes the file it edits");
+    };
+
+    let path = PathBuf::from(target);
+    let dir = tempfile::tempdir()?;
+    let copy = dir
+        .path()
+        .join(path.file_name().context("the edited file has no name")?);
+    fs::copy(&path, &copy)?;
+
+    let copy_arg = shell_words::quote(copy.to_str().context("temporary path isn't UTF-8")?);
+    let preview_command = format!(
+        "{}{copy_arg}{}",
+        &command[..index],
+        &command[index + target.len()..]
+    );
+    ensure_enabled()?;
+    let status = shell_command(&preview_command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+    if !status.success() {
+        bail!("the command failed when run against a copy of the file ({status})");
+    }
+
+    let before = fs::read_to_string(&path)?;
+    let after = fs::read_to_string(&copy)?;
+    Ok(Preview {
+        path,
+        diff: diff(&before, &after),
+    })
+}
+
+/// Diff two texts line by line, using the longest common subsequence of lines.
+fn diff(before: &str, after: &str) -> Option<Vec<DiffLine>> {
+    let a: Vec<&str> = before.lines().collect();
+    let b: Vec<&str> = after.lines().collect();
+    if a.len().saturating_mul(b.len()) > MAX_DIFF_SIZE {
+        return None;
+    }
+
+    // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            lines.push(DiffLine::Unchanged(a[i].to_owned()));
+            i += 1;
+            j += 1;
+        } else if i < a.len() && (j == b.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(DiffLine::Removed(a[i].to_owned()));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Added(b[j].to_owned()));
+            j += 1;
+        }
+    }
+    Some(lines)
+}
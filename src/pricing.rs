@@ -0,0 +1,34 @@
+//! Prices of OpenAI models, for estimating what a session costs.
+//!
+//! Prices change, and other providers' models aren't listed, so costs are only estimates.
+
+use crate::Usage;
+
+/// US dollars per million input and output tokens, keyed by model name prefix. More specific
+/// prefixes come first, since the first match is used.
+const PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4.1-nano", 0.10, 0.40),
+    ("gpt-4.1-mini", 0.40, 1.60),
+    ("gpt-4.1", 2.00, 8.00),
+    ("gpt-4-turbo", 10.00, 30.00),
+    ("gpt-4", 30.00, 60.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("o1-mini", 1.10, 4.40),
+    ("o1", 15.00, 60.00),
+    ("o3-mini", 1.10, 4.40),
+    ("o3", 2.00, 8.00),
+    ("o4-mini", 1.10, 4.40),
+];
+
+/// Estimate the cost of some usage of a model in US dollars, if the model's price is known.
+pub fn cost(model: &str, usage: Usage) -> Option<f64> {
+    let &(_, input, output) = PRICES
+        .iter()
+        .find(|(prefix, ..)| model.starts_with(prefix))?;
+    Some(
+        (usage.prompt_tokens as f64 * input + usage.completion_tokens as f64 * output)
+            / 1_000_000.0,
+    )
+}
@@ -0,0 +1,114 @@
+//! An indicator of how long a command has been running, like `running... 12s`, shown on stderr
+//! once a command has run for a couple of seconds, so that a long one doesn't look stuck.
+//!
+//! The indicator shares the terminal with the command, so it's only shown for commands that
+//! aren't likely to be interactive: ones that don't run an editor, pager, REPL, or anything else
+//! from [`INTERACTIVE_PROGRAMS`]. That's a guess based on the program names, so a command that
+//! prompts for input, like a script, can still have its prompt drawn over, and output that's
+//! shorter than the indicator can leave the end of it on the screen.
+
+use crate::allowlist;
+use dialoguer::console::{style, Term};
+use std::{
+    io::IsTerminal,
+    path::Path,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// How long a command runs before the indicator is shown.
+const DELAY: Duration = Duration::from_secs(2);
+/// How often the elapsed time is updated.
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// Programs that take over the terminal or read from it, which the indicator would get in the way
+/// of.
+pub const INTERACTIVE_PROGRAMS: &[&str] = &[
+    "vi", "vim", "nvim", "nano", "emacs", "micro", "less", "more", "most", "man", "top", "htop",
+    "btop", "watch", "tmux", "screen", "ssh", "telnet", "ftp", "sftp", "mysql", "psql", "sqlite3",
+    "irb", "ghci", "sudo", "su", "passwd", "read", "select", "fzf", "apt", "apt-get", "dnf", "yum",
+    "pacman",
+];
+
+/// Check whether a command would probably interact with the terminal. Commands that can't be
+/// parsed are assumed to.
+pub fn is_interactive(command: &str) -> bool {
+    let Ok(programs) = allowlist::programs(command) else {
+        return true;
+    };
+    programs.iter().any(|program| {
+        let name = Path::new(program)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or(program);
+        INTERACTIVE_PROGRAMS.contains(&name)
+    })
+}
+
+/// The indicator for a running command, which is cleared when it's dropped.
+pub struct Progress {
+    /// Set when the command has finished.
+    done: Arc<(Mutex<bool>, Condvar)>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Progress {
+    /// Start showing the indicator for a command, labeled with `label`, unless stderr isn't a
+    /// terminal or the command looks interactive.
+    pub fn start(command: &str, label: &str) -> Option<Self> {
+        if !std::io::stderr().is_terminal() || is_interactive(command) {
+            return None;
+        }
+        let done = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread = {
+            let done = Arc::clone(&done);
+            let label = label.to_owned();
+            thread::spawn(move || show(&done, &label))
+        };
+        Some(Self {
+            done,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        let (done, finished) = &*self.done;
+        *done.lock().unwrap() = true;
+        finished.notify_all();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Update the indicator until `done` is set, then clear it.
+fn show(done: &(Mutex<bool>, Condvar), label: &str) {
+    let (done, finished) = done;
+    let term = Term::stderr();
+    let start = Instant::now();
+    let mut wait = DELAY;
+    let mut shown = false;
+    let mut guard = done.lock().unwrap();
+    loop {
+        guard = finished
+            .wait_timeout_while(guard, wait, |done| !*done)
+            .unwrap()
+            .0;
+        if *guard {
+            break;
+        }
+        // The cursor is left at the start of the line, so that anything the command writes
+        // replaces the indicator instead of being added to the end of it
+        let text = format!("{label}... {}s", start.elapsed().as_secs());
+        let _ = term.clear_line();
+        let _ = term.write_str(&format!("{}\r", style(text).dim()));
+        shown = true;
+        wait = INTERVAL;
+    }
+    if shown {
+        let _ = term.clear_line();
+    }
+}
@@ -0,0 +1,156 @@
+//! Construction of the system message that instructs the model how to respond.
+
+use crate::platform::shell_version;
+use anyhow::{Context, Result};
+use std::{fmt::Write, fs, path::Path};
+
+/// Which of the stricter command-generation rules in the system message to relax.
+#[derive(Clone, Copy, Default)]
+pub struct PromptRules {
+    /// Allow commands to span multiple lines.
+    pub multiline_commands: bool,
+    /// Allow comments in commands.
+    pub comments: bool,
+    /// Allow a short explanation after the command, marked with [explanation].
+    pub explanations: bool,
+    /// Give commands as JSON with a one-line reason for choosing them, for showing before the
+    /// command.
+    pub reasons: bool,
+    /// Run commands by calling the `run_shell_command` tool instead of marking them with
+    /// `[shell]`.
+    pub tools: bool,
+}
+
+/// Creates a system message which provides the instructions that determine the model's behavior,
+/// for generating commands for `shell`.
+pub fn system_message(shell: &str, rules: PromptRules) -> String {
+    let os = std::env::consts::OS;
+    let version = shell_version(shell);
+    // Written into a single preallocated buffer, since the full message is a few kilobytes
+    let mut message = String::with_capacity(4096);
+    let _ = write!(message, "You are both an AI assistant and a natural language to {shell} command translation engine on {os}.
+If the prompt is asking a general question, you should respond with a helpful and accurate answer as you would normally.
+If you don't understand the prompt, simply explain why.
+
+If the prompt is something that can be accomplished with a shell command, such as creating directories/files, changing directories, downloading files, sending requests, changing OS settings, running programs, editing files, etc., then you should output a single {shell} command that can accomplish the task, preceeded by \"[shell]\" to mark it as a shell command.
+
+Here are the rules for generating {shell} commands:
+");
+
+    macro_rules! rule {
+        ($($arg:tt)*) => {
+            let _ = writeln!(message, $($arg)*);
+        };
+    }
+
+    if rules.multiline_commands {
+        rule!("You may split the command across multiple lines if it makes the command more readable.");
+    } else {
+        rule!("Always use only one line; you can always chain multiple commands on a single line.");
+        rule!("Never use multiple commands on separate lines. Always use semicolons or \"&&\" to chain multiple commands on a single line.");
+    }
+    if rules.comments {
+        rule!("You may use {shell} comments to explain parts of the command.");
+    } else {
+        rule!("Never use comments.");
+    }
+    if rules.explanations {
+        rule!("You may follow the command with a brief explanation of what it does, separated from the command by a line containing only \"[explanation]\".");
+    } else {
+        rule!("Never put introductory statements such as \"Here's a command to do ...\" or \"To do this, run ...\", etc. Just put the command itself and nothing else (except for the \"[shell]\" tag).");
+    }
+    rule!("Never use placeholder file paths like \"C:\\Path\\To\\Directory\\\" or \"/path/to/file\". Instead, assume that paths are relative to the current working directory.");
+    rule!("Always use valid syntax for {shell}.");
+    if let Some(version) = version {
+        rule!("The installed version of {shell} is {version}, so never use features that it doesn't support.");
+    }
+    rule!("Always make sure the command will work properly on {os}.");
+    rule!("Always use file paths that are relative to the current working directory unless otherwise specified.");
+    rule!("Always assume that the command will be executed as-is and without modification (except that the \"[shell]\" tag at the beginning will be removed before executing).");
+    if !rules.explanations {
+        rule!("Never add unnecessary text or details to the answer.");
+    }
+    rule!("Always use plain text; no html, markdown, or other styled or colored text.");
+    if !rules.explanations {
+        rule!("Never paraphrase the question/prompt or restate the prompt in the answer; output only the shell command itself (and the preceeding \"[shell]\" tag).");
+    }
+    rule!("Always make the command as concise and optimized as possible.");
+    if rules.tools {
+        rule!("Instead of outputting commands with the \"[shell]\" tag, run them by calling the run_shell_command tool. You will get the exit code and output of each command, which you can use to continue with the task or answer the question. This takes precedence over the other rules about what to output.");
+    }
+    if rules.reasons {
+        rule!("Instead of the bare command, always output a single-line JSON object after the \"[shell]\" tag, with the command in \"command\" and a one-line justification of why it accomplishes the task in \"reason\", like [shell] {{\"command\": \"...\", \"reason\": \"...\"}}. This takes precedence over the other rules about what to output.");
+    }
+
+    message.push_str("\nIt is extremely important that you never break these rules under any circumstances, with absolutely no exceptions whatsoever.");
+    message
+}
+
+/// Creates a system message asking for a one-line prediction of the side effects of a `shell`
+/// command, for showing before the command is confirmed.
+pub fn effect_message(shell: &str) -> String {
+    let os = std::env::consts::OS;
+    format!("You predict the side effects of {shell} commands on {os}. The user will give you a command. Reply with a single short line saying what running it would change: files or directories created, modified, or deleted, network requests, processes started or stopped, and anything that can't be undone. If it only reads information, say that it has no side effects. Never repeat the command, and use plain text.")
+}
+
+/// Creates a system message asking for a numbered plan of the steps for a task in `shell`, in
+/// prose, for the user to approve with `--plan` before any commands are generated.
+pub fn plan_message(shell: &str) -> String {
+    let os = std::env::consts::OS;
+    format!("You plan how to accomplish tasks with {shell} commands on {os}. The user will describe a task. Reply with a short numbered list of the steps needed to accomplish it, one line per step, saying what each step does in plain words. Don't write the commands themselves, and don't add anything before or after the list. If the task can't be done with shell commands, explain why instead.")
+}
+
+/// Creates a system message asking for a plain-text explanation of a `shell` command, for
+/// `--explain-command`, which never runs it.
+pub fn explain_message(shell: &str) -> String {
+    let os = std::env::consts::OS;
+    format!("You explain {shell} commands on {os}. The user will give you a command. Explain what it does in plain words: what each program, option, and argument means, how the parts are connected, and what running it would change. Point out anything dangerous or surprising. Never suggest running it or give other commands unless it's broken, and use plain text instead of markdown.")
+}
+
+/// The prompt for generating the commands for a plan the user approved with `--plan`.
+pub fn follow_plan(prompt: &str, plan: &str) -> String {
+    format!("{prompt}\n\nThe user approved this plan for it. Give the commands that carry it out, each with its own \"[shell]\" tag, in the same order as the steps:\n{plan}")
+}
+
+/// The system message for requests with `--schema`, which ask for data in a given structure
+/// instead of a command.
+pub const EXTRACTION_MESSAGE: &str = "You are an AI assistant that answers with structured data. Reply to the user with a single JSON value that matches the response format you were given, and nothing else: no markdown, code fences, or explanations.";
+
+/// Read the prompt fragments in a directory for `--system-dir`: every `.txt` and `.md` file,
+/// sorted by file name and separated by blank lines. Prefixing the names with numbers, like
+/// `10-safety.md`, makes the order explicit.
+pub fn read_fragments(dir: &Path) -> Result<String> {
+    let entries = fs::read_dir(dir).with_context(|| {
+        format!(
+            "failed to read the system message directory {}",
+            dir.display()
+        )
+    })?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let is_fragment = path
+            .extension()
+            .is_some_and(|extension| extension == "txt" || extension == "md");
+        if is_fragment && path.is_file() {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+
+    let mut fragments = Vec::with_capacity(paths.len());
+    for path in paths {
+        let text = fs::read_to_string(&path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        fragments.push(text.trim().to_owned());
+    }
+    Ok(fragments.join("\n\n"))
+}
+
+/// Fill in the `{shell}` and `{os}` placeholders in prompt fragments, leaving any other braces as
+/// they are.
+pub fn render_fragments(fragments: &str, shell: &str) -> String {
+    fragments
+        .replace("{shell}", shell)
+        .replace("{os}", std::env::consts::OS)
+}
@@ -0,0 +1,200 @@
+//! A quick check for commands with obviously broken quoting, which would fail in the shell.
+//!
+//! This is a small tokenizer rather than a parser: it only tracks quotes, escapes, and brackets,
+//! so it can't tell whether a command is valid, only when it definitely isn't.
+
+use crate::platform::command_flag;
+use std::fmt;
+
+/// Something in a command that's left open or closed without being opened.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuotingError {
+    /// A quote (`'`, `"`, or `` ` ``) that's never closed.
+    UnclosedQuote(char),
+    /// A `(`, `[`, or `{` that's never closed.
+    UnclosedBracket(char),
+    /// A `)`, `]`, or `}` without a matching opening bracket.
+    UnmatchedBracket(char),
+}
+
+impl fmt::Display for QuotingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnclosedQuote(c) => write!(f, "unclosed {c} quote"),
+            Self::UnclosedBracket(c) => write!(f, "unclosed {c}"),
+            Self::UnmatchedBracket(c) => write!(f, "unmatched {c}"),
+        }
+    }
+}
+
+/// Check a command for broken quoting in the syntax of `shell`.
+pub fn check(command: &str, shell: &str) -> Option<QuotingError> {
+    match command_flag(shell) {
+        "-c" => tokenize(command, Syntax::POSIX),
+        "-Command" => tokenize(command, Syntax::POWERSHELL),
+        // cmd's quoting is too loose to check
+        _ => None,
+    }
+}
+
+/// Check whether a POSIX shell command leaves a quote or bracket open, so that it continues on the
+/// next line.
+pub(crate) fn is_open(command: &str) -> bool {
+    matches!(
+        tokenize(command, Syntax::POSIX),
+        Some(QuotingError::UnclosedQuote(_) | QuotingError::UnclosedBracket(_))
+    )
+}
+
+/// The quoting rules of a family of shells.
+struct Syntax {
+    /// The character that escapes the next one, outside single quotes.
+    escape: char,
+    /// Whether backticks quote command substitutions.
+    backticks: bool,
+    /// Whether `{` and `}` are brackets. In POSIX shells they're also used unbalanced, as in
+    /// `find -exec ... {} \;` and `${var}`, so they're only counted in PowerShell.
+    braces: bool,
+}
+
+impl Syntax {
+    const POSIX: Self = Self {
+        escape: '\\',
+        backticks: true,
+        braces: false,
+    };
+    const POWERSHELL: Self = Self {
+        escape: '`',
+        backticks: false,
+        braces: true,
+    };
+}
+
+fn tokenize(command: &str, syntax: Syntax) -> Option<QuotingError> {
+    let mut brackets = Vec::new();
+    let mut quote = None;
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            // Nothing is special inside single quotes, not even the escape character
+            (Some('\''), '\'') => quote = None,
+            (Some('\''), _) => {}
+            (_, c) if c == syntax.escape => {
+                chars.next();
+            }
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '`') if syntax.backticks => quote = Some(c),
+            // A `#` starting a word begins a comment, where nothing needs to be balanced
+            (None, '#') if at_word_start(command, chars.as_str(), c) => break,
+            (None, '(' | '[') => brackets.push(c),
+            (None, '{') if syntax.braces => brackets.push(c),
+            (None, ')' | ']' | '}') if c != '}' || syntax.braces => {
+                if let Err(err) = close(&mut brackets, c) {
+                    return Some(err);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(q) = quote {
+        return Some(QuotingError::UnclosedQuote(q));
+    }
+    brackets.pop().map(QuotingError::UnclosedBracket)
+}
+
+/// Pop the bracket that `c` closes, erroring if it doesn't match. A `)` without any open brackets
+/// is allowed, since `case` patterns end with one.
+fn close(brackets: &mut Vec<char>, c: char) -> Result<(), QuotingError> {
+    let open = match c {
+        ')' => '(',
+        ']' => '[',
+        _ => '{',
+    };
+    match brackets.last() {
+        Some(&last) if last == open => {
+            brackets.pop();
+            Ok(())
+        }
+        None if c == ')' => Ok(()),
+        _ => Err(QuotingError::UnmatchedBracket(c)),
+    }
+}
+
+/// Check whether the character just read (with `rest` after it) starts a word in `command`.
+fn at_word_start(command: &str, rest: &str, c: char) -> bool {
+    let start = command.len() - rest.len() - c.len_utf8();
+    command[..start]
+        .chars()
+        .next_back()
+        .is_none_or(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use QuotingError::*;
+
+    fn posix(command: &str) -> Option<QuotingError> {
+        tokenize(command, Syntax::POSIX)
+    }
+
+    fn powershell(command: &str) -> Option<QuotingError> {
+        tokenize(command, Syntax::POWERSHELL)
+    }
+
+    #[test]
+    fn accepts_balanced_quotes() {
+        assert_eq!(posix("echo 'it''s' \"a b\" `date`"), None);
+        assert_eq!(posix("echo \"it's\" 'say \"hi\"'"), None);
+        assert_eq!(powershell("Write-Host \"a `\"b`\" c\" 'd'"), None);
+    }
+
+    #[test]
+    fn finds_unterminated_quotes() {
+        assert_eq!(posix("echo 'it"), Some(UnclosedQuote('\'')));
+        assert_eq!(posix("echo \"a b"), Some(UnclosedQuote('"')));
+        assert_eq!(posix("echo `date"), Some(UnclosedQuote('`')));
+        assert_eq!(powershell("Write-Host \"a"), Some(UnclosedQuote('"')));
+    }
+
+    #[test]
+    fn follows_escapes() {
+        assert_eq!(posix("echo it\\'s"), None);
+        assert_eq!(posix("echo \"a \\\" b\""), None);
+        assert_eq!(posix("echo \\("), None);
+        // Backslashes don't escape anything inside single quotes
+        assert_eq!(posix("echo 'a\\' b"), None);
+        assert_eq!(posix("echo 'a\\'' b"), Some(UnclosedQuote('\'')));
+        assert_eq!(powershell("Write-Host it`'s"), None);
+        assert_eq!(powershell("Write-Host C:\\'"), Some(UnclosedQuote('\'')));
+    }
+
+    #[test]
+    fn balances_brackets() {
+        assert_eq!(posix("echo $(ls [ab]*)"), None);
+        assert_eq!(posix("echo $(ls"), Some(UnclosedBracket('(')));
+        assert_eq!(posix("echo ]"), Some(UnmatchedBracket(']')));
+        assert_eq!(posix("echo '('"), None);
+        assert_eq!(posix("case $x in a) echo a;; esac"), None);
+        assert_eq!(posix("find . -exec rm {} \\;"), None);
+        assert_eq!(powershell("if ($x) { ls"), Some(UnclosedBracket('{')));
+        assert_eq!(powershell("ls }"), Some(UnmatchedBracket('}')));
+    }
+
+    #[test]
+    fn ignores_comments() {
+        assert_eq!(posix("ls # it's here"), None);
+        assert_eq!(posix("echo a#'b"), Some(UnclosedQuote('\'')));
+    }
+
+    #[test]
+    fn checks_in_the_syntax_of_the_shell() {
+        assert_eq!(check("echo 'a", "bash"), Some(UnclosedQuote('\'')));
+        assert_eq!(check("echo \"a", "pwsh"), Some(UnclosedQuote('"')));
+        assert_eq!(check("echo \"a", "cmd"), None);
+    }
+}
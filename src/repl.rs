@@ -0,0 +1,285 @@
+//! Parsing of the slash commands that can be entered in the REPL instead of a prompt, and the
+//! state they manage.
+
+use crate::{
+    audit::utc_timestamp,
+    message::{ChatMessage, Role},
+};
+use anyhow::{bail, Result};
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, SystemTime},
+};
+
+/// A REPL command, which is handled by gptsh instead of being sent to the model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplCommand<'a> {
+    /// `/system`: show the current system message.
+    ShowSystem,
+    /// `/system <text>`: replace the system message.
+    SetSystem(&'a str),
+    /// `/system append <text>`: add text to the end of the system message.
+    AppendSystem(&'a str),
+    /// `/system reset`: restore the default system message.
+    ResetSystem,
+    /// `/branch [name]`: fork the conversation into a new branch and switch to it.
+    Branch(Option<&'a str>),
+    /// `/checkout <name>`: switch to another branch.
+    Checkout(&'a str),
+    /// `/branches`: list the branches.
+    ListBranches,
+    /// `/temp`: show the current temperature and the presets.
+    ShowTemperature,
+    /// `/temp <preset|value>`: change the temperature of later requests.
+    SetTemperature(&'a str),
+    /// `/errors`: show the most recent failed requests.
+    Errors,
+    /// `/less`: show the last reply again in the pager.
+    Pager,
+    /// `/status`: turn the status line above the prompt on or off.
+    ToggleStatusBar,
+    /// `/show <n>`: show the `n`th reply of the conversation again.
+    Show(usize),
+    /// `/rerun <n>`: run the command in the `n`th reply again.
+    Rerun(usize),
+    /// `/stats`: show the latency of the session's requests and the tokens used.
+    Stats,
+    /// `/clear`: forget the conversation so far, keeping the system message.
+    Clear,
+}
+
+impl<'a> ReplCommand<'a> {
+    /// Parse a line of REPL input. Returns `None` if it isn't a known command, in which case it
+    /// should be sent to the model as a normal prompt.
+    pub fn parse(input: &'a str) -> Option<Self> {
+        let input = input.trim().strip_prefix('/')?;
+        let (name, rest) = input.split_once(char::is_whitespace).unwrap_or((input, ""));
+        let rest = rest.trim();
+
+        Some(match name {
+            "system" => match rest.split_once(char::is_whitespace) {
+                _ if rest.is_empty() => Self::ShowSystem,
+                _ if rest == "reset" => Self::ResetSystem,
+                Some(("append", text)) => Self::AppendSystem(text.trim()),
+                _ => Self::SetSystem(rest),
+            },
+            "branch" if rest.is_empty() => Self::Branch(None),
+            "branch" => Self::Branch(Some(rest)),
+            "checkout" if !rest.is_empty() => Self::Checkout(rest),
+            "branches" => Self::ListBranches,
+            "temp" if rest.is_empty() => Self::ShowTemperature,
+            "temp" => Self::SetTemperature(rest),
+            "errors" => Self::Errors,
+            "less" => Self::Pager,
+            "status" => Self::ToggleStatusBar,
+            "show" => Self::Show(rest.parse().ok()?),
+            "rerun" => Self::Rerun(rest.parse().ok()?),
+            "stats" => Self::Stats,
+            "clear" => Self::Clear,
+            _ => return None,
+        })
+    }
+}
+
+/// Get the `n`th reply in a conversation, counting from 1. Only replies with text are counted, so
+/// the numbers don't change as calls to tools are made.
+pub fn reply(messages: &[ChatMessage], n: usize) -> Option<&ChatMessage> {
+    replies(messages).nth(n.checked_sub(1)?)
+}
+
+/// The number of replies with text in a conversation, which is the number of the latest one.
+pub fn reply_count(messages: &[ChatMessage]) -> usize {
+    replies(messages).count()
+}
+
+fn replies(messages: &[ChatMessage]) -> impl Iterator<Item = &ChatMessage> {
+    messages
+        .iter()
+        .filter(|message| message.role == Role::Assistant && !message.text().is_empty())
+}
+
+/// Warns once a conversation gets longer than a number of turns, since models tend to drift in
+/// long ones. It warns once each time the conversation grows past the limit, so after going back
+/// under it (e.g. with `/clear`), it warns again.
+#[derive(Debug, Clone, Copy)]
+pub struct LengthWarning {
+    max_turns: usize,
+    warned: bool,
+}
+
+impl LengthWarning {
+    /// Warn after `max_turns` replies, or never if it's 0.
+    pub fn new(max_turns: usize) -> Self {
+        Self {
+            max_turns,
+            warned: false,
+        }
+    }
+
+    /// Check the length of the conversation, returning whether to warn about it now.
+    pub fn check(&mut self, messages: &[ChatMessage]) -> bool {
+        if self.max_turns == 0 {
+            return false;
+        }
+        let too_long = reply_count(messages) > self.max_turns;
+        let warn = too_long && !self.warned;
+        self.warned = too_long;
+        warn
+    }
+}
+
+/// The spread of how long requests took to be answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LatencyStats {
+    pub min: Duration,
+    pub median: Duration,
+    /// The latency that 95% of requests were answered within.
+    pub p95: Duration,
+    pub max: Duration,
+}
+
+impl LatencyStats {
+    /// Summarize the latencies of some requests, or return `None` if there weren't any.
+    pub fn new(latencies: &[Duration]) -> Option<Self> {
+        if latencies.is_empty() {
+            return None;
+        }
+        let mut sorted = latencies.to_vec();
+        sorted.sort();
+        let n = sorted.len();
+        let median = if n.is_multiple_of(2) {
+            (sorted[n / 2 - 1] + sorted[n / 2]) / 2
+        } else {
+            sorted[n / 2]
+        };
+        // The nearest rank, so that it's always the latency of an actual request
+        let p95 = sorted[(n * 95).div_ceil(100) - 1];
+        Some(Self {
+            min: sorted[0],
+            median,
+            p95,
+            max: sorted[n - 1],
+        })
+    }
+}
+
+/// Name of the branch the REPL starts on.
+pub const MAIN_BRANCH: &str = "main";
+
+/// Branches of the conversation, each with their own history. The history of the current branch is
+/// owned by the REPL, and the others are kept here until they're checked out.
+pub struct Branches {
+    current: String,
+    others: BTreeMap<String, Vec<ChatMessage>>,
+}
+
+impl Default for Branches {
+    fn default() -> Self {
+        Self {
+            current: MAIN_BRANCH.to_owned(),
+            others: BTreeMap::new(),
+        }
+    }
+}
+
+impl Branches {
+    /// The name of the current branch.
+    pub fn current(&self) -> &str {
+        &self.current
+    }
+
+    /// All branch names in order, including the current one.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.others.keys().map(String::as_str).collect();
+        names.push(&self.current);
+        names.sort_unstable();
+        names
+    }
+
+    /// Fork the current branch, leaving a copy of `messages` behind, and make the fork current.
+    /// Returns the name of the new branch.
+    pub fn branch(&mut self, name: Option<&str>, messages: &[ChatMessage]) -> Result<String> {
+        let name = match name {
+            Some(name) => name.to_owned(),
+            // Pick the first unused name
+            None => (1..)
+                .map(|i| format!("branch-{i}"))
+                .find(|name| !self.exists(name))
+                .unwrap(),
+        };
+        if self.exists(&name) {
+            bail!("branch `{name}` already exists");
+        }
+
+        let old = std::mem::replace(&mut self.current, name.clone());
+        self.others.insert(old, messages.to_vec());
+        Ok(name)
+    }
+
+    /// Switch to another branch, swapping its history into `messages`.
+    pub fn checkout(&mut self, name: &str, messages: &mut Vec<ChatMessage>) -> Result<()> {
+        if name == self.current {
+            return Ok(());
+        }
+        let Some(history) = self.others.remove(name) else {
+            bail!("there is no branch named `{name}`");
+        };
+
+        let old = std::mem::replace(&mut self.current, name.to_owned());
+        self.others
+            .insert(old, std::mem::replace(messages, history));
+        Ok(())
+    }
+
+    fn exists(&self, name: &str) -> bool {
+        self.current == name || self.others.contains_key(name)
+    }
+}
+
+/// How many failed requests are kept for `/errors`.
+const MAX_ERRORS: usize = 10;
+
+/// A request that failed.
+pub struct FailedTurn {
+    pub prompt: String,
+    /// The error JSON returned by the API, or the error message if the request didn't get a
+    /// response.
+    pub error: Value,
+    pub time: SystemTime,
+}
+
+impl FailedTurn {
+    /// The time of the failure in UTC, as `YYYY-MM-DD HH:MM:SS`.
+    pub fn timestamp(&self) -> String {
+        utc_timestamp(self.time)
+    }
+}
+
+/// The most recent failed requests, oldest first.
+#[derive(Default)]
+pub struct ErrorLog {
+    turns: VecDeque<FailedTurn>,
+}
+
+impl ErrorLog {
+    /// Log a failed request, forgetting the oldest one if the log is full.
+    pub fn push(&mut self, prompt: &str, error: Value) {
+        if self.turns.len() == MAX_ERRORS {
+            self.turns.pop_front();
+        }
+        self.turns.push_back(FailedTurn {
+            prompt: prompt.to_owned(),
+            error,
+            time: SystemTime::now(),
+        });
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &FailedTurn> {
+        self.turns.iter()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.turns.is_empty()
+    }
+}
@@ -0,0 +1,327 @@
+//! Classification of raw model output into answers and shell commands.
+
+use crate::{quoting, PromptRules};
+use serde_json::Value;
+
+/// A model response, classified by whether it's a shell command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// An answer to a general question.
+    Answer(String),
+    /// A shell command, without the `[shell]` tag or any markdown code fences.
+    Command(String),
+}
+
+/// Classify a raw model response by checking for the `[shell]` tag, which marks that a response is
+/// a shell command. Models sometimes wrap the tag or the command in markdown code fences despite
+/// being told not to, so those are stripped too, along with any blank lines around them.
+///
+/// Prose the model adds on the lines after the command is dropped, so that it's never run: only
+/// the command's own line is kept, along with lines it continues onto (see [`continues`]), further
+/// `[shell]` lines, and an `[explanation]`. When `rules` allow multiline commands or comments,
+/// every line is kept instead, since any of them can be part of the command. A command in a code
+/// block is kept as it is, since everything in the block is code.
+pub fn classify(raw: &str, rules: PromptRules) -> Response {
+    match strip_tag(strip_fences(raw)) {
+        Some(command) => {
+            let code = strip_fences(command);
+            if code != trim(command) {
+                return Response::Command(code.to_owned());
+            }
+            Response::Command(command_lines(code, rules).join("\n"))
+        }
+        None => Response::Answer(raw.to_owned()),
+    }
+}
+
+/// Split a command into the commands in it, when the model gave several steps each with their own
+/// `[shell]` tag. A command without any more tags is returned alone. Untagged lines between the
+/// steps are dropped, unless they continue a step or are inside its code block, since they're
+/// likely to be prose, or `rules` allow multiline commands or comments, like in [`classify`].
+pub fn split_commands(command: &str, rules: PromptRules) -> Vec<String> {
+    if !command.lines().any(|line| strip_tag(trim(line)).is_some()) {
+        let command = strip_fences(command);
+        return if command.is_empty() {
+            Vec::new()
+        } else {
+            vec![command.to_owned()]
+        };
+    }
+
+    let mut commands = vec![String::new()];
+    for line in command_lines(command, rules) {
+        match strip_tag(trim(line)) {
+            Some(rest) => commands.push(rest.to_owned()),
+            None => {
+                let current = commands.last_mut().unwrap();
+                if !current.is_empty() {
+                    current.push('\n');
+                }
+                current.push_str(line);
+            }
+        }
+    }
+    commands
+        .iter()
+        .map(|command| strip_fences(command).to_owned())
+        .filter(|command| !command.is_empty())
+        .collect()
+}
+
+/// Get the lines of a command that are part of it, leaving out blank lines and prose: the first
+/// line, `[shell]` lines, lines a command continues onto, and an `[explanation]` with everything
+/// after it. Every line but blank ones is kept if `rules` allow multiline commands or comments.
+fn command_lines(command: &str, rules: PromptRules) -> Vec<&str> {
+    let keep_all = rules.multiline_commands || rules.comments;
+    let mut kept = Vec::new();
+    // The kept lines of the current step, since the last `[shell]` tag
+    let mut step = String::new();
+    let mut lines = command.lines();
+    while let Some(line) = lines.next() {
+        let trimmed = trim(line);
+        if trimmed.starts_with("[explanation]") {
+            kept.push(line);
+            kept.extend(lines);
+            break;
+        }
+        let in_fence = step.matches("```").count() % 2 == 1;
+        if trimmed.is_empty() && !in_fence {
+            continue;
+        }
+        if let Some(rest) = strip_tag(trimmed) {
+            kept.push(trimmed);
+            step = rest.to_owned();
+        } else if kept.is_empty() || step.is_empty() {
+            kept.push(trimmed);
+            step = trimmed.to_owned();
+        } else if keep_all || in_fence || trimmed.starts_with("```") || continues(&step) {
+            kept.push(line.trim_end());
+            step.push('\n');
+            step.push_str(trimmed);
+        }
+    }
+    kept
+}
+
+/// Check whether a command continues on the next line: when its last line ends with `\`, a pipe,
+/// or `&&`/`||`, or it leaves a quote or bracket open.
+fn continues(command: &str) -> bool {
+    let command = command.trim_end();
+    command.ends_with(['\\', '|']) || command.ends_with("&&") || quoting::is_open(command)
+}
+
+/// Split the explanation allowed by [`PromptRules::explanations`](crate::PromptRules) off of a
+/// command, so that it can be shown without being executed.
+pub fn split_explanation(command: &str) -> (&str, Option<&str>) {
+    match command.split_once("[explanation]") {
+        Some((command, explanation)) => (command.trim(), Some(explanation.trim())),
+        None => (command, None),
+    }
+}
+
+/// Split the reason requested by [`PromptRules::reasons`](crate::PromptRules) out of a command
+/// given as `{"command": ..., "reason": ...}`. Commands that aren't in that form are returned as-is,
+/// in case the model ignored the rule.
+pub fn split_reason(command: &str) -> (String, Option<String>) {
+    let json: Option<Value> = serde_json::from_str(command).ok();
+    match json.as_ref().and_then(|json| json.get("command")?.as_str()) {
+        Some(inner) => {
+            let reason = json.as_ref().and_then(|json| json.get("reason")?.as_str());
+            (inner.to_owned(), reason.map(str::to_owned))
+        }
+        None => (command.to_owned(), None),
+    }
+}
+
+/// Remove the `[shell]` tag from the start of some text, in any case, e.g. `[Shell]`.
+fn strip_tag(text: &str) -> Option<&str> {
+    const TAG: &str = "[shell]";
+    let tag = text.get(..TAG.len())?;
+    tag.eq_ignore_ascii_case(TAG).then(|| &text[TAG.len()..])
+}
+
+/// Remove surrounding whitespace, including the invisible characters (a byte order mark or
+/// zero-width space) that some models emit.
+fn trim(text: &str) -> &str {
+    text.trim_matches(|c: char| c.is_whitespace() || c == '\u{feff}' || c == '\u{200b}')
+}
+
+/// Remove surrounding whitespace and a markdown code block (```` ```bash ... ``` ````) or inline
+/// code span (`` `...` ``) around some text. Anything after a single code block at the start is
+/// dropped, since models sometimes comment on the command after it, which must never be run.
+fn strip_fences(text: &str) -> &str {
+    let text = trim(text);
+
+    if let Some(block) = text.strip_prefix("```") {
+        // Several code blocks are left for the caller, e.g. to split into steps
+        let block = match block.split_once("```") {
+            Some((block, rest)) if !rest.contains("```") => Some(block),
+            _ => block.strip_suffix("```"),
+        };
+        if let Some(block) = block {
+            // Skip the language identifier on the first line, if any
+            return match block.split_once('\n') {
+                Some((_, code)) => trim(code),
+                None => trim(block),
+            };
+        }
+    }
+
+    match text.strip_prefix('`').and_then(|t| t.strip_suffix('`')) {
+        Some(code) if !code.contains('`') => trim(code),
+        _ => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(text: &str) -> Response {
+        Response::Command(text.to_owned())
+    }
+
+    /// Classify with the default rules, where commands are a single line.
+    fn classify(raw: &str) -> Response {
+        super::classify(raw, PromptRules::default())
+    }
+
+    fn split_commands(command: &str) -> Vec<String> {
+        super::split_commands(command, PromptRules::default())
+    }
+
+    const MULTILINE: PromptRules = PromptRules {
+        multiline_commands: true,
+        comments: false,
+        explanations: false,
+        reasons: false,
+        tools: false,
+    };
+    const COMMENTS: PromptRules = PromptRules {
+        multiline_commands: false,
+        comments: true,
+        ..MULTILINE
+    };
+
+    #[test]
+    fn classifies_tagged_commands() {
+        assert_eq!(classify("[shell] ls -la"), command("ls -la"));
+        assert_eq!(classify("[SHELL]ls"), command("ls"));
+        assert_eq!(classify("\u{feff}  [shell] ls \n\n"), command("ls"));
+        assert_eq!(classify("[shell]\nls"), command("ls"));
+    }
+
+    #[test]
+    fn classifies_answers() {
+        let answer = "Use `ls` to list files.";
+        assert_eq!(classify(answer), Response::Answer(answer.to_owned()));
+    }
+
+    #[test]
+    fn strips_fences() {
+        assert_eq!(classify("```\n[shell] ls\n```"), command("ls"));
+        assert_eq!(classify("[shell]\n```bash\nls\n```"), command("ls"));
+        assert_eq!(classify("[shell] `ls`"), command("ls"));
+        assert_eq!(
+            classify("[shell]\n```sh\nfor f in *; do\n  echo \"$f\"\ndone\n```\nThis loops."),
+            command("for f in *; do\n  echo \"$f\"\ndone")
+        );
+    }
+
+    #[test]
+    fn drops_prose_after_the_command() {
+        assert_eq!(
+            classify("[shell] rm -r build\nThis removes the build directory."),
+            command("rm -r build")
+        );
+        assert_eq!(
+            classify("[shell] ls\n\nNote: add -a to show hidden files."),
+            command("ls")
+        );
+    }
+
+    #[test]
+    fn keeps_continued_lines() {
+        assert_eq!(
+            classify("[shell] find . -name '*.rs' \\\n  -newer Cargo.toml\nDone."),
+            command("find . -name '*.rs' \\\n  -newer Cargo.toml")
+        );
+        assert_eq!(
+            classify("[shell] ls |\n  wc -l\nCounts files."),
+            command("ls |\n  wc -l")
+        );
+        assert_eq!(
+            classify("[shell] echo 'one\ntwo'\nPrints two lines."),
+            command("echo 'one\ntwo'")
+        );
+    }
+
+    #[test]
+    fn keeps_explanations() {
+        assert_eq!(
+            classify("[shell] ls\n[explanation] Lists files.\nIn this directory."),
+            command("ls\n[explanation] Lists files.\nIn this directory.")
+        );
+    }
+
+    #[test]
+    fn splits_a_single_command() {
+        assert_eq!(split_commands("ls -la"), vec!["ls -la"]);
+        assert_eq!(
+            split_commands("for f in *; do\n  echo \"$f\"\ndone"),
+            vec!["for f in *; do\n  echo \"$f\"\ndone"]
+        );
+        assert!(split_commands("").is_empty());
+    }
+
+    #[test]
+    fn splits_multiple_commands() {
+        assert_eq!(
+            split_commands("mkdir out\n[shell] cd out\n[shell] touch a"),
+            vec!["mkdir out", "cd out", "touch a"]
+        );
+        assert_eq!(
+            split_commands("mkdir out\n[shell]\n```\ncd out\n```"),
+            vec!["mkdir out", "cd out"]
+        );
+    }
+
+    #[test]
+    fn split_drops_prose_between_commands() {
+        assert_eq!(
+            split_commands("mkdir out\n[shell] cd out\nThen create the file:\n[shell] touch a"),
+            vec!["mkdir out", "cd out", "touch a"]
+        );
+        assert_eq!(
+            split_commands("mkdir out\n[shell] ls |\n  wc -l\nCounts them."),
+            vec!["mkdir out", "ls |\n  wc -l"]
+        );
+    }
+
+    #[test]
+    fn keeps_every_line_when_comments_are_allowed() {
+        assert_eq!(
+            super::classify("[shell] # list all files\nls -la", COMMENTS),
+            command("# list all files\nls -la")
+        );
+    }
+
+    #[test]
+    fn keeps_every_line_when_multiline_commands_are_allowed() {
+        assert_eq!(
+            super::classify("[shell] mkdir -p out\ncd out\ntouch a", MULTILINE),
+            command("mkdir -p out\ncd out\ntouch a")
+        );
+        assert_eq!(
+            super::classify(
+                "[shell] for f in *.txt; do\n  wc -l \"$f\"\ndone",
+                MULTILINE
+            ),
+            command("for f in *.txt; do\n  wc -l \"$f\"\ndone")
+        );
+        assert_eq!(
+            super::split_commands("mkdir -p out\ncd out\n[shell] touch a\nls", MULTILINE),
+            vec!["mkdir -p out\ncd out", "touch a\nls"]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Translation between Chat Completions and OpenAI's Responses API, for `--api responses`.
+//!
+//! Requests are built for Chat Completions as usual and translated just before they're sent, and
+//! replies are translated back into the Chat Completions shape, so that the rest of gptsh only has
+//! to deal with one of them. Recordings keep the requests and responses as they were sent.
+
+use serde_json::{json, Map, Value};
+
+/// Translate a Chat Completions request body into a Responses API one.
+pub(crate) fn request(body: &Value) -> Value {
+    let Some(body) = body.as_object() else {
+        return body.clone();
+    };
+    let mut translated = Map::new();
+    for (key, value) in body {
+        match key.as_str() {
+            "messages" => {
+                let input: Vec<Value> = value
+                    .as_array()
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                    .iter()
+                    .flat_map(input_items)
+                    .collect();
+                translated.insert("input".to_owned(), json!(input));
+            }
+            "max_tokens" | "max_completion_tokens" => {
+                translated.insert("max_output_tokens".to_owned(), value.clone());
+            }
+            "response_format" => {
+                translated.insert("text".to_owned(), json!({"format": text_format(value)}));
+            }
+            "tools" => {
+                let tools: Vec<Value> = value
+                    .as_array()
+                    .map(Vec::as_slice)
+                    .unwrap_or_default()
+                    .iter()
+                    .map(tool)
+                    .collect();
+                translated.insert("tools".to_owned(), json!(tools));
+            }
+            _ => {
+                translated.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    Value::Object(translated)
+}
+
+/// The input items for a chat message. Tool calls and their results are items of their own.
+fn input_items(message: &Value) -> Vec<Value> {
+    let role = message["role"].as_str().unwrap_or_default();
+    let content = &message["content"];
+    match role {
+        "tool" => vec![json!({
+            "type": "function_call_output",
+            "call_id": message["tool_call_id"],
+            "output": content.as_str().unwrap_or_default(),
+        })],
+        "assistant" => {
+            let mut items = Vec::new();
+            if content.as_str().is_some_and(|content| !content.is_empty()) {
+                items.push(json!({"role": role, "content": content}));
+            }
+            for call in message["tool_calls"].as_array().into_iter().flatten() {
+                items.push(json!({
+                    "type": "function_call",
+                    "call_id": call["id"],
+                    "name": call["function"]["name"],
+                    "arguments": call["function"]["arguments"],
+                }));
+            }
+            items
+        }
+        _ => vec![json!({"role": role, "content": content})],
+    }
+}
+
+/// `{"type": "json_schema", "json_schema": {...}}` becomes `{"type": "json_schema", ...}`.
+fn text_format(response_format: &Value) -> Value {
+    match response_format
+        .get("json_schema")
+        .and_then(Value::as_object)
+    {
+        Some(json_schema) => {
+            let mut format = json_schema.clone();
+            format.insert("type".to_owned(), json!("json_schema"));
+            Value::Object(format)
+        }
+        None => response_format.clone(),
+    }
+}
+
+/// `{"type": "function", "function": {...}}` becomes `{"type": "function", ...}`.
+fn tool(tool: &Value) -> Value {
+    match tool.get("function").and_then(Value::as_object) {
+        Some(function) => {
+            let mut tool = function.clone();
+            tool.insert("type".to_owned(), json!("function"));
+            Value::Object(tool)
+        }
+        None => tool.clone(),
+    }
+}
+
+/// Translate a Responses API response into a Chat Completions one. Anything without an `output`,
+/// such as an error, is returned as it is.
+pub(crate) fn response(json: Value) -> Value {
+    let Some(output) = json.get("output").and_then(Value::as_array) else {
+        return json;
+    };
+
+    let mut content = String::new();
+    let mut tool_calls = Vec::new();
+    for item in output {
+        match item["type"].as_str() {
+            Some("message") => {
+                for part in item["content"].as_array().into_iter().flatten() {
+                    if part["type"] == "output_text" {
+                        content.push_str(part["text"].as_str().unwrap_or_default());
+                    }
+                }
+            }
+            Some("function_call") => tool_calls.push(json!({
+                "id": item["call_id"],
+                "type": "function",
+                "function": {"name": item["name"], "arguments": item["arguments"]},
+            })),
+            _ => {}
+        }
+    }
+
+    let cut_off = json["status"] == "incomplete"
+        && json["incomplete_details"]["reason"] == "max_output_tokens";
+    let finish_reason = if cut_off {
+        "length"
+    } else if !tool_calls.is_empty() {
+        "tool_calls"
+    } else {
+        "stop"
+    };
+    let mut message = json!({"role": "assistant", "content": content});
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = json!(tool_calls);
+    }
+    let mut translated = json!({"choices": [{"message": message, "finish_reason": finish_reason}]});
+    if let Some(usage) = json.get("usage").filter(|usage| usage.is_object()) {
+        translated["usage"] = json!({
+            "prompt_tokens": usage["input_tokens"],
+            "completion_tokens": usage["output_tokens"],
+            "total_tokens": usage["total_tokens"],
+        });
+    }
+    translated
+}
@@ -0,0 +1,204 @@
+//! Checking structured replies requested with `--schema` against the JSON Schema they were
+//! requested with, since not every model or provider enforces it.
+//!
+//! Only the common keywords are checked: `type`, `enum`, `const`, `properties`, `required`,
+//! `additionalProperties`, `items`, `minItems`, `maxItems`, `minLength`, `maxLength`, `minimum`,
+//! `maximum`, `anyOf`, `oneOf`, and `allOf`. Others, including `$ref`, are ignored.
+
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// Where a value doesn't match its schema, and why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// A JSON pointer to the value, which is empty for the whole reply.
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            f.write_str(&self.message)
+        } else {
+            write!(f, "at {}: {}", self.path, self.message)
+        }
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// Check a value against a schema.
+pub fn validate(value: &Value, schema: &Value) -> Result<(), Mismatch> {
+    check(value, schema, "")
+}
+
+fn check(value: &Value, schema: &Value, path: &str) -> Result<(), Mismatch> {
+    let mismatch = |message: String| {
+        Err(Mismatch {
+            path: path.to_owned(),
+            message,
+        })
+    };
+    // `true` and `{}` allow anything, and `false` nothing
+    let schema = match schema {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => return mismatch("no value is allowed here".to_owned()),
+        Value::Object(schema) => schema,
+        _ => return Ok(()),
+    };
+
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            Value::String(t) => vec![t.as_str()],
+            Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|t| has_type(value, t)) {
+            return mismatch(format!(
+                "expected {}, found {}",
+                types.join(" or "),
+                type_name(value)
+            ));
+        }
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            return mismatch(format!("{value} isn't one of the allowed values"));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if value != expected {
+            return mismatch(format!("expected {expected}, found {value}"));
+        }
+    }
+
+    match value {
+        Value::Object(object) => check_object(object, schema, path)?,
+        Value::Array(items) => {
+            if let Some(min) = schema.get("minItems").and_then(Value::as_u64) {
+                if (items.len() as u64) < min {
+                    return mismatch(format!("expected at least {min} items"));
+                }
+            }
+            if let Some(max) = schema.get("maxItems").and_then(Value::as_u64) {
+                if items.len() as u64 > max {
+                    return mismatch(format!("expected at most {max} items"));
+                }
+            }
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check(item, item_schema, &format!("{path}/{i}"))?;
+                }
+            }
+        }
+        Value::String(s) => {
+            let len = s.chars().count() as u64;
+            if let Some(min) = schema.get("minLength").and_then(Value::as_u64) {
+                if len < min {
+                    return mismatch(format!("expected at least {min} characters"));
+                }
+            }
+            if let Some(max) = schema.get("maxLength").and_then(Value::as_u64) {
+                if len > max {
+                    return mismatch(format!("expected at most {max} characters"));
+                }
+            }
+        }
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if let Some(min) = schema.get("minimum").and_then(Value::as_f64) {
+                if n < min {
+                    return mismatch(format!("expected at least {min}, found {n}"));
+                }
+            }
+            if let Some(max) = schema.get("maximum").and_then(Value::as_f64) {
+                if n > max {
+                    return mismatch(format!("expected at most {max}, found {n}"));
+                }
+            }
+        }
+        Value::Null | Value::Bool(_) => {}
+    }
+
+    if let Some(Value::Array(schemas)) = schema.get("allOf") {
+        for schema in schemas {
+            check(value, schema, path)?;
+        }
+    }
+    if let Some(Value::Array(schemas)) = schema.get("anyOf") {
+        if !schemas
+            .iter()
+            .any(|schema| check(value, schema, path).is_ok())
+        {
+            return mismatch("doesn't match any of the allowed schemas".to_owned());
+        }
+    }
+    if let Some(Value::Array(schemas)) = schema.get("oneOf") {
+        let matches = schemas
+            .iter()
+            .filter(|schema| check(value, schema, path).is_ok())
+            .count();
+        if matches != 1 {
+            return mismatch(format!(
+                "expected to match exactly one of the allowed schemas, but matches {matches}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn check_object(
+    object: &Map<String, Value>,
+    schema: &Map<String, Value>,
+    path: &str,
+) -> Result<(), Mismatch> {
+    if let Some(Value::Array(required)) = schema.get("required") {
+        for key in required.iter().filter_map(Value::as_str) {
+            if !object.contains_key(key) {
+                return Err(Mismatch {
+                    path: path.to_owned(),
+                    message: format!("missing the required property `{key}`"),
+                });
+            }
+        }
+    }
+
+    let properties = schema.get("properties").and_then(Value::as_object);
+    for (key, value) in object {
+        let path = format!("{path}/{}", key.replace('~', "~0").replace('/', "~1"));
+        match properties.and_then(|properties| properties.get(key)) {
+            Some(property) => check(value, property, &path)?,
+            None => match schema.get("additionalProperties") {
+                Some(Value::Bool(false)) => {
+                    return Err(Mismatch {
+                        path,
+                        message: "this property isn't allowed".to_owned(),
+                    });
+                }
+                Some(additional) => check(value, additional, &path)?,
+                None => {}
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Check whether a value has a JSON Schema type.
+fn has_type(value: &Value, t: &str) -> bool {
+    match t {
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        _ => type_name(value) == t,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
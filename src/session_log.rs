@@ -0,0 +1,44 @@
+//! A plain-text log of what a session shows, for `--log-file`: the prompts, answers, commands, and
+//! how the commands finished, as they appear on the terminal but without colors.
+
+use crate::audit::utc_timestamp;
+use anyhow::{Context, Result};
+use dialoguer::console::strip_ansi_codes;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+    time::SystemTime,
+};
+
+/// A log file that sessions are appended to.
+pub struct SessionLog {
+    file: Mutex<File>,
+}
+
+impl SessionLog {
+    /// Open a log file, creating it if it doesn't exist, and mark the start of a new session.
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open log file {}", path.display()))?;
+        let log = Self {
+            file: Mutex::new(file),
+        };
+        log.write(&format!(
+            "--- gptsh session, {} UTC ---",
+            utc_timestamp(SystemTime::now())
+        ));
+        Ok(log)
+    }
+
+    /// Add some text to the log, on its own line. Errors are ignored, so that a log that can't be
+    /// written to doesn't interrupt the session.
+    pub fn write(&self, text: &str) {
+        let mut file = self.file.lock().unwrap();
+        let _ = writeln!(file, "{}", strip_ansi_codes(text).trim_end_matches('\n'));
+    }
+}
@@ -0,0 +1,88 @@
+//! Named REPL conversations, for `--session <NAME>` and `gptsh resume`. Each one is a history file
+//! (see [`crate::history`]) named `<NAME>.json` in the `sessions` directory of the data directory,
+//! so several conversations can be kept and continued separately.
+
+use crate::{
+    config::data_dir,
+    history::{History, HistoryFormat},
+};
+use anyhow::{bail, Context, Result};
+use std::{cmp::Reverse, fs, io::ErrorKind, path::PathBuf, time::SystemTime};
+
+/// How much of a session's last message to show when listing it.
+const SNIPPET_CHARS: usize = 60;
+
+/// A saved session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+    pub name: String,
+    /// When the session was last saved.
+    pub modified: SystemTime,
+    /// The start of the first line of the last message, if there is one.
+    pub snippet: Option<String>,
+}
+
+/// Get the directory the sessions are saved in.
+pub fn dir() -> Option<PathBuf> {
+    data_dir().map(|dir| dir.join("sessions"))
+}
+
+/// Get the path of the file for a session.
+pub fn path(name: &str) -> Result<PathBuf> {
+    if name.trim().is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        bail!("`{name}` can't be used as a session name");
+    }
+    let dir = dir().context("couldn't find a data directory for the sessions")?;
+    Ok(dir.join(format!("{name}.json")))
+}
+
+/// List the saved sessions, most recently saved first. Files that can't be read are left out.
+pub fn list() -> Result<Vec<Session>> {
+    let Some(dir) = dir() else {
+        return Ok(Vec::new());
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).with_context(|| format!("failed to read {}", dir.display())),
+    };
+
+    let mut sessions = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|extension| extension != "json") {
+            continue;
+        }
+        let Some(name) = path.file_stem().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Ok(modified) = path.metadata().and_then(|metadata| metadata.modified()) else {
+            continue;
+        };
+        let Ok((_, messages)) = History::open(path.clone(), Some(HistoryFormat::Json)) else {
+            continue;
+        };
+        let snippet = messages.last().map(|message| snippet(message.text()));
+        sessions.push(Session {
+            name: name.to_owned(),
+            modified,
+            snippet,
+        });
+    }
+    sessions.sort_by_key(|session| Reverse(session.modified));
+    Ok(sessions)
+}
+
+/// Shorten a message to the start of its first line that isn't blank.
+fn snippet(text: &str) -> String {
+    let line = text
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or_default();
+    let mut snippet: String = line.chars().take(SNIPPET_CHARS).collect();
+    if snippet.len() < line.len() {
+        snippet.push('…');
+    }
+    snippet
+}
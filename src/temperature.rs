@@ -0,0 +1,88 @@
+//! Named temperature presets, so that a level of creativity can be picked without remembering the
+//! numbers.
+//!
+//! Custom presets can be added (or the built-in ones overridden) in the config file:
+//!
+//! ```json
+//! {
+//!     "temperature_presets": {
+//!         "wild": 1.6
+//!     }
+//! }
+//! ```
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// The presets that are always available.
+pub const BUILT_IN: &[(&str, f64)] = &[("precise", 0.0), ("balanced", 0.7), ("creative", 1.2)];
+
+/// The highest temperature the API accepts.
+pub const MAX: f64 = 2.0;
+
+/// The built-in presets and any from the config file.
+#[derive(Debug, Clone, Default)]
+pub struct TemperaturePresets {
+    custom: BTreeMap<String, f64>,
+}
+
+impl TemperaturePresets {
+    /// Parse the `temperature_presets` object in the config file.
+    pub fn from_json(json: &Value) -> Result<Self> {
+        let Some(presets) = json.as_object() else {
+            bail!("`temperature_presets` must be an object");
+        };
+        let mut custom = BTreeMap::new();
+        for (name, value) in presets {
+            let temperature = value
+                .as_f64()
+                .filter(|t| (0.0..=MAX).contains(t))
+                .with_context(|| format!("temperature preset `{name}` must be between 0 and 2"))?;
+            custom.insert(name.clone(), temperature);
+        }
+        Ok(Self { custom })
+    }
+
+    /// Get the temperature of a preset, preferring custom presets to the built-in ones.
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.custom.get(name).copied().or_else(|| {
+            BUILT_IN
+                .iter()
+                .find(|&&(preset, _)| preset == name)
+                .map(|&(_, temperature)| temperature)
+        })
+    }
+
+    /// The names of all presets, built-in ones first.
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = BUILT_IN.iter().map(|&(name, _)| name).collect();
+        for name in self.custom.keys() {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// Get the temperature of a preset, with an error listing the presets if there's none by that
+    /// name.
+    pub fn preset(&self, name: &str) -> Result<f64> {
+        match self.get(name) {
+            Some(temperature) => Ok(temperature),
+            None => bail!(
+                "unknown temperature preset `{name}` (available: {})",
+                self.names().join(", ")
+            ),
+        }
+    }
+
+    /// Parse either a preset name or a temperature between 0 and 2.
+    pub fn resolve(&self, preset_or_value: &str) -> Result<f64> {
+        match preset_or_value.parse::<f64>() {
+            Ok(temperature) if (0.0..=MAX).contains(&temperature) => Ok(temperature),
+            Ok(_) => bail!("the temperature must be between 0 and 2"),
+            Err(_) => self.preset(preset_or_value),
+        }
+    }
+}
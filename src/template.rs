@@ -0,0 +1,97 @@
+//! Named prompt templates from the config, for prompts that are used repeatedly.
+//!
+//! Templates are defined in the `templates` object of the config file, either as just the prompt or
+//! as an object that can also pin the model and temperature:
+//!
+//! ```json
+//! "templates": {
+//!     "explain": "explain what this does: {code}",
+//!     "commit": {
+//!         "prompt": "write a git commit message for these changes: {diff}",
+//!         "model": "gpt-4",
+//!         "temperature": 0.2
+//!     }
+//! }
+//! ```
+//!
+//! Placeholders are names made of letters, digits, and underscores in braces. Any other braces are
+//! left as they are.
+
+use crate::config::get_str;
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A prompt template.
+#[derive(Debug, Clone)]
+pub struct Template {
+    pub prompt: String,
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+}
+
+impl Template {
+    /// Parse a template from the config file.
+    pub fn from_json(json: &Value) -> Result<Self> {
+        if let Some(prompt) = json.as_str() {
+            return Ok(Self {
+                prompt: prompt.to_owned(),
+                model: None,
+                temperature: None,
+            });
+        }
+        if !json.is_object() {
+            bail!("template must be a string or an object");
+        }
+        Ok(Self {
+            prompt: get_str(json, "prompt")?.context("template has no `prompt`")?,
+            model: get_str(json, "model")?,
+            temperature: match json.get("temperature") {
+                None | Some(Value::Null) => None,
+                Some(temperature) => Some(
+                    temperature
+                        .as_f64()
+                        .context("`temperature` must be a number")?,
+                ),
+            },
+        })
+    }
+
+    /// The names of the placeholders in the prompt, in order and without duplicates.
+    pub fn placeholders(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        for (_, name) in placeholders(&self.prompt) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    /// Fill in the placeholders, erroring if any of them don't have a value.
+    pub fn render(&self, values: &HashMap<String, String>) -> Result<String> {
+        let mut prompt = String::with_capacity(self.prompt.len());
+        let mut last = 0;
+        for (start, name) in placeholders(&self.prompt) {
+            let Some(value) = values.get(name) else {
+                bail!("no value was given for `{{{name}}}`");
+            };
+            prompt.push_str(&self.prompt[last..start]);
+            prompt.push_str(value);
+            last = start + name.len() + 2;
+        }
+        prompt.push_str(&self.prompt[last..]);
+        Ok(prompt)
+    }
+}
+
+/// Find the placeholders in a prompt, with the byte offset of each one's opening brace.
+fn placeholders(prompt: &str) -> impl Iterator<Item = (usize, &str)> {
+    prompt.match_indices('{').filter_map(move |(start, _)| {
+        let rest = &prompt[start + 1..];
+        let end = rest.find('}')?;
+        let name = &rest[..end];
+        let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        valid.then_some((start, name))
+    })
+}
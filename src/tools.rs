@@ -0,0 +1,79 @@
+//! Tool calling, where the model runs commands by calling a `run_shell_command` tool instead of
+//! marking them with `[shell]`. The result of each call is sent back so the model can continue.
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+
+/// The name of the tool that runs a shell command.
+pub const RUN_SHELL_COMMAND: &str = "run_shell_command";
+
+/// A request from the model to call a tool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolCall {
+    /// The ID that the result of the call is sent back with.
+    pub id: String,
+    pub name: String,
+    /// The arguments, as a JSON string.
+    pub arguments: String,
+}
+
+impl ToolCall {
+    /// Parse the tool calls in the `tool_calls` of a message. Calls that aren't functions are
+    /// skipped.
+    pub(crate) fn parse_all(tool_calls: &Value) -> Vec<Self> {
+        let Some(tool_calls) = tool_calls.as_array() else {
+            return Vec::new();
+        };
+        tool_calls
+            .iter()
+            .filter_map(|call| {
+                let function = call.get("function")?;
+                Some(Self {
+                    id: call.get("id")?.as_str()?.to_owned(),
+                    name: function.get("name")?.as_str()?.to_owned(),
+                    arguments: function
+                        .get("arguments")
+                        .and_then(Value::as_str)
+                        .unwrap_or("{}")
+                        .to_owned(),
+                })
+            })
+            .collect()
+    }
+
+    /// Get the command to run, if this is a call to [`RUN_SHELL_COMMAND`].
+    pub fn command(&self) -> Result<String> {
+        if self.name != RUN_SHELL_COMMAND {
+            bail!("the model called an unknown tool `{}`", self.name);
+        }
+        let arguments: Value = serde_json::from_str(&self.arguments)
+            .context("the model called run_shell_command with invalid arguments")?;
+        arguments
+            .get("command")
+            .and_then(Value::as_str)
+            .map(|command| command.trim().to_owned())
+            .filter(|command| !command.is_empty())
+            .context("the model called run_shell_command without a command")
+    }
+}
+
+/// The definitions of the tools, for the `tools` field of a request.
+pub(crate) fn definitions(shell: &str) -> Value {
+    json!([{
+        "type": "function",
+        "function": {
+            "name": RUN_SHELL_COMMAND,
+            "description": format!("Run a {shell} command after the user confirms it. Returns the exit code and the command's output."),
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "command": {
+                        "type": "string",
+                        "description": format!("The {shell} command to run."),
+                    },
+                },
+                "required": ["command"],
+            },
+        },
+    }])
+}
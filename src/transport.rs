@@ -0,0 +1,373 @@
+//! How requests reach the API: over HTTP, or replayed from a recording of an earlier session.
+//!
+//! Recordings are JSONL files with one `{"request": ..., "response": ...}` object per request, in
+//! the order the requests were sent.
+
+use crate::{secrets, AuthScheme, Endpoint, MalformedResponse, Unauthorized};
+use anyhow::{Context, Result};
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, RETRY_AFTER},
+    Client, Response, StatusCode,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::Write,
+    path::Path,
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+use tokio::runtime::{self, Runtime};
+
+/// Something that can send a request body and return the response JSON.
+pub(crate) enum Transport {
+    Http(Http),
+    /// Responses from a recording, returned in order regardless of the request.
+    Replay(Mutex<VecDeque<Value>>),
+}
+
+impl Transport {
+    pub(crate) fn send(&self, body: &Value) -> Result<Value> {
+        match self {
+            Self::Http(http) => http.send(body),
+            Self::Replay(responses) => responses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .context("the recording has no more responses to replay"),
+        }
+    }
+
+    /// Send later requests with a different API key. Replayed responses don't depend on the key.
+    pub(crate) fn set_api_key(&self, api_key: &str) -> Result<()> {
+        match self {
+            Self::Http(http) => http.set_api_key(api_key),
+            Self::Replay(_) => Ok(()),
+        }
+    }
+
+    /// Load the responses from a recording.
+    pub(crate) fn replay(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read recording {}", path.display()))?;
+        let responses = text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let mut entry: Value = serde_json::from_str(line)?;
+                Ok(entry["response"].take())
+            })
+            .collect::<Result<_>>()
+            .with_context(|| format!("failed to parse recording {}", path.display()))?;
+        Ok(Self::Replay(Mutex::new(responses)))
+    }
+}
+
+/// How many times to retry a request that was rate limited.
+const MAX_RATE_LIMIT_RETRIES: usize = 5;
+/// How long to wait before the first retry of a rate limited request.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// The longest to wait before retrying a rate limited request.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Sends requests to an API endpoint. Requests are made asynchronously on a runtime that each call
+/// to `send` blocks on, so that the rest of gptsh can stay synchronous.
+pub(crate) struct Http {
+    /// Built on first use, along with the client, to keep startup fast.
+    runtime: OnceLock<Runtime>,
+    /// Built on first use, since initializing TLS is one of the slowest parts of startup. Behind a
+    /// mutex so that it can be rebuilt with a new API key.
+    client: Mutex<Option<Client>>,
+    /// Behind a mutex so that it can be replaced mid-session.
+    api_key: Mutex<Option<String>>,
+    url: String,
+    auth_header: String,
+    auth_scheme: AuthScheme,
+    insecure: bool,
+}
+
+impl Http {
+    pub(crate) fn new(endpoint: &Endpoint) -> Result<Self> {
+        // Check the headers now, so that a bad key or header name fails before the first request
+        auth_headers(
+            &endpoint.auth_header,
+            endpoint.auth_scheme,
+            endpoint.api_key.as_deref(),
+        )?;
+        Ok(Self {
+            runtime: OnceLock::new(),
+            client: Mutex::new(None),
+            api_key: Mutex::new(endpoint.api_key.clone()),
+            url: endpoint.url.clone(),
+            auth_header: endpoint.auth_header.clone(),
+            auth_scheme: endpoint.auth_scheme,
+            insecure: endpoint.insecure,
+        })
+    }
+
+    /// Send later requests with a different API key, e.g. after the old one expired.
+    pub(crate) fn set_api_key(&self, api_key: &str) -> Result<()> {
+        auth_headers(&self.auth_header, self.auth_scheme, Some(api_key))?;
+        *self.api_key.lock().unwrap() = Some(api_key.to_owned());
+        // The client is rebuilt with the new headers on the next request
+        *self.client.lock().unwrap() = None;
+        Ok(())
+    }
+
+    /// Get the async runtime, building it if this is the first request.
+    fn runtime(&self) -> Result<&Runtime> {
+        if let Some(runtime) = self.runtime.get() {
+            return Ok(runtime);
+        }
+        // A single worker is plenty, since requests are only ever waited on by blocking callers
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .context("failed to start the async runtime")?;
+        Ok(self.runtime.get_or_init(|| runtime))
+    }
+
+    /// Get the HTTP client, building it if this is the first request.
+    fn client(&self) -> Result<Client> {
+        let mut client = self.client.lock().unwrap();
+        if let Some(client) = &*client {
+            // Clients share their connection pool, so cloning them is cheap
+            return Ok(client.clone());
+        }
+        // The client is created with the API key in the headers
+        let headers = auth_headers(
+            &self.auth_header,
+            self.auth_scheme,
+            self.api_key.lock().unwrap().as_deref(),
+        )?;
+        let built = Client::builder()
+            .default_headers(headers)
+            .danger_accept_invalid_certs(self.insecure)
+            .build()?;
+        Ok(client.insert(built).clone())
+    }
+
+    fn send(&self, body: &Value) -> Result<Value> {
+        self.runtime()?.block_on(self.send_async(body))
+    }
+
+    /// Send a GET request to another endpoint of the same API, authenticated the same way.
+    pub(crate) fn get(&self, url: &str) -> Result<Value> {
+        self.runtime()?.block_on(async {
+            let resp = self.client()?.get(url).send().await?;
+            Ok(resp.json().await?)
+        })
+    }
+
+    async fn send_async(&self, body: &Value) -> Result<Value> {
+        // Replies that are cut off partway are usually a one-off, so they're worth sending again.
+        // Error pages from gateways, like a 502, usually aren't, so they're reported right away.
+        match self.post(body).await {
+            Err(err)
+                if err
+                    .downcast_ref::<MalformedResponse>()
+                    .is_some_and(MalformedResponse::is_success) =>
+            {
+                self.post(body).await
+            }
+            result => result,
+        }
+    }
+
+    async fn post(&self, body: &Value) -> Result<Value> {
+        let mut backoff = INITIAL_BACKOFF;
+        for _ in 0..MAX_RATE_LIMIT_RETRIES {
+            let resp = self.client()?.post(&self.url).json(body).send().await?;
+            if resp.status() == StatusCode::UNAUTHORIZED {
+                return Err(Unauthorized(read_json(resp).await?).into());
+            }
+            if resp.status() != StatusCode::TOO_MANY_REQUESTS {
+                return read_json(resp).await;
+            }
+
+            // Wait as long as the server asks, or back off exponentially if it doesn't say
+            let wait = resp
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|v| v.to_str().ok()?.parse().ok())
+                .map_or(backoff, Duration::from_secs);
+            tokio::time::sleep(wait.min(MAX_BACKOFF)).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+        // Return the error from the last attempt
+        let resp = self.client()?.post(&self.url).json(body).send().await?;
+        read_json(resp).await
+    }
+}
+
+/// Read the JSON body of a response, telling a malformed body apart from a failed connection.
+async fn read_json(resp: Response) -> Result<Value> {
+    let status = resp.status().as_u16();
+    let text = resp.text().await?;
+    serde_json::from_str(&text).map_err(|err| MalformedResponse::new(status, &text, err).into())
+}
+
+/// Build the headers that authenticate requests with an API key, if there is one.
+fn auth_headers(auth_header: &str, scheme: AuthScheme, api_key: Option<&str>) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    let auth = match (scheme, api_key) {
+        (AuthScheme::Bearer, Some(api_key)) => Some(format!("Bearer {api_key}")),
+        (AuthScheme::Raw, Some(api_key)) => Some(api_key.to_owned()),
+        _ => None,
+    };
+    if let Some(auth) = auth {
+        let mut value = HeaderValue::from_str(&auth)?;
+        value.set_sensitive(true); // API key is sensitive
+        headers.insert(HeaderName::from_bytes(auth_header.as_bytes())?, value);
+    }
+    Ok(headers)
+}
+
+/// Records every request and response to a file, for replaying later. Anything that looks like a
+/// secret is redacted.
+pub(crate) struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    pub(crate) fn create(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("failed to create recording {}", path.display()))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Add a request and its response to the recording, redacting `api_key` if it appears
+    /// anywhere in them.
+    pub(crate) fn record(
+        &self,
+        request: &Value,
+        response: &Value,
+        api_key: Option<&str>,
+    ) -> Result<()> {
+        let mut entry = json!({"request": request, "response": response});
+        secrets::redact_json(&mut entry, api_key);
+
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{entry}").context("failed to write to the recording")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::{BufRead, BufReader, Read},
+        net::TcpListener,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+    };
+
+    const REPLY: &str = r#"{"choices": []}"#;
+
+    /// An HTTP response with a status line, extra headers, and a body.
+    fn response(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    /// Serve each response to one request, in order, on a local port. Returns an endpoint for the
+    /// server and the number of requests it has answered.
+    fn serve(responses: Vec<String>) -> (Endpoint, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "http://{}/v1/chat/completions",
+            listener.local_addr().unwrap()
+        );
+        let served = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&served);
+        thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                reader.read_exact(&mut vec![0; length]).unwrap();
+                // Counted before answering, so the count is up to date once the reply arrives
+                counter.fetch_add(1, Ordering::SeqCst);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        let endpoint = Endpoint {
+            url,
+            ..Endpoint::openai("key")
+        };
+        (endpoint, served)
+    }
+
+    #[test]
+    fn waits_out_rate_limits() {
+        let (endpoint, served) = serve(vec![
+            response("429 Too Many Requests", "Retry-After: 0\r\n", ""),
+            response("429 Too Many Requests", "Retry-After: 0\r\n", ""),
+            response("200 OK", "", REPLY),
+        ]);
+        let http = Http::new(&endpoint).unwrap();
+        assert_eq!(http.send(&json!({})).unwrap(), json!({"choices": []}));
+        assert_eq!(served.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn retries_malformed_replies_once() {
+        let (endpoint, served) = serve(vec![
+            response("200 OK", "", r#"{"choi"#),
+            response("200 OK", "", REPLY),
+        ]);
+        let http = Http::new(&endpoint).unwrap();
+        assert_eq!(http.send(&json!({})).unwrap(), json!({"choices": []}));
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+
+        let (endpoint, served) = serve(vec![
+            response("200 OK", "", "<html>Bad gateway</html>"),
+            response("200 OK", "", "<html>Bad gateway</html>"),
+            response("200 OK", "", REPLY),
+        ]);
+        let http = Http::new(&endpoint).unwrap();
+        let err = http.send(&json!({})).unwrap_err();
+        let malformed = err.downcast_ref::<MalformedResponse>().unwrap();
+        assert!(malformed.snippet.contains("Bad gateway"));
+        assert_eq!(served.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn reports_error_pages_without_retrying() {
+        let (endpoint, served) = serve(vec![
+            response("502 Bad Gateway", "", "<html>Bad gateway</html>"),
+            response("200 OK", "", REPLY),
+        ]);
+        let http = Http::new(&endpoint).unwrap();
+        let err = http.send(&json!({})).unwrap_err();
+        let malformed = err.downcast_ref::<MalformedResponse>().unwrap();
+        assert_eq!(malformed.status, 502);
+        assert!(!malformed.is_success());
+        let message = malformed.to_string();
+        assert!(message.contains("HTTP 502"), "{message}");
+        assert!(message.contains("<html>Bad gateway</html>"), "{message}");
+        assert_eq!(served.load(Ordering::SeqCst), 1);
+    }
+}
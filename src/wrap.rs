@@ -0,0 +1,82 @@
+//! Wrapping of printed answers and commands to the width of the terminal.
+
+use dialoguer::console::Term;
+
+/// The width to wrap to when the terminal's size can't be read.
+const DEFAULT_WIDTH: usize = 80;
+/// The narrowest width to wrap to, so that tiny terminals don't break text into single letters.
+const MIN_WIDTH: usize = 20;
+/// Shown at the end of each part of a command that continues on the next line.
+pub const CONTINUATION: &str = " ↩";
+
+/// Get the width to wrap output to, or `None` if stdout isn't a terminal, so that piped output is
+/// left exactly as the model gave it.
+pub fn width() -> Option<usize> {
+    let term = Term::stdout();
+    if !term.is_term() {
+        return None;
+    }
+    let width = term
+        .size_checked()
+        .map_or(DEFAULT_WIDTH, |(_, columns)| columns.into());
+    Some(width.max(MIN_WIDTH))
+}
+
+/// Wrap text at spaces so that its lines fit in `width`. Wrapped lines keep the indentation of the
+/// line they continue, and fenced code blocks are left alone. Words longer than the width aren't
+/// broken.
+pub fn wrap_text(text: &str, width: usize) -> String {
+    let mut wrapped = Vec::new();
+    let mut in_code = false;
+
+    for line in text.lines() {
+        if line.trim_start().starts_with("```") {
+            in_code = !in_code;
+        }
+        if in_code || line.chars().count() <= width {
+            wrapped.push(line.to_owned());
+            continue;
+        }
+
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let mut current = indent.to_owned();
+        for word in line.split_whitespace() {
+            let len = current.chars().count();
+            if len > indent.len() && len + 1 + word.chars().count() > width {
+                wrapped.push(std::mem::replace(&mut current, indent.to_owned()));
+            }
+            if current.len() > indent.len() {
+                current.push(' ');
+            }
+            current.push_str(word);
+        }
+        wrapped.push(current);
+    }
+
+    wrapped.join("\n")
+}
+
+/// Split a line of a command into parts that fit in `width` along with [`CONTINUATION`], breaking
+/// after spaces where possible. The parts join back into the exact line.
+pub fn wrap_command_line(line: &str, width: usize) -> Vec<&str> {
+    let limit = width.saturating_sub(CONTINUATION.chars().count()).max(1);
+    let mut parts = Vec::new();
+    let mut rest = line;
+
+    while rest.chars().count() > limit {
+        // The byte offset just past the last character that fits
+        let end = rest
+            .char_indices()
+            .nth(limit)
+            .map_or(rest.len(), |(i, _)| i);
+        let split = match rest[..end].rfind(' ') {
+            Some(space) if space > 0 => space + 1,
+            _ => end,
+        };
+        parts.push(&rest[..split]);
+        rest = &rest[split..];
+    }
+    parts.push(rest);
+
+    parts
+}
@@ -0,0 +1,320 @@
+//! Tests that run the gptsh binary, with the API replaced by recordings made with --record.
+
+use serde_json::json;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    process::{Command, Output, Stdio},
+    thread::{self, JoinHandle},
+};
+use tempfile::TempDir;
+
+/// A gptsh command that replays `responses` instead of calling the API, with its config and data
+/// directories in `dir` so that the user's aren't used. Running as root is allowed, as it is in
+/// containers.
+fn gptsh(dir: &TempDir, responses: &[serde_json::Value]) -> Command {
+    let mut command = gptsh_elevated(dir, responses);
+    command.arg("--i-know-what-im-doing");
+    command
+}
+
+/// Like [`gptsh`], but with the extra care taken when running as root.
+fn gptsh_elevated(dir: &TempDir, responses: &[serde_json::Value]) -> Command {
+    let recording = dir.path().join("recording.jsonl");
+    let lines: Vec<String> = responses
+        .iter()
+        .map(|response| json!({"request": {}, "response": response}).to_string())
+        .collect();
+    std::fs::write(&recording, lines.join("\n")).unwrap();
+
+    let mut command = isolated(dir);
+    command.arg("--replay").arg(&recording);
+    command
+}
+
+/// A gptsh command with its config and data directories in `dir`, and none of the environment
+/// variables that change its behavior.
+fn isolated(dir: &TempDir) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_gptsh"));
+    command
+        .env("HOME", dir.path())
+        .env("XDG_CONFIG_HOME", dir.path().join("config"))
+        .env("XDG_DATA_HOME", dir.path().join("data"))
+        .env("LC_ALL", "C")
+        .env_remove("GPTSH_CONFIG")
+        .env_remove("GPTSH_MODEL")
+        .env_remove("GPTSH_NO_EXEC");
+    command
+}
+
+/// A chat completion response with a reply.
+fn reply(content: &str) -> serde_json::Value {
+    json!({
+        "choices": [{
+            "message": {"role": "assistant", "content": content},
+            "finish_reason": "stop",
+        }],
+    })
+}
+
+/// Run a command with `input` on its stdin.
+fn run_with_stdin(command: &mut Command, input: &[u8]) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn reports_empty_replies() {
+    for content in ["", " \n\t "] {
+        let dir = tempfile::tempdir().unwrap();
+        let output = gptsh(&dir, &[reply(content)])
+            .arg("list files")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", stderr(&output));
+        assert_eq!(stdout(&output), "");
+        assert!(stderr(&output).contains("(empty response)"));
+    }
+}
+
+#[test]
+fn refuses_commands_cut_off_by_the_token_limit() {
+    let dir = tempfile::tempdir().unwrap();
+    let marker = dir.path().join("marker");
+    let mut truncated = reply(&format!("[shell] touch {}", marker.display()));
+    truncated["choices"][0]["finish_reason"] = json!("length");
+    let output = gptsh(&dir, &[truncated])
+        .args(["--yes", "create the marker"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("cut off by the token limit"));
+    assert!(!marker.exists());
+}
+
+/// A chat completion response that calls the shell tool with a command.
+fn tool_call(command: &str) -> serde_json::Value {
+    json!({
+        "choices": [{
+            "message": {
+                "role": "assistant",
+                "content": null,
+                "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {
+                        "name": "run_shell_command",
+                        "arguments": json!({"command": command}).to_string(),
+                    },
+                }],
+            },
+            "finish_reason": "tool_calls",
+        }],
+    })
+}
+
+#[test]
+fn reviews_long_tool_call_commands() {
+    let dir = tempfile::tempdir().unwrap();
+    let marker = dir.path().join("marker");
+    let output = gptsh(
+        &dir,
+        &[
+            tool_call(&format!("touch {}", marker.display())),
+            reply("Done."),
+        ],
+    )
+    .args(["--tools", "--yes", "--quiet", "--max-command-length", "5"])
+    .arg("create the marker")
+    .output()
+    .unwrap();
+    assert!(stderr(&output).contains("too long to review"));
+    assert!(!marker.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn needs_an_opt_in_to_run_unconfirmed_commands_as_root() {
+    // SAFETY: geteuid has no preconditions and cannot fail
+    if unsafe { libc::geteuid() } != 0 {
+        eprintln!("not running as root, so elevated commands can't be tested");
+        return;
+    }
+    for mode in ["--quiet", "--json"] {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("marker");
+        let touch = reply(&format!("[shell] touch {}", marker.display()));
+        let output = gptsh_elevated(&dir, std::slice::from_ref(&touch))
+            .args([mode, "--yes", "create the marker"])
+            .output()
+            .unwrap();
+        assert!(stderr(&output).contains("--allow-elevated"), "{mode}");
+        assert!(!marker.exists(), "{mode}");
+
+        let output = gptsh_elevated(&dir, &[touch])
+            .args([mode, "--yes", "--allow-elevated", "create the marker"])
+            .output()
+            .unwrap();
+        assert!(marker.exists(), "{mode}: {}", stderr(&output));
+    }
+}
+
+#[test]
+fn quiet_tool_calls_only_print_the_output_and_reply() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut call = tool_call("echo from-the-command");
+    call["choices"][0]["message"]["content"] = json!("Let me check.");
+    let output = gptsh(&dir, &[call, reply("Done.")])
+        .args(["--tools", "--yes", "--quiet", "say hi"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert_eq!(stdout(&output).trim(), "from-the-command\nDone.");
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn rejects_stdin_that_isnt_utf8_or_encodes_it() {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir_all(dir.path().join("config/gptsh")).unwrap();
+    std::fs::write(
+        dir.path().join("config/gptsh/config.json"),
+        r#"{"templates": {"describe": "describe this file: {file}"}}"#,
+    )
+    .unwrap();
+    let describe = || {
+        let mut command = gptsh(&dir, &[]);
+        command.args(["-k", "x", "--template", "describe", "--dry-request"]);
+        command
+    };
+
+    let output = run_with_stdin(&mut describe(), b"\xff\xfe");
+    assert!(!output.status.success());
+    assert!(stderr(&output).contains("stdin isn't text (invalid UTF-8 at byte 0)"));
+    assert!(stderr(&output).contains("--stdin-base64"));
+
+    let output = run_with_stdin(describe().arg("--stdin-base64"), b"\xff\xfe");
+    assert!(output.status.success(), "{}", stderr(&output));
+    assert!(stdout(&output).contains("describe this file: //4="));
+
+    let output = run_with_stdin(
+        gptsh(&dir, &[]).args(["-k", "x", "--prompt-file", "-", "--dry-request"]),
+        b"ok \xff",
+    );
+    assert!(stderr(&output).contains("invalid UTF-8 at byte 3"));
+}
+
+/// Answer one request with each of `responses`, in order, on a local port. Returns the API's base
+/// URL and a handle that gives the bodies of the requests once they've all been answered.
+fn serve(responses: &[serde_json::Value]) -> (String, JoinHandle<Vec<serde_json::Value>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+    let responses = responses.to_vec();
+    let server = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for response in responses {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            requests.push(serde_json::from_slice(&body).unwrap());
+
+            let body = response.to_string();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .unwrap();
+        }
+        requests
+    });
+    (base_url, server)
+}
+
+/// The requests in a recording made with --record.
+fn recorded_requests(path: &std::path::Path) -> Vec<serde_json::Value> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["request"].take())
+        .collect()
+}
+
+#[test]
+fn sends_and_reads_the_same_over_http_as_in_replays() {
+    let mut call = tool_call("echo from-the-command");
+    call["choices"][0]["message"]["content"] = json!("Let me check.");
+    let mut command = reply("[shell] ls");
+    command["usage"] = json!({"prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7});
+    let cases = [
+        (vec![command], vec!["--json", "list files"]),
+        (
+            vec![reply("ls lists files.")],
+            vec!["--json", "what does ls do"],
+        ),
+        (
+            vec![call, reply("Done.")],
+            vec!["--tools", "--yes", "--quiet", "say hi"],
+        ),
+    ];
+
+    for (responses, args) in cases {
+        let dir = tempfile::tempdir().unwrap();
+        let replayed = gptsh(&dir, &responses)
+            .arg("--record")
+            .arg(dir.path().join("replayed.jsonl"))
+            .args(&args)
+            .output()
+            .unwrap();
+
+        let (base_url, server) = serve(&responses);
+        let sent = isolated(&dir)
+            .args([
+                "--i-know-what-im-doing",
+                "-k",
+                "key",
+                "--base-url",
+                &base_url,
+            ])
+            .args(&args)
+            .output()
+            .unwrap();
+
+        assert_eq!(
+            server.join().unwrap(),
+            recorded_requests(&dir.path().join("replayed.jsonl")),
+            "{args:?}"
+        );
+        assert_eq!(stdout(&sent), stdout(&replayed), "{args:?}");
+        assert_eq!(stderr(&sent), stderr(&replayed), "{args:?}");
+        assert_eq!(sent.status.code(), replayed.status.code(), "{args:?}");
+    }
+}